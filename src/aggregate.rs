@@ -0,0 +1,153 @@
+// Turning daily values into the buckets of the period tables, calendar decides which bucket a day is in
+use std::collections::{BTreeMap, BTreeSet};
+use chrono::NaiveDate;
+use sqlx::{MySql, Pool};
+
+use crate::calendar;
+use crate::daily::{DailyTemp, MeanSource};
+use crate::periods::BucketTemps;
+
+/// Sum and number of days of the values in every bucket of every year
+#[derive(Clone, Debug)]
+pub struct BucketSums {
+    pub sums: Vec<f64>,
+    pub days: Vec<i32>,
+}
+
+pub fn sum_by_bucket(period: &str, values: impl Iterator<Item = (NaiveDate, f64)>) -> BTreeMap<i32, BucketSums> {
+    let buckets = calendar::bucket_count(period);
+    let mut years: BTreeMap<i32, BucketSums> = BTreeMap::new();
+    for (date, value) in values {
+        let Some((year, bucket)) = calendar::date_bucket(period, &date) else { continue; };
+        let year = years.entry(year).or_insert_with(|| BucketSums { sums: vec![0.0; buckets], days: vec![0; buckets] });
+        year.sums[bucket - 1] += value;
+        year.days[bucket - 1] += 1;
+    }
+    years
+}
+
+/// Avg hi, low and mean of every bucket of every year, None where a bucket has no readings
+pub fn period_means(period: &str, days: &[DailyTemp]) -> BTreeMap<i32, BucketTemps> {
+    let buckets = calendar::bucket_count(period);
+    let hi = sum_by_bucket(period, days.iter().filter_map(|d| Some((d.date, f64::from(d.tmax?)))));
+    let low = sum_by_bucket(period, days.iter().filter_map(|d| Some((d.date, f64::from(d.tmin?)))));
+    let mean = sum_by_bucket(period, days.iter().filter_map(|d| Some((d.date, d.tmean()?.0))));
+    let averages = |sums: Option<&BucketSums>| -> Vec<Option<f64>> {
+        match sums {
+            Some(sums) => sums.sums.iter().zip(&sums.days).map(|(sum, days)| (*days > 0).then(|| sum / f64::from(*days))).collect(),
+            None => vec![None; buckets],
+        }
+    };
+    let years: BTreeSet<i32> = hi.keys().chain(low.keys()).chain(mean.keys()).copied().collect();
+    years.into_iter().map(|year| (year, BucketTemps { tmax: averages(hi.get(&year)), tmin: averages(low.get(&year)), tmean: averages(mean.get(&year)) })).collect()
+}
+
+/// period_means rounded to whole degrees like the period tables
+pub fn period_averages(period: &str, days: &[DailyTemp]) -> BTreeMap<i32, BucketTemps> {
+    period_means(period, days).iter().map(|(year, temps)| (*year, temps.map(f64::round))).collect()
+}
+
+/// Where each bucket's mean came from: "R" every day was a reported TAVG, "C" every day was (tmax + tmin) / 2,
+/// "M" a mix of both. None where the bucket has no mean
+pub fn mean_sources(period: &str, days: &[DailyTemp]) -> BTreeMap<i32, Vec<Option<&'static str>>> {
+    let reported = sum_by_bucket(period, days.iter().filter_map(|d| {
+        let (_, source) = d.tmean()?;
+        Some((d.date, if source == MeanSource::Reported { 1.0 } else { 0.0 }))
+    }));
+    reported.into_iter().map(|(year, sums)| {
+        let flags = sums.sums.iter().zip(&sums.days).map(|(reported, days)| match (*reported as i32, *days) {
+            (_, 0) => None,
+            (0, _) => Some("C"),
+            (r, d) if r == d => Some("R"),
+            _ => Some("M"),
+        }).collect();
+        (year, flags)
+    }).collect()
+}
+
+/// Table the rebuilt averages go in, next to the original {city}_{period} the way homogenize writes {city}_{period}_adj.
+/// The original week / fort / month tables are never rebuilt, nothing says how they were bucketed
+pub fn agg_table(city: &str, period: &str) -> String {
+    format!("{city}_{}_agg", period.to_lowercase())
+}
+
+/// Same layout as the original week / fort / month tables plus the mean series
+pub async fn create_period_table(pool: &Pool<MySql>, table: &str, period: &str) -> Result<(), sqlx::Error> {
+    let create_stmt = format!(r#"CREATE TABLE if NOT exists `{}` (
+  `id` int(11) NOT NULL,
+  `station` char(12) DEFAULT NULL,
+  `tyear` smallint(6) NOT NULL,
+  `{}` smallint(6) NOT NULL,
+  `tmax` smallint(6) DEFAULT NULL,
+  `tmin` smallint(6) DEFAULT NULL,
+  `tmean` smallint(6) DEFAULT NULL,
+  `tmean_source` char(1) DEFAULT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#, table, calendar::period_column(period));
+    let _result = sqlx::query(&create_stmt).execute(pool).await?;
+    Ok(())
+}
+
+/// Replaces everything in {city}_{period}_agg with averages of `days` and records the bucketing in period_calendar.
+/// Pass days through qc::exclude_flagged first to leave flagged readings out. The station column is left NULL
+pub async fn rebuild_period_table(pool: &Pool<MySql>, city: &str, period: &str, days: &[DailyTemp]) -> Result<usize, sqlx::Error> {
+    let table = agg_table(city, period);
+    let averages = period_averages(period, days);
+    let sources = mean_sources(period, days);
+    create_period_table(pool, &table, period).await?;
+    calendar::create_calendar_table(pool).await?;
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!("DELETE FROM `{table}`")).execute(&mut *tx).await?;
+    let insert_stmt = format!("INSERT INTO `{table}` (id, tyear, {}, tmax, tmin, tmean, tmean_source) VALUES (?, ?, ?, ?, ?, ?, ?)", calendar::period_column(period));
+    let mut id = 0;
+    for (year, temps) in &averages {
+        for (idx, ((hi, low), mean)) in temps.tmax.iter().zip(&temps.tmin).zip(&temps.tmean).enumerate() {
+            if hi.is_none() && low.is_none() && mean.is_none() {
+                continue;
+            }
+            id += 1;
+            sqlx::query(&insert_stmt)
+                .bind(id)
+                .bind(year)
+                .bind(idx as i32 + 1)
+                .bind(hi.map(|v| v as i32))
+                .bind(low.map(|v| v as i32))
+                .bind(mean.map(|v| v as i32))
+                .bind(sources.get(year).and_then(|s| s[idx]))
+                .execute(&mut *tx).await?;
+        }
+    }
+    tx.commit().await?;
+    calendar::store_calendar(pool, &table, period).await?;
+    Ok(id as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(month: u32, dom: u32, tmax: Option<i32>, tmin: Option<i32>) -> DailyTemp {
+        DailyTemp { date: NaiveDate::from_ymd_opt(2001, month, dom).unwrap(), tmax, tmin, tavg: None }
+    }
+
+    #[test]
+    fn averages_round_and_leave_empty_buckets_none() {
+        let days = [day(1, 1, Some(40), Some(20)), day(1, 2, Some(41), None), day(1, 3, None, Some(25)), day(3, 1, Some(60), None)];
+        let years = period_averages("Month", &days);
+        let year = &years[&2001];
+        assert_eq!(year.tmax.len(), 12);
+        assert_eq!(year.tmax[0], Some(41.0)); // 40.5 rounds away from zero
+        assert_eq!(year.tmin[0], Some(23.0)); // 22.5
+        assert_eq!((year.tmax[1], year.tmin[1]), (None, None));
+        assert_eq!((year.tmax[2], year.tmin[2]), (Some(60.0), None));
+    }
+
+    #[test]
+    fn sums_count_days_per_bucket() {
+        let values = [(1, 1, 1.5), (1, 7, 2.0), (1, 8, 4.0), (12, 31, 3.0)].map(|(month, dom, value)| (NaiveDate::from_ymd_opt(2001, month, dom).unwrap(), value));
+        let sums = &sum_by_bucket("Week", values.into_iter())[&2001];
+        assert_eq!((sums.sums[0], sums.days[0]), (3.5, 2));
+        assert_eq!((sums.sums[1], sums.days[1]), (4.0, 1));
+        assert_eq!((sums.sums[51], sums.days[51]), (3.0, 1)); // day 365 folds into week 52
+    }
+}
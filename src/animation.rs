@@ -0,0 +1,198 @@
+// Year by year animation of one city/period, the roadmap's "charts as frames in a video".
+// Writes one animated GIF plus a numbered PNG frame sequence for external encoders (ffmpeg etc.)
+use sqlx::{mysql::MySqlRow, MySql, Pool};
+use plotters::prelude::*;
+use plotters::coord::Shift;
+
+use crate::{YScale, DWG_WIDTH, DWG_HEIGHT, TOP_MARGIN, LEFT_MARGIN, RIGHT_MARGIN, BOTTOM_LINE_Y,
+            bar_height, bar_x_width, draw_chart_base, draw_series_temps, get_temps, title_period};
+use crate::daily;
+use crate::periods::{self, BucketTemps};
+use crate::units::Unit;
+
+const YEAR_FONT_SIZE: i32 = 72; // year has to be readable at 3-10 frames per second
+const GHOST_OVERHANG: i32 = 3; // ghost bars are this much wider on each side so they show around the real bars
+
+pub struct AnimationOptions {
+    pub frame_delay_ms: u32,
+    pub trailing_years: i32,
+    pub show_record_mean: bool,
+}
+
+// Means drawn behind the current year's bars, None when that overlay is turned off or has no data yet
+struct Ghosts<'a> {
+    trailing: Option<&'a BucketTemps>,
+    trailing_years: i32,
+    record: Option<&'a BucketTemps>,
+    record_span: (i32, i32),
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn animate_years(pool: &Pool<MySql>,
+                           city: &str,
+                           period: &str,
+                           tperiod: &str,
+                           first_year: i32,
+                           last_year: i32,
+                           y_scale: &YScale,
+                           options: &AnimationOptions,
+                           series: &[&str],
+                           unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let city_period = format!("{city}_{period}");
+    let gif_name = format!("imgs/{city}_{period}_{first_year}-{last_year}{}.gif", unit.file_suffix());
+    let frame_dir = format!("imgs/frames/{city}_{period}{}", unit.file_suffix());
+    std::fs::create_dir_all(&frame_dir)?;
+
+    // the overlays come from the same period table as the bars, so read every year once up front
+    let buckets = periods::bucket_count(period);
+    let all_years = if options.trailing_years > 0 || options.show_record_mean {
+        unit.years(&periods::temps_by_year(period, &periods::get_all_temps(pool, tperiod, &city_period).await?))
+    } else {
+        Default::default()
+    };
+    let record_mean = periods::mean_temps(&all_years, buckets, first_year, last_year);
+
+    let gif = BitMapBackend::gif(&gif_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32), options.frame_delay_ms)?.into_drawing_area();
+    let mut frame_count = 0;
+    for year in first_year..=last_year {
+        let rows = get_temps(pool, tperiod, &city_period, year).await?;
+        if rows.is_empty() { // still gets a frame, marked no data, so the years in the video stay evenly spaced
+            println!("No {period} data for {city} in {year}, drawing a no data frame");
+        }
+        // only once a full N years are behind this one, otherwise the first frames would jump around
+        let trailing_mean = if options.trailing_years > 0 && year - options.trailing_years >= first_year {
+            Some(periods::mean_temps(&all_years, buckets, year - options.trailing_years, year - 1))
+        } else {
+            None
+        };
+        let ghosts = Ghosts {
+            trailing: trailing_mean.as_ref(),
+            trailing_years: options.trailing_years,
+            record: if options.show_record_mean { Some(&record_mean) } else { None },
+            record_span: (first_year, last_year),
+        };
+
+        draw_frame(&gif, city, period, year, y_scale, &ghosts, &rows, series, unit)?;
+        gif.present()?; // each present() adds a frame to the gif
+
+        // zero padded so the frames sort correctly, ex. ffmpeg -framerate 5 -i Los_Angeles_CA_Month_%04d.png
+        let png_name = format!("{frame_dir}/{city}_{period}_{frame_count:04}.png");
+        let png = BitMapBackend::new(&png_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+        draw_frame(&png, city, period, year, y_scale, &ghosts, &rows, series, unit)?;
+        png.present()?;
+        frame_count += 1;
+    }
+    println!("Wrote {frame_count} frames to {gif_name} and {frame_dir}");
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_frame(dwg: &DrawingArea<BitMapBackend, Shift>, city: &str, period: &str, year: i32, y_scale: &YScale, ghosts: &Ghosts, rows: &[MySqlRow], series: &[&str], unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    dwg.fill(&WHITE)?; // gif frames reuse the same drawing area so clear the last year
+    let title_text = format!("{city}  {} Avg Temperatures ({})", title_period(period), unit.symbol());
+    draw_chart_base(dwg, &title_text, period, y_scale)?;
+    // each series' outline right before its own bars, so a lower series' outline isn't hidden inside a taller
+    // series' filled bar, and the record mean lines after all the bars so they sit on top
+    let shown: Vec<&str> = daily::SERIES.iter().filter(|s| series.contains(s)).copied().collect();
+    for name in &shown {
+        if let Some(trailing) = ghosts.trailing {
+            draw_ghost_bars(dwg, period, y_scale, trailing.series(name), ghost_color(name))?;
+        }
+        draw_series_temps(dwg, period, y_scale, rows, &[name], unit)?;
+    }
+    if let Some(record) = ghosts.record {
+        for name in &shown {
+            draw_ghost_lines(dwg, period, y_scale, record.series(name))?;
+        }
+    }
+    draw_ghost_legend(dwg, ghosts)?;
+    draw_year(dwg, year)?;
+    if rows.is_empty() {
+        draw_no_data(dwg)?;
+    }
+    Ok(())
+}
+
+// darker shade of the series' bar color
+fn ghost_color(series: &str) -> RGBColor {
+    match series {
+        "tmax" => RGBColor(128, 0, 0),
+        "tmean" => RGBColor(150, 90, 0),
+        _ => RGBColor(0, 100, 0),
+    }
+}
+
+// Outlined (not filled) bars for the trailing mean
+fn draw_ghost_bars(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, y_scale: &YScale, temps: &[Option<f64>], color: RGBColor) -> Result<(), Box<dyn std::error::Error>> {
+    for (idx, temp) in temps.iter().enumerate() {
+        let (Some(temp), Some((x, width))) = (temp, bar_x_width(period, idx as i32 + 1)) else { continue; };
+        let y_adj = bar_height(*temp, y_scale.zero_line_offset, y_scale.pixel_per_degree);
+        dwg.draw(&Rectangle::new(
+            [(x - GHOST_OVERHANG, BOTTOM_LINE_Y - 2), (x + width + GHOST_OVERHANG, BOTTOM_LINE_Y - y_adj)],
+            Into::<ShapeStyle>::into(color).stroke_width(1),
+        ))?;
+    }
+    Ok(())
+}
+
+// Thin horizontal line at the full record mean for each bucket
+fn draw_ghost_lines(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, y_scale: &YScale, temps: &[Option<f64>]) -> Result<(), Box<dyn std::error::Error>> {
+    for (idx, temp) in temps.iter().enumerate() {
+        let (Some(temp), Some((x, width))) = (temp, bar_x_width(period, idx as i32 + 1)) else { continue; };
+        let y = BOTTOM_LINE_Y - bar_height(*temp, y_scale.zero_line_offset, y_scale.pixel_per_degree);
+        dwg.draw(&PathElement::new(
+            vec![(x - GHOST_OVERHANG, y), (x + width + GHOST_OVERHANG, y)],
+            Into::<ShapeStyle>::into(&BLACK).stroke_width(2),
+        ))?;
+    }
+    Ok(())
+}
+
+fn draw_ghost_legend(dwg: &DrawingArea<BitMapBackend, Shift>, ghosts: &Ghosts) -> Result<(), Box<dyn std::error::Error>> {
+    let legend_style = ("sans-serif", 16).into_font().color(&BLACK);
+    let mut lines = Vec::new();
+    if ghosts.trailing.is_some() {
+        lines.push(format!("Outline: previous {} year mean", ghosts.trailing_years));
+    }
+    if ghosts.record.is_some() {
+        lines.push(format!("Black line: {}-{} mean", ghosts.record_span.0, ghosts.record_span.1));
+    }
+    let x = LEFT_MARGIN + 15;
+    let mut y = TOP_MARGIN + 10;
+    for line in lines {
+        let (line_width, line_height) = dwg.estimate_text_size(&line, &legend_style)?;
+        dwg.draw(&Rectangle::new(
+            [(x - 5, y - 2), (x + line_width as i32 + 5, y + line_height as i32 + 2)],
+            Into::<ShapeStyle>::into(RGBAColor(255, 255, 255, 0.8)).filled(),
+        ))?;
+        dwg.draw_text(&line, &legend_style, (x, y))?;
+        y += line_height as i32 + 6;
+    }
+    Ok(())
+}
+
+// Big year in the top right corner of the plot, on a white box so bars don't hide it
+fn draw_year(dwg: &DrawingArea<BitMapBackend, Shift>, year: i32) -> Result<(), Box<dyn std::error::Error>> {
+    let year_style = ("sans-serif", YEAR_FONT_SIZE).into_font().style(FontStyle::Bold).color(&BLACK);
+    let year_text = year.to_string();
+    let (year_width, year_height) = dwg.estimate_text_size(&year_text, &year_style)?;
+    let x = DWG_WIDTH - RIGHT_MARGIN - year_width as i32 - 20;
+    let y = TOP_MARGIN + 10;
+    dwg.draw(&Rectangle::new(
+        [(x - 10, y - 5), (x + year_width as i32 + 10, y + year_height as i32 + 5)],
+        Into::<ShapeStyle>::into(RGBAColor(255, 255, 255, 0.8)).filled(),
+    ))?;
+    dwg.draw_text(&year_text, &year_style, (x, y))?;
+    Ok(())
+}
+
+// "No data" across the middle of the plot for a year without rows
+fn draw_no_data(dwg: &DrawingArea<BitMapBackend, Shift>) -> Result<(), Box<dyn std::error::Error>> {
+    let style = ("sans-serif", YEAR_FONT_SIZE).into_font().color(&RGBColor(120, 120, 120));
+    let text = "No data";
+    let (text_width, text_height) = dwg.estimate_text_size(text, &style)?;
+    let x = LEFT_MARGIN + (DWG_WIDTH - LEFT_MARGIN - RIGHT_MARGIN - text_width as i32) / 2;
+    let y = TOP_MARGIN + (BOTTOM_LINE_Y - TOP_MARGIN - text_height as i32) / 2;
+    dwg.draw_text(text, &style, (x, y))?;
+    Ok(())
+}
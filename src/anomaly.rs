@@ -0,0 +1,98 @@
+// Anomaly charts: each year's avg hi, low or mean for every bucket minus the same bucket's mean over a baseline period.
+// Bars go up (warm, red) or down (cool, blue) from a zero line so different climates can be compared.
+use sqlx::{MySql, Pool};
+use plotters::prelude::*;
+use plotters::coord::Shift;
+
+use crate::{YScale, DWG_WIDTH, DWG_HEIGHT, AXIS_HEIGHT, TOP_MARGIN, LEFT_MARGIN, AXIS_WIDTH,
+            bar_x_width, draw_chart_base, title_period};
+use crate::daily;
+use crate::periods::{self, BucketTemps};
+use crate::units::Unit;
+
+const WARM_COLOR: RGBColor = RGBColor(200, 30, 30);
+const COOL_COLOR: RGBColor = RGBColor(30, 80, 200);
+
+#[allow(clippy::too_many_arguments)]
+pub async fn draw_anomaly_charts(pool: &Pool<MySql>,
+                                 city: &str,
+                                 period: &str,
+                                 tperiod: &str,
+                                 first_year: i32,
+                                 last_year: i32,
+                                 baseline: (i32, i32),
+                                 series: &[&str],
+                                 unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let city_period = format!("{city}_{period}");
+    let buckets = periods::bucket_count(period);
+    let all_years = periods::temps_by_year(period, &periods::get_all_temps(pool, tperiod, &city_period).await?);
+    let base = periods::mean_temps(&all_years, buckets, baseline.0, baseline.1);
+    if series.iter().flat_map(|s| base.series(s)).any(|b| b.is_none()) {
+        println!("Baseline {}-{} is missing some {period} buckets for {city}, those bars are left out", baseline.0, baseline.1);
+    }
+
+    let departures: Vec<(i32, BucketTemps)> = all_years.range(first_year..=last_year)
+        .map(|(year, temps)| (*year, unit.deltas(&periods::departures(temps, &base))))
+        .collect();
+
+    // one scale for every year of the city so the charts can be flipped through and compared
+    let y_scale = symmetric_scale(departures.iter().flat_map(|(_, d)| series.iter().flat_map(|s| d.series(s))).flatten().copied(), unit.step(5));
+
+    for (year, departure) in &departures {
+        for name in series {
+            let values = departure.series(name);
+            if values.iter().all(|v| v.is_none()) { // tmean before aggregate has rebuilt the table
+                continue;
+            }
+            let file_name = format!("imgs/{city}_{year}_{period}_{name}_anomaly{}.png", unit.file_suffix());
+            let series_text = daily::series_text(name);
+            let title_text = format!("{year} {city}  {} Avg {series_text} vs {}-{} Baseline ({})", title_period(period), baseline.0, baseline.1, unit.symbol());
+            let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+            dwg.fill(&WHITE)?;
+            draw_chart_base(&dwg, &title_text, period, &y_scale)?;
+            draw_anomaly_bars(&dwg, period, &y_scale, values)?;
+            dwg.present()?;
+        }
+    }
+    println!("Drew {} years of {period} anomaly charts for {city}", departures.len());
+    Ok(())
+}
+
+/// Symmetric scale around zero rounded up to a multiple of step, so the zero line sits on the middle grid line
+pub fn symmetric_scale(values: impl Iterator<Item = f64>, step: i32) -> YScale {
+    let biggest = values.fold(0.0_f64, |big, v| big.max(v.abs()));
+    let y_highest = ((biggest / f64::from(step)).ceil() as i32).max(1) * step;
+    let y_range = y_highest * 2;
+    let pixel_per_degree = f64::from(AXIS_HEIGHT) / f64::from(y_range);
+    YScale {
+        lowest: -y_highest,
+        highest: y_highest,
+        range: y_range,
+        pixel_per_degree,
+        zero_line_offset: f64::from(y_highest) * pixel_per_degree,
+    }
+}
+
+// Diverging bars from the zero line, y_scale.zero_line_offset is the distance from the bottom axis to zero
+pub fn draw_anomaly_bars(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, y_scale: &YScale, values: &[Option<f64>]) -> Result<(), Box<dyn std::error::Error>> {
+    draw_diverging_bars(dwg, period, y_scale, values, WARM_COLOR, COOL_COLOR)
+}
+
+// draw_anomaly_bars with other colors for values above and below zero
+pub fn draw_diverging_bars(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, y_scale: &YScale, values: &[Option<f64>], above: RGBColor, below: RGBColor) -> Result<(), Box<dyn std::error::Error>> {
+    let zero_y = TOP_MARGIN + AXIS_HEIGHT - y_scale.zero_line_offset.round() as i32;
+    for (idx, value) in values.iter().enumerate() {
+        let (Some(value), Some((x, width))) = (value, bar_x_width(period, idx as i32 + 1)) else { continue; };
+        let bar_y = zero_y - (value * y_scale.pixel_per_degree).round() as i32;
+        let color = if *value >= 0.0 { above } else { below };
+        dwg.draw(&Rectangle::new(
+            [(x, zero_y), (x + width, bar_y)],
+            Into::<ShapeStyle>::into(color).filled(),
+        ))?;
+    }
+    dwg.draw(&PathElement::new( // zero line over the bars
+        vec![(LEFT_MARGIN, zero_y), (LEFT_MARGIN + AXIS_WIDTH, zero_y)],
+        Into::<ShapeStyle>::into(&BLACK).stroke_width(2),
+    ))?;
+    Ok(())
+}
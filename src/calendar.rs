@@ -0,0 +1,223 @@
+// How dates are put into the buckets of each period. Aggregation, the period tables and the x axis labels
+// all go through here so a chart's buckets are the ones its table was built with. Periods:
+//   "Week"    - 7 day blocks from Jan 1, days 365 and 366 fold into week 52
+//   "Week53"  - 7 day blocks from Jan 1, days 365 and 366 are a short week 53
+//   "IsoWeek" - ISO 8601 weeks, Monday start, week 1 holds the year's first Thursday. The last days of
+//               December can be week 1 of the next year and the first days of January week 52/53 of the last
+//   "Fort"    - 14 day blocks from Jan 1, days 365 and 366 fold into fort 26
+//   "Fort27"  - 14 day blocks from Jan 1, days 365 and 366 are a short fort 27
+//   "Month"   - calendar months
+//   "Season"  - meteorological seasons DJF, MAM, JJA, SON. December counts toward the next year's winter
+// The original {city}_{period} tables came with their buckets already assigned and nothing records how, so only
+// the _agg tables aggregate builds (listed in period_calendar) are known to follow these rules.
+use chrono::{Datelike, NaiveDate};
+use sqlx::{MySql, Pool};
+
+const SEASONS: [&str; 4] = ["DJF", "MAM", "JJA", "SON"];
+
+pub fn bucket_count(period: &str) -> usize {
+    match period {
+        "Week" => 52,
+        "Week53" | "IsoWeek" => 53,
+        "Fort" => 26,
+        "Fort27" => 27,
+        "Month" => 12,
+        "Season" => 4,
+        _ => 0,
+    }
+}
+
+// column holding the bucket number in each period table
+pub fn period_column(period: &str) -> &'static str {
+    match period {
+        "Week" | "Week53" | "IsoWeek" => "tweek",
+        "Fort" | "Fort27" => "tfort",
+        "Month" => "tmonth",
+        "Season" => "tseason",
+        _ => "",
+    }
+}
+
+/// Year and bucket (1 based) a date is counted in. The year is the date's own except for IsoWeek
+/// (the ISO year) and Season (December goes with the following winter)
+pub fn date_bucket(period: &str, date: &NaiveDate) -> Option<(i32, usize)> {
+    let day0 = date.ordinal0() as usize;
+    match period {
+        "Week" => Some((date.year(), (day0 / 7).min(51) + 1)),
+        "Week53" => Some((date.year(), day0 / 7 + 1)),
+        "IsoWeek" => Some((date.iso_week().year(), date.iso_week().week() as usize)),
+        "Fort" => Some((date.year(), (day0 / 14).min(25) + 1)),
+        "Fort27" => Some((date.year(), day0 / 14 + 1)),
+        "Month" => Some((date.year(), date.month() as usize)),
+        "Season" => match date.month() {
+            12 => Some((date.year() + 1, 1)),
+            month => Some((date.year(), (month as usize / 3) % 4 + 1)),
+        },
+        _ => None,
+    }
+}
+
+/// First and last date counted in a bucket of a year, the inverse of date_bucket. None for buckets the year
+/// doesn't have (week 53 of an ISO year with 52 weeks). Season 1 starts on Dec 1 of the year before
+pub fn bucket_range(period: &str, year: i32, bucket: usize) -> Option<(NaiveDate, NaiveDate)> {
+    if bucket == 0 || bucket > bucket_count(period) {
+        return None;
+    }
+    let jan1 = NaiveDate::from_ymd_opt(year, 1, 1)?;
+    let dec31 = NaiveDate::from_ymd_opt(year, 12, 31)?;
+    // blocks of `days` from Jan 1, the last one runs to Dec 31 when the extra days fold into it
+    let block = |days: u64, folded_last: bool| -> Option<(NaiveDate, NaiveDate)> {
+        let start = jan1.checked_add_days(chrono::Days::new(days * (bucket as u64 - 1)))?;
+        let end = if folded_last && bucket == bucket_count(period) { dec31 } else { start.checked_add_days(chrono::Days::new(days - 1))?.min(dec31) };
+        (start.year() == year).then_some((start, end))
+    };
+    let month_end = |year: i32, month: u32| -> Option<NaiveDate> {
+        let next = if month == 12 { NaiveDate::from_ymd_opt(year + 1, 1, 1) } else { NaiveDate::from_ymd_opt(year, month + 1, 1) };
+        next?.pred_opt()
+    };
+    match period {
+        "Week" => block(7, true),
+        "Week53" => block(7, false),
+        "Fort" => block(14, true),
+        "Fort27" => block(14, false),
+        "IsoWeek" => {
+            let start = NaiveDate::from_isoywd_opt(year, bucket as u32, chrono::Weekday::Mon)?;
+            Some((start, NaiveDate::from_isoywd_opt(year, bucket as u32, chrono::Weekday::Sun)?))
+        },
+        "Month" => Some((NaiveDate::from_ymd_opt(year, bucket as u32, 1)?, month_end(year, bucket as u32)?)),
+        "Season" => match bucket {
+            1 => Some((NaiveDate::from_ymd_opt(year - 1, 12, 1)?, month_end(year, 2)?)),
+            _ => {
+                let first_month = bucket as u32 * 3 - 3;
+                Some((NaiveDate::from_ymd_opt(year, first_month, 1)?, month_end(year, first_month + 2)?))
+            },
+        },
+        _ => None,
+    }
+}
+
+/// x axis label for a bucket, month and season names, otherwise the bucket number
+pub fn bucket_label(period: &str, bucket: usize) -> String {
+    match period {
+        "Month" => crate::month_abbr(bucket as i32).to_string(),
+        "Season" => SEASONS.get(bucket.wrapping_sub(1)).copied().unwrap_or("").to_string(),
+        _ => bucket.to_string(),
+    }
+}
+
+/// One line saying how dates were bucketed, stored with every rebuilt table
+pub fn describe(period: &str) -> &'static str {
+    match period {
+        "Week" => "7 day blocks from Jan 1, days 365-366 in week 52",
+        "Week53" => "7 day blocks from Jan 1, days 365-366 in a short week 53",
+        "IsoWeek" => "ISO 8601 weeks of the ISO year",
+        "Fort" => "14 day blocks from Jan 1, days 365-366 in fort 26",
+        "Fort27" => "14 day blocks from Jan 1, days 365-366 in a short fort 27",
+        "Month" => "calendar months",
+        "Season" => "meteorological seasons, December in the next year's DJF",
+        _ => "unknown period",
+    }
+}
+
+pub async fn create_calendar_table(pool: &Pool<MySql>) -> Result<(), sqlx::Error> {
+    let create_stmt = r#"CREATE TABLE if NOT exists `period_calendar` (
+  `table_name` varchar(60) NOT NULL,
+  `period` varchar(8) NOT NULL,
+  `buckets` smallint(6) NOT NULL,
+  `strategy` varchar(80) NOT NULL,
+  `built` char(19) NOT NULL,
+  PRIMARY KEY (`table_name`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#;
+    let _result = sqlx::query(create_stmt).execute(pool).await?;
+    Ok(())
+}
+
+/// Records the bucketing a period table was built with, replacing what was recorded for it before
+pub async fn store_calendar(pool: &Pool<MySql>, table: &str, period: &str) -> Result<(), sqlx::Error> {
+    let built = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    sqlx::query("REPLACE INTO `period_calendar` (table_name, period, buckets, strategy, built) VALUES (?, ?, ?, ?, ?)")
+        .bind(table)
+        .bind(period)
+        .bind(bucket_count(period) as i32)
+        .bind(describe(period))
+        .bind(built)
+        .execute(pool).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIODS: [&str; 7] = ["Week", "Week53", "IsoWeek", "Fort", "Fort27", "Month", "Season"];
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn leap_year_dec_31_folds_or_gets_its_own_bucket() {
+        let dec31 = date(2020, 12, 31); // day 366
+        assert_eq!(date_bucket("Week", &dec31), Some((2020, 52)));
+        assert_eq!(date_bucket("Week53", &dec31), Some((2020, 53)));
+        assert_eq!(date_bucket("Fort", &dec31), Some((2020, 26)));
+        assert_eq!(date_bucket("Fort27", &dec31), Some((2020, 27)));
+        assert_eq!(date_bucket("Week53", &date(2020, 12, 30)), Some((2020, 53)));
+        assert_eq!(date_bucket("Week53", &date(2020, 12, 29)), Some((2020, 52)));
+    }
+
+    #[test]
+    fn iso_week_53() {
+        assert_eq!(date_bucket("IsoWeek", &date(2020, 12, 31)), Some((2020, 53)));
+        assert!(bucket_range("IsoWeek", 2020, 53).is_some());
+        assert_eq!(bucket_range("IsoWeek", 2021, 53), None);
+    }
+
+    #[test]
+    fn early_january_in_the_previous_iso_year() {
+        assert_eq!(date_bucket("IsoWeek", &date(2021, 1, 1)), Some((2020, 53)));
+        assert_eq!(date_bucket("IsoWeek", &date(2021, 1, 3)), Some((2020, 53)));
+        assert_eq!(date_bucket("IsoWeek", &date(2021, 1, 4)), Some((2021, 1)));
+        assert_eq!(date_bucket("IsoWeek", &date(2019, 12, 30)), Some((2020, 1)));
+    }
+
+    #[test]
+    fn december_in_next_years_winter() {
+        assert_eq!(date_bucket("Season", &date(2020, 12, 1)), Some((2021, 1)));
+        assert_eq!(date_bucket("Season", &date(2021, 2, 28)), Some((2021, 1)));
+        assert_eq!(date_bucket("Season", &date(2021, 3, 1)), Some((2021, 2)));
+        assert_eq!(date_bucket("Season", &date(2021, 11, 30)), Some((2021, 4)));
+        assert_eq!(bucket_range("Season", 2021, 1), Some((date(2020, 12, 1), date(2021, 2, 28))));
+    }
+
+    #[test]
+    fn every_date_is_inside_its_bucket_range() {
+        let mut day = date(2018, 12, 1);
+        while day <= date(2022, 1, 31) {
+            for period in PERIODS {
+                let (year, bucket) = date_bucket(period, &day).unwrap();
+                let (start, end) = bucket_range(period, year, bucket).unwrap_or_else(|| panic!("{period} {year} {bucket} has no range"));
+                assert!(start <= day && day <= end, "{period} {day} in {year} {bucket} = {start}..{end}");
+            }
+            day = day.succ_opt().unwrap();
+        }
+    }
+
+    #[test]
+    fn every_bucket_range_maps_back_to_its_bucket() {
+        for period in PERIODS {
+            for year in 2019..=2021 {
+                for bucket in 1..=bucket_count(period) {
+                    let Some((start, end)) = bucket_range(period, year, bucket) else { continue; };
+                    assert!(start <= end);
+                    assert_eq!(date_bucket(period, &start), Some((year, bucket)), "{period} {year} {bucket} start {start}");
+                    assert_eq!(date_bucket(period, &end), Some((year, bucket)), "{period} {year} {bucket} end {end}");
+                    assert_ne!(date_bucket(period, &end.succ_opt().unwrap()), Some((year, bucket)), "{period} {year} {bucket} runs past {end}");
+                    assert_ne!(date_bucket(period, &start.pred_opt().unwrap()), Some((year, bucket)), "{period} {year} {bucket} starts after {start}");
+                }
+            }
+        }
+        assert_eq!(bucket_range("Week", 2020, 0), None);
+        assert_eq!(bucket_range("Month", 2020, 13), None);
+    }
+}
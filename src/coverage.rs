@@ -0,0 +1,261 @@
+// Where the holes are in the raw daily {city} table: missing tmax / tmin days per year and month,
+// the longest runs of missing days and the years below a completeness fraction, as text, csv and
+// an image with a row per year and a column per day of the year.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use chrono::{Datelike, NaiveDate};
+use plotters::prelude::*;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT, AXIS_WIDTH, AXIS_HEIGHT, TOP_MARGIN, LEFT_MARGIN, BOTTOM_LINE_Y, draw_title, month_abbr};
+use crate::daily::{days_in_year, DailyTemp};
+use crate::heatmap::draw_year_label;
+
+const BOTH_READ: RGBColor = RGBColor(120, 190, 120);
+const TMAX_MISSING: RGBColor = RGBColor(240, 160, 0);
+const TMIN_MISSING: RGBColor = RGBColor(60, 120, 220);
+const BOTH_MISSING: RGBColor = RGBColor(200, 30, 30);
+const ESTIMATED: RGBColor = RGBColor(150, 60, 170); // missing reading with a value in {city}_fill
+const NOTE_SPACE: i32 = 30; // grid starts below the color key
+
+/// Missing days in one month, or one year when summed
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Coverage {
+    pub days: i32,
+    pub tmax_missing: i32,
+    pub tmin_missing: i32,
+}
+
+impl Coverage {
+    fn add(&mut self, other: &Coverage) {
+        self.days += other.days;
+        self.tmax_missing += other.tmax_missing;
+        self.tmin_missing += other.tmin_missing;
+    }
+
+    /// Fraction of days read, for whichever of tmax and tmin is missing more
+    pub fn completeness(&self) -> f64 {
+        if self.days == 0 {
+            return 0.0;
+        }
+        f64::from(self.days - self.tmax_missing.max(self.tmin_missing)) / f64::from(self.days)
+    }
+}
+
+/// Run of days without a reading
+#[derive(Clone, Debug)]
+pub struct Gap {
+    pub series: &'static str,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub days: i64,
+}
+
+/// (tmax read, tmin read) for every date with a row, a date read in any of its duplicate rows counts as read
+pub fn readings_by_date(days: &[DailyTemp]) -> BTreeMap<NaiveDate, (bool, bool)> {
+    let mut dates: BTreeMap<NaiveDate, (bool, bool)> = BTreeMap::new();
+    for day in days {
+        let read = dates.entry(day.date).or_default();
+        read.0 |= day.tmax.is_some();
+        read.1 |= day.tmin.is_some();
+    }
+    dates
+}
+
+/// First and last year with a row, None for an empty table
+pub fn record_years(readings: &BTreeMap<NaiveDate, (bool, bool)>) -> Option<(i32, i32)> {
+    Some((readings.keys().next()?.year(), readings.keys().last()?.year()))
+}
+
+/// Every month of first_year..=last_year keyed (year, month). Whole years are counted,
+/// so a record starting in June shows Jan-May missing
+pub fn monthly_coverage(readings: &BTreeMap<NaiveDate, (bool, bool)>, first_year: i32, last_year: i32) -> BTreeMap<(i32, u32), Coverage> {
+    let mut months: BTreeMap<(i32, u32), Coverage> = BTreeMap::new();
+    let (Some(jan_1), Some(dec_31)) = (NaiveDate::from_ymd_opt(first_year, 1, 1), NaiveDate::from_ymd_opt(last_year, 12, 31)) else {
+        return months;
+    };
+    for date in jan_1.iter_days().take_while(|d| *d <= dec_31) {
+        let (tmax_read, tmin_read) = readings.get(&date).copied().unwrap_or_default();
+        let month = months.entry((date.year(), date.month())).or_default();
+        month.days += 1;
+        month.tmax_missing += i32::from(!tmax_read);
+        month.tmin_missing += i32::from(!tmin_read);
+    }
+    months
+}
+
+pub fn yearly_coverage(months: &BTreeMap<(i32, u32), Coverage>) -> BTreeMap<i32, Coverage> {
+    let mut years: BTreeMap<i32, Coverage> = BTreeMap::new();
+    for ((year, _), coverage) in months {
+        years.entry(*year).or_default().add(coverage);
+    }
+    years
+}
+
+/// Longest runs of missing days for one series, longest first. Only between the first and last row
+/// of the table, the time before a station opened isn't a gap
+pub fn longest_gaps(readings: &BTreeMap<NaiveDate, (bool, bool)>, series: &'static str, count: usize) -> Vec<Gap> {
+    let read_dates: Vec<NaiveDate> = readings.iter()
+        .filter(|(_, (tmax_read, tmin_read))| if series == "tmax" { *tmax_read } else { *tmin_read })
+        .map(|(date, _)| *date)
+        .collect();
+    let mut gaps: Vec<Gap> = read_dates.windows(2)
+        .filter(|pair| (pair[1] - pair[0]).num_days() > 1)
+        .filter_map(|pair| Some(Gap { series, start: pair[0].succ_opt()?, end: pair[1].pred_opt()?, days: (pair[1] - pair[0]).num_days() - 1 }))
+        .collect();
+    gaps.sort_by(|a, b| b.days.cmp(&a.days).then(a.start.cmp(&b.start)));
+    gaps.truncate(count);
+    gaps
+}
+
+pub fn write_coverage_csv(city: &str, months: &BTreeMap<(i32, u32), Coverage>, completeness: f64) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_coverage.csv");
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "span,year,month,days,tmax_missing,tmin_missing,complete")?;
+    for (year, coverage) in yearly_coverage(months) {
+        writeln!(out, "year,{year},,{},{},{},{}", coverage.days, coverage.tmax_missing, coverage.tmin_missing, coverage.completeness() >= completeness)?;
+    }
+    for ((year, month), coverage) in months {
+        writeln!(out, "month,{year},{month},{},{},{},{}", coverage.days, coverage.tmax_missing, coverage.tmin_missing, coverage.completeness() >= completeness)?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+pub fn write_gaps_csv(city: &str, gaps: &[Gap]) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_gaps.csv");
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "series,start,end,days")?;
+    for gap in gaps {
+        writeln!(out, "{},{},{},{}", gap.series, gap.start.format("%Y-%m-%d"), gap.end.format("%Y-%m-%d"), gap.days)?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+/// Plain text summary: totals, the incomplete years and the longest gaps
+pub fn write_coverage_text(city: &str, months: &BTreeMap<(i32, u32), Coverage>, gaps: &[Gap], completeness: f64) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_coverage.txt");
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    let years = yearly_coverage(months);
+    let mut total = Coverage::default();
+    years.values().for_each(|coverage| total.add(coverage));
+    let (first, last) = (years.keys().next().copied().unwrap_or_default(), years.keys().last().copied().unwrap_or_default());
+    writeln!(out, "{city} {first}-{last}: {} days", total.days)?;
+    writeln!(out, "  tmax missing {} days ({:.1}%)", total.tmax_missing, 100.0 * f64::from(total.tmax_missing) / f64::from(total.days.max(1)))?;
+    writeln!(out, "  tmin missing {} days ({:.1}%)", total.tmin_missing, 100.0 * f64::from(total.tmin_missing) / f64::from(total.days.max(1)))?;
+    writeln!(out)?;
+    let incomplete: Vec<(&i32, &Coverage)> = years.iter().filter(|(_, c)| c.completeness() < completeness).collect();
+    writeln!(out, "Years with less than {:.0}% of days read: {}", completeness * 100.0, incomplete.len())?;
+    for (year, coverage) in incomplete {
+        writeln!(out, "  {year}: tmax missing {}, tmin missing {}", coverage.tmax_missing, coverage.tmin_missing)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "Longest gaps")?;
+    for gap in gaps {
+        writeln!(out, "  {} {} to {}: {} days", gap.series, gap.start.format("%Y-%m-%d"), gap.end.format("%Y-%m-%d"), gap.days)?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+/// Row per year, column per day of the year colored by which readings are there.
+/// Days in `filled` that are missing a reading are drawn as estimated
+pub fn draw_coverage(city: &str, readings: &BTreeMap<NaiveDate, (bool, bool)>, filled: &BTreeSet<NaiveDate>, first_year: i32, last_year: i32) -> Result<(), Box<dyn std::error::Error>> {
+    let file_name = format!("imgs/{city}_coverage.png");
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first_year}-{last_year}  Daily Data Coverage");
+    draw_title(&dwg, &title_text, ("sans-serif", 36).into_font().color(&BLACK))?;
+
+    let grid_top = TOP_MARGIN + NOTE_SPACE;
+    let cell_width = f64::from(AXIS_WIDTH) / 366.0;
+    let cell_height = f64::from(AXIS_HEIGHT - NOTE_SPACE) / f64::from(last_year - first_year + 1);
+    for (row, year) in (first_year..=last_year).enumerate() {
+        let y0 = grid_top + (row as f64 * cell_height).round() as i32;
+        let y1 = grid_top + ((row + 1) as f64 * cell_height).round() as i32;
+        for day0 in 0..days_in_year(year) {
+            let Some(date) = NaiveDate::from_yo_opt(year, day0 + 1) else { continue; };
+            let color = match readings.get(&date).copied().unwrap_or_default() {
+                (true, true) => BOTH_READ,
+                _ if filled.contains(&date) => ESTIMATED,
+                (false, true) => TMAX_MISSING,
+                (true, false) => TMIN_MISSING,
+                (false, false) => BOTH_MISSING,
+            };
+            let x0 = LEFT_MARGIN + (f64::from(day0) * cell_width).round() as i32;
+            let x1 = LEFT_MARGIN + (f64::from(day0 + 1) * cell_width).round() as i32;
+            dwg.draw(&Rectangle::new([(x0, y0), (x1, y1)], color.filled()))?;
+        }
+        if year % 10 == 0 || row == 0 {
+            draw_year_label(&dwg, year, y0 + (cell_height / 2.0).round() as i32)?;
+        }
+    }
+
+    // month names under the first day of each month, using a non leap year
+    let label_style = ("sans-serif", 14).into_font().color(&BLACK);
+    for month in 1..=12 {
+        let Some(first_day) = NaiveDate::from_ymd_opt(2001, month, 1) else { continue; };
+        let x = LEFT_MARGIN + (f64::from(first_day.ordinal0()) * cell_width).round() as i32;
+        dwg.draw_text(month_abbr(month as i32), &label_style, (x, BOTTOM_LINE_Y + 8))?;
+    }
+    // color key above the grid
+    let mut x = LEFT_MARGIN;
+    for (text, color) in [("Both read", BOTH_READ), ("tmax missing", TMAX_MISSING), ("tmin missing", TMIN_MISSING), ("Both missing", BOTH_MISSING), ("Estimated", ESTIMATED)] {
+        dwg.draw(&Rectangle::new([(x, TOP_MARGIN + 6), (x + 20, TOP_MARGIN + 20)], color.filled()))?;
+        dwg.draw_text(text, &label_style, (x + 26, TOP_MARGIN + 6))?;
+        x += 26 + dwg.estimate_text_size(text, &label_style)?.0 as i32 + 30;
+    }
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, dom: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, dom).unwrap()
+    }
+
+    fn day(date: NaiveDate, tmax: Option<i32>, tmin: Option<i32>) -> DailyTemp {
+        DailyTemp { date, tmax, tmin, tavg: None }
+    }
+
+    #[test]
+    fn duplicate_rows_count_as_read_if_either_was() {
+        let readings = readings_by_date(&[day(date(2001, 1, 1), Some(40), None), day(date(2001, 1, 1), None, Some(20))]);
+        assert_eq!(readings[&date(2001, 1, 1)], (true, true));
+    }
+
+    #[test]
+    fn whole_years_are_counted() {
+        let days: Vec<DailyTemp> = date(2000, 2, 1).iter_days().take(29).map(|d| day(d, Some(50), None)).collect();
+        let readings = readings_by_date(&days);
+        assert_eq!(record_years(&readings), Some((2000, 2000)));
+        let months = monthly_coverage(&readings, 2000, 2000);
+        assert_eq!(months.len(), 12);
+        let feb = months[&(2000, 2)];
+        assert_eq!((feb.days, feb.tmax_missing, feb.tmin_missing), (29, 0, 29));
+        assert_eq!(feb.completeness(), 0.0); // judged by the series missing more
+        let year = yearly_coverage(&months)[&2000];
+        assert_eq!((year.days, year.tmax_missing, year.tmin_missing), (366, 337, 366));
+        assert_eq!(record_years(&BTreeMap::new()), None);
+    }
+
+    #[test]
+    fn gaps_are_longest_first_and_inside_the_record() {
+        let read = [date(2001, 1, 1), date(2001, 1, 3), date(2001, 1, 10), date(2001, 1, 11), date(2001, 1, 15)];
+        let mut days: Vec<DailyTemp> = read.iter().map(|d| day(*d, Some(50), Some(30))).collect();
+        days.push(day(date(2001, 1, 12), None, Some(30))); // a row without tmax is still a tmax gap
+        let gaps = longest_gaps(&readings_by_date(&days), "tmax", 2);
+        let found: Vec<(NaiveDate, NaiveDate, i64)> = gaps.iter().map(|g| (g.start, g.end, g.days)).collect();
+        assert_eq!(found, vec![(date(2001, 1, 4), date(2001, 1, 9), 6), (date(2001, 1, 12), date(2001, 1, 14), 3)]);
+        let tmin_gaps = longest_gaps(&readings_by_date(&days), "tmin", 10);
+        assert_eq!(tmin_gaps.iter().map(|g| g.days).collect::<Vec<i64>>(), vec![6, 2, 1]);
+    }
+}
@@ -0,0 +1,94 @@
+// Raw daily hi/low/avg temps from the {city} table, for analysis that can't be done on the period averages.
+// The mean series is the station's reported TAVG where there is one, otherwise (tmax + tmin) / 2. Many
+// stations only started reporting TAVG in the late 1990s, so most of a long record is the computed mean
+use chrono::{Datelike, NaiveDate};
+use sqlx::{MySql, Pool, Row};
+
+/// The temperature series a chart can draw, in the order they're drawn
+pub const SERIES: [&str; 3] = ["tmax", "tmean", "tmin"];
+
+#[derive(Clone, Debug)]
+pub struct DailyTemp {
+    pub date: NaiveDate,
+    pub tmax: Option<i32>,
+    pub tmin: Option<i32>,
+    pub tavg: Option<i32>, // TAVG as reported by the station, usually the mean of hourly readings
+}
+
+/// Where a day's mean came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeanSource {
+    Reported, // TAVG
+    Computed, // (tmax + tmin) / 2
+}
+
+impl DailyTemp {
+    /// The day's mean and where it came from, None without a TAVG or both tmax and tmin
+    pub fn tmean(&self) -> Option<(f64, MeanSource)> {
+        match (self.tavg, self.tmax, self.tmin) {
+            (Some(tavg), _, _) => Some((f64::from(tavg), MeanSource::Reported)),
+            (None, Some(hi), Some(low)) => Some((f64::from(hi + low) / 2.0, MeanSource::Computed)),
+            _ => None,
+        }
+    }
+
+    /// "tmax", "tmin" or "tmean"
+    pub fn value(&self, series: &str) -> Option<f64> {
+        match series {
+            "tmax" => self.tmax.map(f64::from),
+            "tmin" => self.tmin.map(f64::from),
+            "tmean" => self.tmean().map(|(mean, _)| mean),
+            _ => None,
+        }
+    }
+}
+
+/// Every day for the city in date order. Rows with an unreadable tdate are skipped and counted
+pub async fn get_daily_temps(pool: &Pool<MySql>, city: &str) -> Result<Vec<DailyTemp>, sqlx::Error> {
+    let query_stmt_string = format!("SELECT * FROM {city} ORDER BY tdate"); // tables from before import have no tavg column
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_stmt_string)
+        .fetch_all(pool)
+        .await?;
+    let mut days = Vec::with_capacity(rows.len());
+    let mut bad_dates = 0;
+    for row in rows {
+        let tdate: &str = row.get("tdate"); //date string, for ex. 2020-09-05
+        let Some(date) = parse_tdate(tdate) else {
+            bad_dates += 1;
+            continue;
+        };
+        days.push(DailyTemp {
+            date,
+            tmax: row.try_get("tmax").ok(), // NULL means no reading that day
+            tmin: row.try_get("tmin").ok(),
+            tavg: row.try_get("tavg").ok(),
+        });
+    }
+    if bad_dates > 0 {
+        eprintln!("Skipped {bad_dates} rows of {city} with an unreadable tdate");
+    }
+    Ok(days)
+}
+
+/// Hi, Low or Mean for titles and legends
+pub fn series_text(series: &str) -> &'static str {
+    match series {
+        "tmax" => "Hi",
+        "tmin" => "Low",
+        "tmean" => "Mean",
+        _ => "Unknown",
+    }
+}
+
+pub fn parse_tdate(tdate: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(tdate.get(0..10)?, "%Y-%m-%d").ok()
+}
+
+pub fn days_in_year(year: i32) -> u32 {
+    if NaiveDate::from_ymd_opt(year, 2, 29).is_some() { 366 } else { 365 }
+}
+
+// (month, day) so Feb 29 is its own calendar day
+pub fn calendar_day(date: &NaiveDate) -> (u32, u32) {
+    (date.month(), date.day())
+}
@@ -0,0 +1,495 @@
+// The whole dataset as Parquet files for analysis tools, and back into MySQL on another machine without a dump.
+// One file per city and table, partitioned the way DuckDB / Spark / pandas read directories:
+//   {dir}/city={city}/period=Daily/part-0.parquet     the {city} table, one row per station day
+//   {dir}/city={city}/period={period}/part-0.parquet  the {city}_{period} table, period Week_agg for {city}_week_agg
+// Each file's key/value metadata has the schema version, city, period, units and for period files the
+// bucketing period_calendar recorded for the table, so a file copied somewhere on its own still says what it
+// holds. The original tables have no record and say NOT_RECORDED rather than claim calendar.rs's rules.
+// Values are written as stored (whole °F, inches), the unit setting doesn't apply here.
+// The daily file also carries the city's city_names min and max so charts work after an import.
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use chrono::NaiveDate;
+use sqlx::{MySql, Pool, Row};
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, DoubleType, Int32Type};
+use parquet::errors::ParquetError;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::record::{Field, Row as ParquetRow};
+use parquet::schema::parser::parse_message_type;
+
+use crate::aggregate;
+use crate::calendar;
+use crate::daily;
+use crate::import::{self, ImportRow};
+use crate::periods;
+
+pub const SCHEMA: &str = "weather3.dataset.1";
+pub const DAILY: &str = "Daily"; // period name of the daily table's partition
+pub const NOT_RECORDED: &str = "not recorded, buckets as loaded into the original table";
+
+type MinMax = Option<(i32, i32)>; // city_names (min_temp, max_temp)
+
+const DAILY_SCHEMA: &str = "message daily {
+  OPTIONAL BYTE_ARRAY station (UTF8);
+  REQUIRED INT32 tdate (DATE);
+  OPTIONAL INT32 tmax;
+  OPTIONAL INT32 tmin;
+  OPTIONAL BYTE_ARRAY tmax_attributes (UTF8);
+  OPTIONAL BYTE_ARRAY tmin_attributes (UTF8);
+  OPTIONAL INT32 tavg;
+  OPTIONAL DOUBLE prcp;
+  OPTIONAL DOUBLE snow;
+  OPTIONAL DOUBLE snwd;
+}";
+
+/// One row of a {city}_{period} table
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeriodRow {
+    pub station: Option<String>,
+    pub tyear: i32,
+    pub bucket: i32,
+    pub tmax: Option<i32>,
+    pub tmin: Option<i32>,
+    pub tmean: Option<i32>,
+    pub tmean_source: Option<String>,
+}
+
+// same columns as the table, bucket under its table name (tweek, tfort ...)
+fn period_schema(period: &str) -> String {
+    format!("message period {{
+  OPTIONAL BYTE_ARRAY station (UTF8);
+  REQUIRED INT32 tyear;
+  REQUIRED INT32 {};
+  OPTIONAL INT32 tmax;
+  OPTIONAL INT32 tmin;
+  OPTIONAL INT32 tmean;
+  OPTIONAL BYTE_ARRAY tmean_source (UTF8);
+}}", periods::period_column(period))
+}
+
+pub fn partition_file(dir: &str, city: &str, period: &str) -> PathBuf {
+    Path::new(dir).join(format!("city={city}")).join(format!("period={period}")).join("part-0.parquet")
+}
+
+/// Every (city, period, file) under dir, Daily first for each city so imports load the raw data before the averages
+pub fn partitions(dir: &str) -> Result<Vec<(String, String, PathBuf)>, std::io::Error> {
+    let mut found = Vec::new();
+    for city_dir in std::fs::read_dir(dir)? {
+        let city_dir = city_dir?.path();
+        let Some(city) = partition_value(&city_dir, "city") else { continue; };
+        for period_dir in std::fs::read_dir(&city_dir)? {
+            let period_dir = period_dir?.path();
+            let Some(period) = partition_value(&period_dir, "period") else { continue; };
+            let file = period_dir.join("part-0.parquet");
+            if file.is_file() {
+                found.push((city.clone(), period, file));
+            }
+        }
+    }
+    found.sort_by_key(|(city, period, _)| (city.clone(), period != DAILY, period.clone()));
+    Ok(found)
+}
+
+// "Los_Angeles_CA" from .../city=Los_Angeles_CA
+fn partition_value(path: &Path, key: &str) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    name.strip_prefix(key)?.strip_prefix('=').map(|v| v.to_string())
+}
+
+fn metadata(city: &str, period: &str, units: &str, bucketing: &str) -> Vec<KeyValue> {
+    let exported = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    vec![
+        KeyValue::new("weather3.schema".to_string(), SCHEMA.to_string()),
+        KeyValue::new("weather3.city".to_string(), city.to_string()),
+        KeyValue::new("weather3.period".to_string(), period.to_string()),
+        KeyValue::new("weather3.units".to_string(), units.to_string()),
+        KeyValue::new("weather3.bucketing".to_string(), bucketing.to_string()),
+        KeyValue::new("weather3.exported".to_string(), exported),
+    ]
+}
+
+fn create_writer(path: &Path, schema: &str, metadata: Vec<KeyValue>) -> Result<SerializedFileWriter<File>, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(path.parent().ok_or("partition file has no directory")?)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_key_value_metadata(Some(metadata))
+        .build();
+    Ok(SerializedFileWriter::new(File::create(path)?, parse_message_type(schema)?.into(), properties.into())?)
+}
+
+// the next column of the row group, they come in schema order. Definition level 1 where there's a value
+fn write_optional<T: DataType>(group: &mut SerializedRowGroupWriter<File>, values: &[Option<T::T>]) -> Result<(), ParquetError> {
+    let mut column = group.next_column()?.ok_or_else(|| ParquetError::General("more columns written than in the schema".to_string()))?;
+    let present: Vec<T::T> = values.iter().flatten().cloned().collect();
+    let levels: Vec<i16> = values.iter().map(|v| i16::from(v.is_some())).collect();
+    column.typed::<T>().write_batch(&present, Some(&levels), None)?;
+    column.close()
+}
+
+fn write_required<T: DataType>(group: &mut SerializedRowGroupWriter<File>, values: &[T::T]) -> Result<(), ParquetError> {
+    let mut column = group.next_column()?.ok_or_else(|| ParquetError::General("more columns written than in the schema".to_string()))?;
+    column.typed::<T>().write_batch(values, None, None)?;
+    column.close()
+}
+
+fn text(value: &str) -> Option<ByteArray> {
+    (!value.is_empty()).then(|| ByteArray::from(value))
+}
+
+fn days_from_epoch(date: NaiveDate) -> i32 {
+    (date - NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default()).num_days() as i32
+}
+
+// (min_temp, max_temp) from city_names, None when the city has no row
+async fn city_min_max(pool: &Pool<MySql>, city: &str) -> Result<MinMax, sqlx::Error> {
+    let row = sqlx::query("SELECT min_temp, max_temp FROM city_names WHERE name_of_city = ?")
+        .bind(city)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(|r| (r.get(0), r.get(1))))
+}
+
+/// Writes the Daily partition file of a city, rows without a readable tdate are left out. Returns the rows written
+pub fn write_daily_file(path: &Path, city: &str, rows: &[ImportRow], min_max: MinMax) -> Result<usize, Box<dyn std::error::Error>> {
+    let rows: Vec<(NaiveDate, &ImportRow)> = rows.iter().filter_map(|row| Some((daily::parse_tdate(&row.tdate)?, row))).collect();
+    let mut metadata = metadata(city, DAILY, "tmax, tmin, tavg: whole degrees F; prcp, snow, snwd: inches", "one row per station day");
+    if let Some((min_temp, max_temp)) = min_max {
+        metadata.push(KeyValue::new("weather3.city_min_temp".to_string(), min_temp.to_string()));
+        metadata.push(KeyValue::new("weather3.city_max_temp".to_string(), max_temp.to_string()));
+    }
+    let mut writer = create_writer(path, DAILY_SCHEMA, metadata)?;
+    let mut group = writer.next_row_group()?;
+    write_optional::<ByteArrayType>(&mut group, &rows.iter().map(|(_, r)| text(&r.station)).collect::<Vec<_>>())?;
+    write_required::<Int32Type>(&mut group, &rows.iter().map(|(date, _)| days_from_epoch(*date)).collect::<Vec<_>>())?;
+    write_optional::<Int32Type>(&mut group, &rows.iter().map(|(_, r)| r.tmax).collect::<Vec<_>>())?;
+    write_optional::<Int32Type>(&mut group, &rows.iter().map(|(_, r)| r.tmin).collect::<Vec<_>>())?;
+    write_optional::<ByteArrayType>(&mut group, &rows.iter().map(|(_, r)| text(&r.tmax_attributes)).collect::<Vec<_>>())?;
+    write_optional::<ByteArrayType>(&mut group, &rows.iter().map(|(_, r)| text(&r.tmin_attributes)).collect::<Vec<_>>())?;
+    write_optional::<Int32Type>(&mut group, &rows.iter().map(|(_, r)| r.tavg).collect::<Vec<_>>())?;
+    write_optional::<DoubleType>(&mut group, &rows.iter().map(|(_, r)| r.prcp).collect::<Vec<_>>())?;
+    write_optional::<DoubleType>(&mut group, &rows.iter().map(|(_, r)| r.snow).collect::<Vec<_>>())?;
+    write_optional::<DoubleType>(&mut group, &rows.iter().map(|(_, r)| r.snwd).collect::<Vec<_>>())?;
+    group.close()?;
+    writer.close()?;
+    Ok(rows.len())
+}
+
+/// The {city} table to {dir}/city={city}/period=Daily, returns the file written
+pub async fn export_daily(pool: &Pool<MySql>, dir: &str, city: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let rows = import::get_daily_rows(pool, city).await?;
+    let path = partition_file(dir, city, DAILY);
+    let count = write_daily_file(&path, city, &rows, city_min_max(pool, city).await?)?;
+    Ok(format!("{} ({count} rows)", path.display()))
+}
+
+// "Week" for both the Week and Week_agg partitions, the name calendar.rs knows the period by
+fn base_period(period: &str) -> &str {
+    period.strip_suffix("_agg").unwrap_or(period)
+}
+
+// {city}_week for Week, {city}_week_agg (aggregate::agg_table) for Week_agg
+fn period_table(city: &str, period: &str) -> String {
+    format!("{city}_{}", period.to_lowercase())
+}
+
+/// The bucketing line of a table built with calendar.rs's rules for period (Week, Week_agg ...)
+pub fn calendar_bucketing(period: &str) -> String {
+    let period = base_period(period);
+    format!("{} buckets in {}: {}", calendar::bucket_count(period), periods::period_column(period), calendar::describe(period))
+}
+
+// what period_calendar says the table was built with, None for tables it has no row for (the original ones)
+async fn recorded_bucketing(pool: &Pool<MySql>, table: &str, period: &str) -> Result<Option<String>, sqlx::Error> {
+    calendar::create_calendar_table(pool).await?;
+    let row = sqlx::query("SELECT buckets, strategy FROM period_calendar WHERE table_name = ?")
+        .bind(table)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(|r| format!("{} buckets in {}: {}", r.get::<i32, _>("buckets"), periods::period_column(base_period(period)), r.get::<String, _>("strategy"))))
+}
+
+/// Writes a period partition file, bucketing is NOT_RECORDED or calendar_bucketing's line. Returns the rows written
+pub fn write_period_file(path: &Path, city: &str, period: &str, bucketing: &str, rows: &[PeriodRow]) -> Result<usize, Box<dyn std::error::Error>> {
+    let units = "tmax, tmin, tmean: bucket averages rounded to whole degrees F; tmean_source: R reported TAVG, C (tmax + tmin) / 2, M mixed";
+    let mut writer = create_writer(path, &period_schema(base_period(period)), metadata(city, period, units, bucketing))?;
+    let mut group = writer.next_row_group()?;
+    write_optional::<ByteArrayType>(&mut group, &rows.iter().map(|r| r.station.as_deref().map(ByteArray::from)).collect::<Vec<_>>())?;
+    write_required::<Int32Type>(&mut group, &rows.iter().map(|r| r.tyear).collect::<Vec<_>>())?;
+    write_required::<Int32Type>(&mut group, &rows.iter().map(|r| r.bucket).collect::<Vec<_>>())?;
+    write_optional::<Int32Type>(&mut group, &rows.iter().map(|r| r.tmax).collect::<Vec<_>>())?;
+    write_optional::<Int32Type>(&mut group, &rows.iter().map(|r| r.tmin).collect::<Vec<_>>())?;
+    write_optional::<Int32Type>(&mut group, &rows.iter().map(|r| r.tmean).collect::<Vec<_>>())?;
+    write_optional::<ByteArrayType>(&mut group, &rows.iter().map(|r| r.tmean_source.as_deref().map(ByteArray::from)).collect::<Vec<_>>())?;
+    group.close()?;
+    writer.close()?;
+    Ok(rows.len())
+}
+
+/// The {city}_{period} table to {dir}/city={city}/period={period}, returns the file written. period can be Week_agg
+/// and the like for the tables aggregate builds. The bucketing metadata is what period_calendar recorded for the
+/// table, the original tables have no record so their files say NOT_RECORDED
+pub async fn export_period(pool: &Pool<MySql>, dir: &str, city: &str, period: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let tperiod = periods::period_column(base_period(period));
+    let table = period_table(city, period);
+    let rows: Vec<PeriodRow> = periods::get_all_temps(pool, tperiod, &table).await?.iter().map(|row| PeriodRow {
+        station: row.try_get::<String, _>("station").ok().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()),
+        tyear: row.get("tyear"),
+        bucket: row.get(tperiod),
+        tmax: row.try_get("tmax").ok(),
+        tmin: row.try_get("tmin").ok(),
+        tmean: row.try_get("tmean").ok(),
+        tmean_source: row.try_get("tmean_source").ok(),
+    }).collect();
+    let bucketing = recorded_bucketing(pool, &table, period).await?.unwrap_or_else(|| NOT_RECORDED.to_string());
+    let path = partition_file(dir, city, period);
+    let count = write_period_file(&path, city, period, &bucketing, &rows)?;
+    Ok(format!("{} ({count} rows)", path.display()))
+}
+
+// the file's weather3.* metadata value for key
+fn metadata_value(reader: &SerializedFileReader<File>, key: &str) -> Option<String> {
+    reader.metadata().file_metadata().key_value_metadata()?
+        .iter()
+        .find(|kv| kv.key == format!("weather3.{key}"))
+        .and_then(|kv| kv.value.clone())
+}
+
+// opens a partition file and checks it is this schema version, city and period
+fn open_partition(path: &Path, city: &str, period: &str) -> Result<SerializedFileReader<File>, Box<dyn std::error::Error>> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    let schema = metadata_value(&reader, "schema").unwrap_or_default();
+    if schema != SCHEMA {
+        return Err(format!("{} is schema \"{schema}\", expected {SCHEMA}", path.display()).into());
+    }
+    if metadata_value(&reader, "city").as_deref() != Some(city) || metadata_value(&reader, "period").as_deref() != Some(period) {
+        return Err(format!("{} is not the {city} {period} partition its directory says it is", path.display()).into());
+    }
+    Ok(reader)
+}
+
+fn field<'a>(row: &'a ParquetRow, name: &str) -> Option<&'a Field> {
+    row.get_column_iter().find(|(column, _)| column.as_str() == name).map(|(_, value)| value)
+}
+
+fn int_field(row: &ParquetRow, name: &str) -> Option<i32> {
+    match field(row, name)? {
+        Field::Int(v) | Field::Date(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn double_field(row: &ParquetRow, name: &str) -> Option<f64> {
+    match field(row, name)? {
+        Field::Double(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn str_field(row: &ParquetRow, name: &str) -> Option<String> {
+    match field(row, name)? {
+        Field::Str(v) => Some(v.clone()),
+        _ => None,
+    }
+}
+
+/// Rows of a Daily partition file and the city_names (min_temp, max_temp) it carries
+pub fn read_daily_file(path: &Path, city: &str) -> Result<(Vec<ImportRow>, MinMax), Box<dyn std::error::Error>> {
+    let reader = open_partition(path, city, DAILY)?;
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+    let mut rows = Vec::new();
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        let Some(date) = int_field(&row, "tdate").and_then(|days| epoch.checked_add_signed(chrono::Duration::days(i64::from(days)))) else { continue; };
+        rows.push(ImportRow {
+            station: str_field(&row, "station").unwrap_or_default(),
+            tdate: date.format("%Y-%m-%d").to_string(),
+            tmax: int_field(&row, "tmax"),
+            tmin: int_field(&row, "tmin"),
+            tmax_attributes: str_field(&row, "tmax_attributes").unwrap_or_default(),
+            tmin_attributes: str_field(&row, "tmin_attributes").unwrap_or_default(),
+            tavg: int_field(&row, "tavg"),
+            prcp: double_field(&row, "prcp"),
+            snow: double_field(&row, "snow"),
+            snwd: double_field(&row, "snwd"),
+        });
+    }
+    let min_max = match (metadata_value(&reader, "city_min_temp"), metadata_value(&reader, "city_max_temp")) {
+        (Some(min_temp), Some(max_temp)) => Some((min_temp.parse()?, max_temp.parse()?)),
+        _ => None,
+    };
+    Ok((rows, min_max))
+}
+
+/// Replaces the {city} table with a Daily partition file and updates or adds the city's city_names min and max.
+/// The hash ledger isn't touched, it only records CDO downloads
+pub async fn import_daily(pool: &Pool<MySql>, city: &str, path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    let (rows, min_max) = read_daily_file(path, city)?;
+    import::create_daily_table(pool, city).await?;
+    import::store_daily_rows(pool, city, &rows).await?;
+    if let Some((min_temp, max_temp)) = min_max {
+        let updated = sqlx::query("UPDATE city_names SET min_temp = ?, max_temp = ? WHERE name_of_city = ?")
+            .bind(min_temp).bind(max_temp).bind(city)
+            .execute(pool).await?;
+        if updated.rows_affected() == 0 && city_min_max(pool, city).await?.is_none() {
+            sqlx::query("INSERT INTO city_names (name_of_city, min_temp, max_temp) VALUES (?, ?, ?)")
+                .bind(city).bind(min_temp).bind(max_temp)
+                .execute(pool).await?;
+        }
+    }
+    Ok(rows.len())
+}
+
+/// Rows of a period partition file and whether its bucketing was recorded. Refuses a file bucketed by different
+/// rules than calendar.rs uses now, its buckets wouldn't line up with anything rebuilt here
+pub fn read_period_file(path: &Path, city: &str, period: &str) -> Result<(Vec<PeriodRow>, bool), Box<dyn std::error::Error>> {
+    let reader = open_partition(path, city, period)?;
+    let bucketing = calendar_bucketing(period);
+    let file_bucketing = metadata_value(&reader, "bucketing").unwrap_or_default();
+    if file_bucketing != bucketing && file_bucketing != NOT_RECORDED {
+        return Err(format!("{} was bucketed as \"{file_bucketing}\", this build buckets {period} as \"{bucketing}\"", path.display()).into());
+    }
+    let tperiod = periods::period_column(base_period(period));
+    let mut rows = Vec::new();
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        let (Some(tyear), Some(bucket)) = (int_field(&row, "tyear"), int_field(&row, tperiod)) else { continue; };
+        rows.push(PeriodRow {
+            station: str_field(&row, "station"),
+            tyear,
+            bucket,
+            tmax: int_field(&row, "tmax"),
+            tmin: int_field(&row, "tmin"),
+            tmean: int_field(&row, "tmean"),
+            tmean_source: str_field(&row, "tmean_source"),
+        });
+    }
+    Ok((rows, file_bucketing == bucketing))
+}
+
+/// Replaces the {city}_{period} table with a period partition file, see read_period_file. period_calendar only
+/// gets a row when the file's bucketing was recorded
+pub async fn import_period(pool: &Pool<MySql>, city: &str, period: &str, path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    let (rows, recorded) = read_period_file(path, city, period)?;
+    let tperiod = periods::period_column(base_period(period));
+    let table = period_table(city, period);
+    // dropped rather than emptied, an original table may not have the mean columns the file brings
+    sqlx::query(&format!("DROP TABLE IF EXISTS `{table}`")).execute(pool).await?;
+    aggregate::create_period_table(pool, &table, base_period(period)).await?;
+    let mut tx = pool.begin().await?;
+    let insert_stmt = format!("INSERT INTO `{table}` (id, station, tyear, {tperiod}, tmax, tmin, tmean, tmean_source) VALUES (?, ?, ?, ?, ?, ?, ?, ?)");
+    for (idx, row) in rows.iter().enumerate() {
+        sqlx::query(&insert_stmt)
+            .bind(idx as i32 + 1)
+            .bind(&row.station)
+            .bind(row.tyear)
+            .bind(row.bucket)
+            .bind(row.tmax)
+            .bind(row.tmin)
+            .bind(row.tmean)
+            .bind(&row.tmean_source)
+            .execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    if recorded {
+        calendar::store_calendar(pool, &table, base_period(period)).await?;
+    }
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("weather3_dataset_{}_{name}", std::process::id()));
+        dir.to_str().unwrap().to_string()
+    }
+
+    fn daily_row(tdate: &str, tmax: Option<i32>, tmin: Option<i32>, prcp: Option<f64>) -> ImportRow {
+        ImportRow {
+            station: "USW00023174".to_string(),
+            tdate: tdate.to_string(),
+            tmax,
+            tmin,
+            tmax_attributes: ",,W,2400".to_string(),
+            tmin_attributes: String::new(),
+            tavg: None,
+            prcp,
+            snow: Some(0.0),
+            snwd: None,
+        }
+    }
+
+    fn period_row(tyear: i32, bucket: i32, tmax: Option<i32>, tmean: Option<i32>) -> PeriodRow {
+        PeriodRow {
+            station: None,
+            tyear,
+            bucket,
+            tmax,
+            tmin: Some(50),
+            tmean,
+            tmean_source: tmean.map(|_| "C".to_string()),
+        }
+    }
+
+    #[test]
+    fn daily_round_trip_keeps_missing_values() {
+        let dir = temp_dir("daily");
+        let mut rows = vec![
+            daily_row("1899-12-31", Some(61), None, Some(0.25)),
+            daily_row("2020-02-29", None, Some(48), None),
+            daily_row("2021-07-04", Some(-3), Some(-20), Some(1.5)),
+        ];
+        rows[1].station = String::new();
+        rows[2].tavg = Some(-11);
+        let mut with_bad_date = rows.clone();
+        with_bad_date.push(daily_row("not a date", Some(70), Some(50), None));
+        let path = partition_file(&dir, "Los_Angeles_CA", DAILY);
+        assert_eq!(write_daily_file(&path, "Los_Angeles_CA", &with_bad_date, Some((30, 110))).unwrap(), 3);
+        let (read, min_max) = read_daily_file(&path, "Los_Angeles_CA").unwrap();
+        assert_eq!(read, rows);
+        assert_eq!(min_max, Some((30, 110)));
+        assert_eq!(partitions(&dir).unwrap(), vec![("Los_Angeles_CA".to_string(), DAILY.to_string(), path.clone())]);
+        assert!(read_daily_file(&path, "Spokane_WA").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn period_round_trip_keeps_missing_values() {
+        let dir = temp_dir("period");
+        let mut rows = vec![period_row(1950, 1, Some(70), None), period_row(1950, 3, None, Some(60)), period_row(2020, 52, Some(-5), Some(-12))];
+        rows[0].station = Some("USW00023174".to_string());
+        rows[2].tmin = None;
+        let path = partition_file(&dir, "Los_Angeles_CA", "Week_agg");
+        write_period_file(&path, "Los_Angeles_CA", "Week_agg", &calendar_bucketing("Week_agg"), &rows).unwrap();
+        let (read, recorded) = read_period_file(&path, "Los_Angeles_CA", "Week_agg").unwrap();
+        assert!(recorded);
+        assert_eq!(read, rows);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unrecorded_bucketing_loads_without_a_calendar_record() {
+        let dir = temp_dir("unrecorded");
+        let path = partition_file(&dir, "Los_Angeles_CA", "Month");
+        write_period_file(&path, "Los_Angeles_CA", "Month", NOT_RECORDED, &[period_row(1950, 12, Some(68), None)]).unwrap();
+        let (read, recorded) = read_period_file(&path, "Los_Angeles_CA", "Month").unwrap();
+        assert_eq!((read.len(), recorded), (1, false));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mismatched_bucketing_is_refused() {
+        // import_period reads through read_period_file, so this is the check that keeps the table untouched
+        let dir = temp_dir("mismatch");
+        let path = partition_file(&dir, "Los_Angeles_CA", "Week_agg");
+        write_period_file(&path, "Los_Angeles_CA", "Week_agg", &calendar_bucketing("Week53"), &[period_row(2020, 53, Some(60), None)]).unwrap();
+        let error = read_period_file(&path, "Los_Angeles_CA", "Week_agg").unwrap_err().to_string();
+        assert!(error.contains("was bucketed as"), "{error}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
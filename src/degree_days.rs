@@ -0,0 +1,176 @@
+// Heating and cooling degree days from daily hi/low, totaled into the week / fort / month buckets
+// ({city}_dd_week, {city}_dd_fort, {city}_dd_month) and per year ({city}_dd_year).
+// Each day is max(0, base - mean) heating and max(0, mean - base) cooling, mean = the reported TAVG or
+// (tmax + tmin) / 2 without one. Days with no mean add nothing, the days column says how many days are behind each total.
+// The tables are always °F degree days, the charts convert them (a °C degree day is 5/9 of a °F one).
+use std::collections::BTreeMap;
+use chrono::{Datelike, NaiveDate};
+use sqlx::{mysql::MySqlRow, MySql, Pool, Row};
+use plotters::prelude::*;
+
+use crate::{YScale, DWG_WIDTH, DWG_HEIGHT, BOTTOM_LINE_Y, bar_x_width, draw_chart_base, title_period};
+use crate::aggregate::{self, BucketSums};
+use crate::daily::DailyTemp;
+use crate::periods;
+use crate::units::Unit;
+use crate::yearchart;
+
+const HDD_COLOR: RGBColor = RGBColor(0, 255, 0); // same green as draw_low_temps
+const CDD_COLOR: RGBColor = RGBColor(255, 0, 0); // same red as draw_hi_temps
+
+pub fn daily_degree_days(day: &DailyTemp, base: f64) -> Option<(f64, f64)> {
+    let (mean, _) = day.tmean()?;
+    Some(((base - mean).max(0.0), (mean - base).max(0.0)))
+}
+
+/// (heating, cooling) totals per bucket for every year
+pub fn degree_days_by_bucket(period: &str, days: &[DailyTemp], base: f64) -> (BTreeMap<i32, BucketSums>, BTreeMap<i32, BucketSums>) {
+    let dd: Vec<(NaiveDate, (f64, f64))> = days.iter().filter_map(|d| Some((d.date, daily_degree_days(d, base)?))).collect();
+    let heating = aggregate::sum_by_bucket(period, dd.iter().map(|(date, (hdd, _))| (*date, *hdd)));
+    let cooling = aggregate::sum_by_bucket(period, dd.iter().map(|(date, (_, cdd))| (*date, *cdd)));
+    (heating, cooling)
+}
+
+/// (heating, cooling, days) for every year
+pub fn annual_degree_days(days: &[DailyTemp], base: f64) -> BTreeMap<i32, (f64, f64, i32)> {
+    let mut years: BTreeMap<i32, (f64, f64, i32)> = BTreeMap::new();
+    for day in days {
+        let Some((hdd, cdd)) = daily_degree_days(day, base) else { continue; };
+        let year = years.entry(day.date.year()).or_default();
+        year.0 += hdd;
+        year.1 += cdd;
+        year.2 += 1;
+    }
+    years
+}
+
+pub async fn create_degree_day_tables(pool: &Pool<MySql>, city: &str) -> Result<(), sqlx::Error> {
+    for period in ["Week", "Fort", "Month"] {
+        let create_stmt = format!(r#"CREATE TABLE if NOT exists `{}_dd_{}` (
+  `id` int(11) NOT NULL,
+  `station` char(12) DEFAULT NULL,
+  `tyear` smallint(6) NOT NULL,
+  `{}` smallint(6) NOT NULL,
+  `hdd` smallint(6) DEFAULT NULL,
+  `cdd` smallint(6) DEFAULT NULL,
+  `days` smallint(6) NOT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#, city, period.to_lowercase(), periods::period_column(period));
+        let _result = sqlx::query(&create_stmt).execute(pool).await?;
+    }
+    let create_year_stmt = format!(r#"CREATE TABLE if NOT exists `{city}_dd_year` (
+  `tyear` smallint(6) NOT NULL,
+  `hdd` int(11) DEFAULT NULL,
+  `cdd` int(11) DEFAULT NULL,
+  `days` smallint(6) NOT NULL,
+  PRIMARY KEY (`tyear`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#);
+    let _result = sqlx::query(&create_year_stmt).execute(pool).await?;
+    Ok(())
+}
+
+// replaces everything in {city}_dd_{period}, totals are rounded to whole degree days like the period averages
+pub async fn store_degree_days(pool: &Pool<MySql>, city: &str, period: &str, heating: &BTreeMap<i32, BucketSums>, cooling: &BTreeMap<i32, BucketSums>) -> Result<(), sqlx::Error> {
+    let table = format!("{city}_dd_{}", period.to_lowercase());
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!("DELETE FROM `{table}`")).execute(&mut *tx).await?;
+    let insert_stmt = format!("INSERT INTO `{table}` (id, tyear, {}, hdd, cdd, days) VALUES (?, ?, ?, ?, ?, ?)", periods::period_column(period));
+    let mut id = 0;
+    for (year, hdd) in heating {
+        let Some(cdd) = cooling.get(year) else { continue; };
+        for idx in 0..hdd.sums.len() {
+            id += 1;
+            let has_days = hdd.days[idx] > 0;
+            sqlx::query(&insert_stmt)
+                .bind(id)
+                .bind(year)
+                .bind(idx as i32 + 1)
+                .bind(has_days.then(|| hdd.sums[idx].round() as i32))
+                .bind(has_days.then(|| cdd.sums[idx].round() as i32))
+                .bind(hdd.days[idx])
+                .execute(&mut *tx).await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn store_annual_degree_days(pool: &Pool<MySql>, city: &str, annual: &BTreeMap<i32, (f64, f64, i32)>) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!("DELETE FROM `{city}_dd_year`")).execute(&mut *tx).await?;
+    let insert_stmt = format!("INSERT INTO `{city}_dd_year` (tyear, hdd, cdd, days) VALUES (?, ?, ?, ?)");
+    for (year, (hdd, cdd, days)) in annual {
+        sqlx::query(&insert_stmt).bind(year).bind(hdd.round() as i32).bind(cdd.round() as i32).bind(days).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// Rows shaped like get_temps returns: cdd as tmax, hdd as tmin
+pub async fn get_degree_days(pool: &Pool<MySql>, city: &str, period: &str, year: i32) -> Result<Vec<MySqlRow>, sqlx::Error> {
+    let tperiod = periods::period_column(period);
+    let query_string = format!("SELECT tyear, {tperiod}, cdd AS tmax, hdd AS tmin FROM {city}_dd_{} WHERE tyear = {year} ORDER BY {tperiod}", period.to_lowercase());
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_string)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows)
+}
+
+/// Heating (green) and cooling (red) degree day charts for one year, both on one scale.
+/// base and biggest are °F
+pub fn draw_degree_day_charts(city: &str, period: &str, year: i32, base: f64, rows: &[MySqlRow], biggest: f64, unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let y_scale = yearchart::value_scale([0.0, unit.delta(biggest)].into_iter(), 0.0);
+    for (kind, name) in [("hdd", "Heating"), ("cdd", "Cooling")] {
+        let file_name = format!("imgs/{city}_{year}_{period}_{kind}{}.png", unit.file_suffix());
+        let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+        dwg.fill(&WHITE)?;
+        let title_text = format!("{year} {city}  {} {name} Degree Days (base {}{})", title_period(period), unit.format_temp(base, 0), unit.symbol());
+        draw_chart_base(&dwg, &title_text, period, &y_scale)?;
+        if kind == "hdd" {
+            draw_degree_day_bars(&dwg, period, "tmin", GREEN, &y_scale, rows, unit)?;
+        } else {
+            draw_degree_day_bars(&dwg, period, "tmax", RED, &y_scale, rows, unit)?;
+        }
+        dwg.present()?;
+        println!("Drew {file_name}");
+    }
+    Ok(())
+}
+
+// Bars up from the zero line like draw_precip_bars, bar_height's extra degree for charts above 0°F would
+// make every total one degree day too tall
+fn draw_degree_day_bars(dwg: &DrawingArea<BitMapBackend, plotters::coord::Shift>, period: &str, column: &str, color: RGBColor, y_scale: &YScale, rows: &[MySqlRow], unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    for row in rows {
+        let Ok(bucket) = row.try_get::<i32, _>(periods::period_column(period)) else { continue; };
+        let Ok(dd) = row.try_get::<i32, _>(column) else { continue; };
+        let Some((x, width)) = bar_x_width(period, bucket) else { continue; };
+        let height = (unit.delta(f64::from(dd)) * y_scale.pixel_per_degree + y_scale.zero_line_offset).round() as i32;
+        dwg.draw(&Rectangle::new(
+            [(x, BOTTOM_LINE_Y - 2), (x + width, BOTTOM_LINE_Y - height)],
+            Into::<ShapeStyle>::into(color).filled(),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Annual heating and cooling totals over the years, years with missing days drawn as they are
+pub fn draw_annual_degree_days(city: &str, base: f64, annual: &BTreeMap<i32, (f64, f64, i32)>, unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some(first), Some(last)) = (annual.keys().next().copied(), annual.keys().last().copied()) else {
+        return Ok(());
+    };
+    let heating: Vec<(i32, f64)> = annual.iter().map(|(year, (hdd, _, _))| (*year, unit.delta(*hdd))).collect();
+    let cooling: Vec<(i32, f64)> = annual.iter().map(|(year, (_, cdd, _))| (*year, unit.delta(*cdd))).collect();
+    let y_scale = yearchart::value_scale(heating.iter().chain(&cooling).map(|(_, v)| *v).chain([0.0]), 0.0);
+    let file_name = format!("imgs/{city}_annual_degree_days{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first}-{last}  Annual Degree Days (base {}{})", unit.format_temp(base, 0), unit.symbol());
+    yearchart::draw_year_chart_base(&dwg, &title_text, first, last, &y_scale)?;
+    yearchart::draw_year_line(&dwg, &heating, first, last, &y_scale, HDD_COLOR.stroke_width(2))?;
+    yearchart::draw_year_line(&dwg, &cooling, first, last, &y_scale, CDD_COLOR.stroke_width(2))?;
+    let legend = vec![("Heating degree days".to_string(), HDD_COLOR), ("Cooling degree days".to_string(), CDD_COLOR)];
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
@@ -0,0 +1,215 @@
+// Observation by observation differences between two versions of a city's daily data, each either a
+// CDO csv file or the {city} table. Where ledger verify says which station-years changed, this says how:
+// every added, removed and changed day with old and new values and flags, counts per year, and a chart
+// of how much the period averages moved.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use sqlx::{MySql, Pool};
+use plotters::prelude::*;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT, title_period};
+use crate::aggregate;
+use crate::import::{self, ImportRow};
+use crate::periods;
+use crate::units::Unit;
+use crate::yearchart;
+
+const HI_COLOR: RGBColor = RGBColor(200, 30, 30);
+const LOW_COLOR: RGBColor = RGBColor(30, 80, 200);
+
+#[derive(Clone, Debug)]
+pub struct ObsDiff {
+    pub station: String,
+    pub tdate: String,
+    pub kind: &'static str, // "added", "removed" or "changed"
+    pub old: Option<ImportRow>,
+    pub new: Option<ImportRow>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct YearDiff {
+    pub added: i32,
+    pub removed: i32,
+    pub changed: i32,
+}
+
+/// "db" for the {city} table, anything else is the path of a CDO csv
+pub async fn load_version(pool: &Pool<MySql>, city: &str, source: &str) -> Result<Vec<ImportRow>, Box<dyn std::error::Error>> {
+    if source == "db" {
+        Ok(import::get_daily_rows(pool, city).await?)
+    } else {
+        Ok(import::read_cdo_csv(source)?.0)
+    }
+}
+
+// keyed by (station, date), the first row wins when a date is duplicated
+fn by_observation(rows: &[ImportRow]) -> BTreeMap<(String, String), &ImportRow> {
+    let mut observations = BTreeMap::new();
+    for row in rows {
+        observations.entry((row.station.clone(), row.tdate.clone())).or_insert(row);
+    }
+    observations
+}
+
+pub fn diff_rows(old: &[ImportRow], new: &[ImportRow]) -> Vec<ObsDiff> {
+    let (old_obs, new_obs) = (by_observation(old), by_observation(new));
+    let mut keys: Vec<&(String, String)> = old_obs.keys().chain(new_obs.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter().filter_map(|key| {
+        let (old_row, new_row) = (old_obs.get(key).copied(), new_obs.get(key).copied());
+        let kind = match (old_row, new_row) {
+            (Some(o), Some(n)) if o == n => return None,
+            (Some(_), Some(_)) => "changed",
+            (None, Some(_)) => "added",
+            (Some(_), None) => "removed",
+            (None, None) => return None,
+        };
+        Some(ObsDiff { station: key.0.clone(), tdate: key.1.clone(), kind, old: old_row.cloned(), new: new_row.cloned() })
+    }).collect()
+}
+
+pub fn diffs_per_year(diffs: &[ObsDiff]) -> BTreeMap<i32, YearDiff> {
+    let mut years: BTreeMap<i32, YearDiff> = BTreeMap::new();
+    for diff in diffs {
+        let Some(year) = diff.tdate.get(0..4).and_then(|y| y.parse::<i32>().ok()) else { continue; };
+        let counts = years.entry(year).or_default();
+        match diff.kind {
+            "added" => counts.added += 1,
+            "removed" => counts.removed += 1,
+            _ => counts.changed += 1,
+        }
+    }
+    years
+}
+
+pub fn write_diff_csv(city: &str, diffs: &[ObsDiff], unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_diff{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "station,date,change,old_tmax,new_tmax,old_tmin,new_tmin,old_tmax_attributes,new_tmax_attributes,old_tmin_attributes,new_tmin_attributes,old_tavg,new_tavg,old_prcp,new_prcp,old_snow,new_snow,old_snwd,new_snwd")?;
+    let temp = |row: &Option<ImportRow>, hi: bool| row.as_ref().and_then(|r| if hi { r.tmax } else { r.tmin }).map(|t| unit.format_temp(f64::from(t), 0)).unwrap_or_default();
+    let flags = |row: &Option<ImportRow>, hi: bool| row.as_ref().map(|r| if hi { r.tmax_attributes.clone() } else { r.tmin_attributes.clone() }).unwrap_or_default();
+    let tavg = |row: &Option<ImportRow>| row.as_ref().and_then(|r| r.tavg).map(|t| unit.format_temp(f64::from(t), 0)).unwrap_or_default();
+    let amount = |row: &Option<ImportRow>, element: &str| row.as_ref().and_then(|r| match element {
+        "prcp" => r.prcp,
+        "snow" => r.snow,
+        _ => r.snwd,
+    }).map(|v| v.to_string()).unwrap_or_default();
+    for diff in diffs {
+        writeln!(out, "{},{},{},{},{},{},{},\"{}\",\"{}\",\"{}\",\"{}\",{},{},{},{},{},{},{},{}", diff.station, diff.tdate, diff.kind,
+                 temp(&diff.old, true), temp(&diff.new, true), temp(&diff.old, false), temp(&diff.new, false),
+                 flags(&diff.old, true), flags(&diff.new, true), flags(&diff.old, false), flags(&diff.new, false),
+                 tavg(&diff.old), tavg(&diff.new),
+                 amount(&diff.old, "prcp"), amount(&diff.new, "prcp"), amount(&diff.old, "snow"), amount(&diff.new, "snow"),
+                 amount(&diff.old, "snwd"), amount(&diff.new, "snwd"))?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+pub fn write_year_diff_csv(city: &str, years: &BTreeMap<i32, YearDiff>) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_diff_years.csv");
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "year,added,removed,changed")?;
+    for (year, counts) in years {
+        writeln!(out, "{year},{},{},{}", counts.added, counts.removed, counts.changed)?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+/// Mean over the buckets of each year of (new avg - old avg), (tmax shift, tmin shift). Only buckets both versions have are counted
+pub fn period_shifts(period: &str, old: &[ImportRow], new: &[ImportRow]) -> BTreeMap<i32, (Option<f64>, Option<f64>)> {
+    let old_means = aggregate::period_means(period, &import::to_daily_temps(old));
+    let new_means = aggregate::period_means(period, &import::to_daily_temps(new));
+    let shift = |new: &[Option<f64>], old: &[Option<f64>]| periods::mean(new.iter().zip(old).filter_map(|(n, o)| Some((*n)? - (*o)?)));
+    new_means.iter().filter_map(|(year, new_temps)| {
+        let old_temps = old_means.get(year)?;
+        Some((*year, (shift(&new_temps.tmax, &old_temps.tmax), shift(&new_temps.tmin, &old_temps.tmin))))
+    }).collect()
+}
+
+/// How far each year's period averages moved between the versions, zero means nothing that matters changed. shifts are °F
+pub fn draw_period_shifts(city: &str, period: &str, shifts: &BTreeMap<i32, (Option<f64>, Option<f64>)>, unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some(first), Some(last)) = (shifts.keys().next().copied(), shifts.keys().last().copied()) else {
+        println!("The versions share no years, no shift chart drawn");
+        return Ok(());
+    };
+    let hi: Vec<(i32, f64)> = shifts.iter().filter_map(|(year, (h, _))| Some((*year, unit.delta((*h)?)))).collect();
+    let low: Vec<(i32, f64)> = shifts.iter().filter_map(|(year, (_, l))| Some((*year, unit.delta((*l)?)))).collect();
+    let y_scale = yearchart::value_scale(hi.iter().chain(&low).map(|(_, v)| *v).chain([0.0]), 1.0);
+    let file_name = format!("imgs/{city}_{period}_diff_shift{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first}-{last}  {} Avg Change, New - Old ({})", title_period(period), unit.symbol());
+    yearchart::draw_year_chart_base(&dwg, &title_text, first, last, &y_scale)?;
+    yearchart::draw_year_line(&dwg, &hi, first, last, &y_scale, HI_COLOR.stroke_width(2))?;
+    yearchart::draw_year_line(&dwg, &low, first, last, &y_scale, LOW_COLOR.stroke_width(2))?;
+    let legend = vec![("Avg Hi shift".to_string(), HI_COLOR), ("Avg Low shift".to_string(), LOW_COLOR)];
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(station: &str, tdate: &str, tmax: Option<i32>, tmax_attributes: &str) -> ImportRow {
+        ImportRow {
+            station: station.to_string(),
+            tdate: tdate.to_string(),
+            tmax,
+            tmin: Some(40),
+            tmax_attributes: tmax_attributes.to_string(),
+            tmin_attributes: String::new(),
+            tavg: None,
+            prcp: None,
+            snow: None,
+            snwd: None,
+        }
+    }
+
+    #[test]
+    fn added_removed_and_changed_observations() {
+        let old = [
+            row("A", "2001-01-01", Some(60), ""),
+            row("A", "2001-01-02", Some(61), ""),
+            row("A", "2001-01-03", Some(62), ""),
+            row("A", "2001-01-04", Some(63), ""),
+        ];
+        let new = [
+            row("A", "2001-01-01", Some(60), ""),
+            row("A", "2001-01-02", Some(65), ""), // value changed
+            row("A", "2001-01-03", Some(62), ",,W,2400"), // only a flag changed
+            row("B", "2001-01-04", Some(63), ""), // same day from another station
+            row("A", "2001-01-01", Some(99), ""), // duplicate, the first row is the observation
+        ];
+        let diffs = diff_rows(&old, &new);
+        let found: Vec<(&str, &str, &str)> = diffs.iter().map(|d| (d.station.as_str(), d.tdate.as_str(), d.kind)).collect();
+        assert_eq!(found, vec![
+            ("A", "2001-01-02", "changed"),
+            ("A", "2001-01-03", "changed"),
+            ("A", "2001-01-04", "removed"),
+            ("B", "2001-01-04", "added"),
+        ]);
+        assert_eq!(diffs[2].new, None);
+        assert_eq!(diffs[3].old, None);
+    }
+
+    #[test]
+    fn per_year_counts_skip_dates_without_a_year() {
+        let diff = |tdate: &str, kind| ObsDiff { station: "A".to_string(), tdate: tdate.to_string(), kind, old: None, new: None };
+        let diffs = [diff("2000-12-31", "added"), diff("2001-01-01", "removed"), diff("2001-06-01", "changed"),
+                     diff("2001-07-01", "changed"), diff("200", "added"), diff("", "added"), diff("year-01-01", "added")];
+        let years = diffs_per_year(&diffs);
+        assert_eq!(years.keys().copied().collect::<Vec<i32>>(), vec![2000, 2001]);
+        let y = years[&2001];
+        assert_eq!((y.added, y.removed, y.changed), (0, 1, 2));
+        assert_eq!(years[&2000].added, 1);
+    }
+}
@@ -0,0 +1,187 @@
+// Diurnal temperature range (tmax - tmin) as its own series. Highs and lows can both warm while the
+// range between them narrows or widens, so DTR is trended separately from the annual means.
+// Daily values come from the raw {city} table, period values from the {city}_{period} averages.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use chrono::NaiveDate;
+use plotters::prelude::*;
+use plotters::element::DashedPathElement;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT};
+use crate::daily::DailyTemp;
+use crate::periods::{self, BucketTemps};
+use crate::trend::{linear_fit, moving_average};
+use crate::units::Unit;
+use crate::yearchart::{self, value_y, year_x};
+
+const DTR_COLOR: RGBColor = RGBColor(120, 40, 160);
+// one line per city on the comparison chart, repeats after 8 cities
+const CITY_COLORS: [RGBColor; 8] = [
+    RGBColor(200, 30, 30),
+    RGBColor(30, 80, 200),
+    RGBColor(0, 140, 60),
+    RGBColor(220, 120, 0),
+    RGBColor(120, 40, 160),
+    RGBColor(0, 160, 170),
+    RGBColor(150, 100, 50),
+    RGBColor(90, 90, 90),
+];
+
+/// (date, tmax - tmin) for every day with both readings
+pub fn daily_dtr(days: &[DailyTemp]) -> Vec<(NaiveDate, i32)> {
+    days.iter().filter_map(|d| Some((d.date, d.tmax? - d.tmin?))).collect()
+}
+
+/// Range of every bucket, None unless the bucket has both an avg hi and an avg low
+pub fn bucket_dtr(temps: &BucketTemps) -> Vec<Option<f64>> {
+    temps.tmax.iter().zip(&temps.tmin).map(|(hi, low)| Some((*hi)? - (*low)?)).collect()
+}
+
+/// Mean range of each year with every bucket present, same rule as periods::annual_means
+pub fn annual_dtr(years: &BTreeMap<i32, BucketTemps>, first_year: i32, last_year: i32) -> Vec<(i32, f64)> {
+    years.range(first_year..=last_year).filter_map(|(year, temps)| {
+        let ranges = bucket_dtr(temps);
+        if ranges.iter().any(|r| r.is_none()) {
+            return None;
+        }
+        Some((*year, periods::mean(ranges.into_iter().flatten())?))
+    }).collect()
+}
+
+pub fn write_daily_dtr_csv(city: &str, daily: &[(NaiveDate, i32)], unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_dtr_daily{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "date,dtr")?;
+    for (date, dtr) in daily {
+        writeln!(out, "{},{}", date.format("%Y-%m-%d"), unit.format_delta(f64::from(*dtr), 0))?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+pub fn write_period_dtr_csv(city: &str, period: &str, years: &BTreeMap<i32, BucketTemps>, unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_{period}_dtr{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "year,{},dtr", periods::period_column(period))?;
+    for (year, temps) in years {
+        for (idx, dtr) in bucket_dtr(temps).iter().enumerate() {
+            let dtr = dtr.map(|v| unit.format_delta(v, 0)).unwrap_or_default();
+            writeln!(out, "{year},{},{dtr}", idx + 1)?;
+        }
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+/// Annual mean DTR (°F) with its moving average and least squares trend
+pub fn draw_dtr_trend(city: &str, first_year: i32, last_year: i32, annual: &[(i32, f64)], smoothing_years: i32, unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    if annual.is_empty() {
+        println!("No complete years for {city}, no DTR chart drawn");
+        return Ok(());
+    }
+    let annual: Vec<(i32, f64)> = annual.iter().map(|(year, v)| (*year, unit.delta(*v))).collect();
+    let annual = annual.as_slice();
+    let y_scale = yearchart::value_scale(annual.iter().map(|(_, v)| *v), unit.delta(2.0));
+    let file_name = format!("imgs/{city}_dtr_trend{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first_year}-{last_year}  Diurnal Temperature Range ({})", unit.symbol());
+    yearchart::draw_year_chart_base(&dwg, &title_text, first_year, last_year, &y_scale)?;
+    yearchart::draw_year_line(&dwg, annual, first_year, last_year, &y_scale, DTR_COLOR.mix(0.5).stroke_width(1))?;
+    let mut legend = Vec::new();
+    if smoothing_years > 1 {
+        let smoothed = moving_average(annual, smoothing_years);
+        yearchart::draw_year_line(&dwg, &smoothed, first_year, last_year, &y_scale, DTR_COLOR.stroke_width(3))?;
+    }
+    match linear_fit(annual) {
+        Some((slope, intercept)) => {
+            let (start, end) = (annual[0].0, annual[annual.len() - 1].0);
+            let line: Vec<(i32, i32)> = [start, end].iter()
+                .map(|year| (year_x(f64::from(*year), first_year, last_year), value_y(slope * f64::from(*year) + intercept, &y_scale)))
+                .collect();
+            dwg.draw(&DashedPathElement::new(line, 12, 6, DTR_COLOR.stroke_width(2)))?;
+            legend.push((format!("Avg Hi - Avg Low  trend {:+.2} {} per decade ({} years)", slope * 10.0, unit.symbol(), annual.len()), DTR_COLOR));
+        },
+        None => legend.push(("Avg Hi - Avg Low  not enough years for a trend".to_string(), DTR_COLOR)),
+    }
+    if smoothing_years > 1 {
+        legend.push((format!("Thick line: {smoothing_years} year centered moving average"), RGBColor(255, 255, 255)));
+    }
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+/// Every city's smoothed annual DTR (°F) on one chart with its trend in the legend
+pub fn draw_dtr_comparison(cities: &[(String, Vec<(i32, f64)>)], smoothing_years: i32, unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let cities: Vec<(String, Vec<(i32, f64)>)> = cities.iter()
+        .map(|(city, annual)| (city.clone(), annual.iter().map(|(year, v)| (*year, unit.delta(*v))).collect()))
+        .collect();
+    let years = cities.iter().flat_map(|(_, annual)| annual.iter().map(|(year, _)| *year));
+    let (first_year, last_year) = years.fold((i32::MAX, i32::MIN), |(first, last), year| (first.min(year), last.max(year)));
+    if first_year > last_year {
+        println!("No city has a complete year, no DTR comparison drawn");
+        return Ok(());
+    }
+    let lines: Vec<Vec<(i32, f64)>> = cities.iter()
+        .map(|(_, annual)| if smoothing_years > 1 { moving_average(annual, smoothing_years) } else { annual.clone() })
+        .collect();
+    let y_scale = yearchart::value_scale(lines.iter().flatten().map(|(_, v)| *v), unit.delta(2.0));
+    let file_name = format!("imgs/cities_dtr{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{first_year}-{last_year}  Diurnal Temperature Range by City ({})", unit.symbol());
+    yearchart::draw_year_chart_base(&dwg, &title_text, first_year, last_year, &y_scale)?;
+    let mut legend = Vec::new();
+    for (idx, ((city, annual), line)) in cities.iter().zip(&lines).enumerate() {
+        let color = CITY_COLORS[idx % CITY_COLORS.len()];
+        yearchart::draw_year_line(&dwg, line, first_year, last_year, &y_scale, color.stroke_width(2))?;
+        match linear_fit(annual) {
+            Some((slope, _)) => legend.push((format!("{city}  {:+.2} {} per decade ({} years)", slope * 10.0, unit.symbol(), annual.len()), color)),
+            None => legend.push((format!("{city}  not enough years for a trend"), color)),
+        }
+    }
+    if smoothing_years > 1 {
+        legend.push((format!("Lines are {smoothing_years} year centered moving averages"), RGBColor(255, 255, 255)));
+    }
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temps(tmax: &[Option<f64>], tmin: &[Option<f64>]) -> BucketTemps {
+        BucketTemps { tmax: tmax.to_vec(), tmin: tmin.to_vec(), tmean: vec![None; tmax.len()] }
+    }
+
+    #[test]
+    fn daily_range_needs_both_readings() {
+        let date = |dom| NaiveDate::from_ymd_opt(2001, 7, dom).unwrap();
+        let days = [
+            DailyTemp { date: date(1), tmax: Some(90), tmin: Some(65), tavg: None },
+            DailyTemp { date: date(2), tmax: Some(88), tmin: None, tavg: None },
+            DailyTemp { date: date(3), tmax: Some(40), tmin: Some(45), tavg: None }, // kept as it is, a front can do that
+        ];
+        assert_eq!(daily_dtr(&days), vec![(date(1), 25), (date(3), -5)]);
+    }
+
+    #[test]
+    fn years_missing_a_bucket_are_left_out() {
+        let years = BTreeMap::from([
+            (2000, temps(&[Some(50.0), Some(70.0)], &[Some(30.0), Some(40.0)])),
+            (2001, temps(&[Some(50.0), Some(70.0)], &[Some(30.0), None])),
+            (2002, temps(&[Some(60.0), Some(80.0)], &[Some(45.0), Some(55.0)])),
+        ]);
+        assert_eq!(bucket_dtr(&years[&2001]), vec![Some(20.0), None]);
+        assert_eq!(annual_dtr(&years, 2000, 2002), vec![(2000, 25.0), (2002, 20.0)]);
+        assert_eq!(annual_dtr(&years, 2001, 2001), vec![]);
+    }
+}
@@ -0,0 +1,187 @@
+// Period averages with what's needed to use them away from this program, for spreadsheets and notebooks.
+// The csv and the json have the same fields in the same order. Fields are only ever added at the end,
+// SCHEMA is the version in the json and changes when they do.
+//   city            table name of the city, ex. Los_Angeles_CA
+//   period          Week, Fort, Month, Week53, IsoWeek, Fort27 or Season (see calendar.rs)
+//   station         GHCN id(s) of the station(s) behind the bucket's daily rows, ";" between them when it changed
+//   year            year the bucket counts toward, the ISO year for IsoWeek and the January's year for Season 1 (DJF)
+//   bucket          1 based, the tweek / tfort / tmonth / tseason of the period table
+//   start_date      first day of the bucket, yyyy-mm-dd
+//   end_date        last day of the bucket
+//   unit            F, C or K
+//   tmax            period table avg hi in unit, empty in the csv and null in the json when missing
+//   tmin            avg low, same
+//   tmean           avg mean, same. Missing everywhere unless exported from the _agg tables aggregate builds
+//   tmean_source    R every day a reported TAVG, C every day (tmax + tmin) / 2, M a mix, missing with tmean
+//   tmax_days       days in the bucket with a tmax in the daily table (qc flagged readings included)
+//   tmin_days       days with a tmin
+//   tmean_days      days with a TAVG or both tmax and tmin
+//   calendar_days   days from start_date to end_date
+// A bucket is exported when its period table has a row for it.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use chrono::NaiveDate;
+use sqlx::{MySql, Pool, Row};
+
+use crate::aggregate::{self, BucketSums};
+use crate::calendar;
+use crate::daily;
+use crate::import;
+use crate::periods;
+use crate::units::Unit;
+
+pub const SCHEMA: &str = "weather3.period_averages.1";
+
+const CSV_HEADER: &str = "city,period,station,year,bucket,start_date,end_date,unit,tmax,tmin,tmean,tmean_source,tmax_days,tmin_days,tmean_days,calendar_days";
+
+#[derive(Clone, Debug)]
+pub struct ExportRow {
+    pub city: String,
+    pub period: String,
+    pub station: String,
+    pub year: i32,
+    pub bucket: usize,
+    pub range: Option<(NaiveDate, NaiveDate)>,
+    pub tmax: Option<f64>, // °F, converted when written
+    pub tmin: Option<f64>,
+    pub tmean: Option<f64>,
+    pub tmean_source: Option<String>,
+    pub tmax_days: i32,
+    pub tmin_days: i32,
+    pub tmean_days: i32,
+}
+
+impl ExportRow {
+    pub fn calendar_days(&self) -> Option<i64> {
+        self.range.map(|(start, end)| (end - start).num_days() + 1)
+    }
+}
+
+/// Every bucket of the city's period table from first_year to last_year, with day counts and stations from the daily table.
+/// aggregated reads the {city}_{period}_agg table rebuilt by aggregate instead of the original
+pub async fn export_rows(pool: &Pool<MySql>, city: &str, period: &str, aggregated: bool, first_year: i32, last_year: i32) -> Result<Vec<ExportRow>, sqlx::Error> {
+    let tperiod = periods::period_column(period);
+    let table = if aggregated { aggregate::agg_table(city, period) } else { format!("{city}_{period}") };
+    let table_rows = periods::get_all_temps(pool, tperiod, &table).await?;
+    let daily_rows = import::get_daily_rows(pool, city).await?;
+    let days = import::to_daily_temps(&daily_rows);
+    let counts: Vec<BTreeMap<i32, BucketSums>> = ["tmax", "tmin", "tmean"].iter()
+        .map(|series| aggregate::sum_by_bucket(period, days.iter().filter_map(|d| Some((d.date, d.value(series)?)))))
+        .collect();
+    let mut stations: BTreeMap<(i32, usize), BTreeSet<String>> = BTreeMap::new();
+    for row in daily_rows.iter().filter(|r| !r.station.is_empty()) {
+        let Some(key) = daily::parse_tdate(&row.tdate).and_then(|date| calendar::date_bucket(period, &date)) else { continue; };
+        stations.entry(key).or_default().insert(row.station.clone());
+    }
+
+    let mut rows = Vec::new();
+    for row in &table_rows {
+        let year: i32 = row.get("tyear");
+        let bucket: i32 = row.get(tperiod);
+        if year < first_year || year > last_year || bucket < 1 || bucket as usize > calendar::bucket_count(period) {
+            continue;
+        }
+        let bucket = bucket as usize;
+        let day_count = |series: usize| counts[series].get(&year).map(|sums| sums.days[bucket - 1]).unwrap_or(0);
+        let station = match stations.get(&(year, bucket)) {
+            Some(ids) => ids.iter().cloned().collect::<Vec<String>>().join(";"),
+            None => row.try_get::<String, _>("station").map(|s| s.trim().to_string()).unwrap_or_default(),
+        };
+        rows.push(ExportRow {
+            city: city.to_string(),
+            period: period.to_string(),
+            station,
+            year,
+            bucket,
+            range: calendar::bucket_range(period, year, bucket),
+            tmax: row.try_get::<i32, _>("tmax").ok().map(f64::from),
+            tmin: row.try_get::<i32, _>("tmin").ok().map(f64::from),
+            tmean: row.try_get::<i32, _>("tmean").ok().map(f64::from),
+            tmean_source: row.try_get::<String, _>("tmean_source").ok(),
+            tmax_days: day_count(0),
+            tmin_days: day_count(1),
+            tmean_days: day_count(2),
+        });
+    }
+    Ok(rows)
+}
+
+// the field values in schema order, None for missing. Strings are unquoted
+fn fields(row: &ExportRow, unit: Unit) -> [Option<String>; 16] {
+    let date = |d: Option<NaiveDate>| d.map(|d| d.format("%Y-%m-%d").to_string());
+    let temp = |t: Option<f64>| t.map(|t| unit.format_temp(t, 0));
+    [
+        Some(row.city.clone()),
+        Some(row.period.clone()),
+        Some(row.station.clone()),
+        Some(row.year.to_string()),
+        Some(row.bucket.to_string()),
+        date(row.range.map(|(start, _)| start)),
+        date(row.range.map(|(_, end)| end)),
+        Some(unit.code().to_string()),
+        temp(row.tmax),
+        temp(row.tmin),
+        temp(row.tmean),
+        row.tmean_source.clone(),
+        Some(row.tmax_days.to_string()),
+        Some(row.tmin_days.to_string()),
+        Some(row.tmean_days.to_string()),
+        row.calendar_days().map(|d| d.to_string()),
+    ]
+}
+
+// fields that are json strings, the rest are numbers
+const STRING_FIELDS: [usize; 7] = [0, 1, 2, 5, 6, 7, 11];
+
+/// reports/{name}_period_averages.csv, name is the city or "cities"
+pub fn write_export_csv(name: &str, rows: &[ExportRow], unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{name}_period_averages{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "{CSV_HEADER}")?;
+    for row in rows {
+        let values: Vec<String> = fields(row, unit).into_iter().map(|f| f.unwrap_or_default()).collect();
+        writeln!(out, "{}", values.join(","))?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+/// reports/{name}_period_averages.json, {"schema": SCHEMA, "rows": [{field: value, ...}, ...]}
+pub fn write_export_json(name: &str, rows: &[ExportRow], unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{name}_period_averages{}.json", unit.file_suffix());
+    let names: Vec<&str> = CSV_HEADER.split(',').collect();
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "{{\"schema\": {}, \"rows\": [", json_string(SCHEMA))?;
+    for (n, row) in rows.iter().enumerate() {
+        let members: Vec<String> = fields(row, unit).into_iter().enumerate().map(|(idx, value)| {
+            let value = match value {
+                Some(v) if STRING_FIELDS.contains(&idx) => json_string(&v),
+                Some(v) => v,
+                None => "null".to_string(),
+            };
+            format!("{}: {value}", json_string(names[idx]))
+        }).collect();
+        writeln!(out, "  {{{}}}{}", members.join(", "), if n + 1 < rows.len() { "," } else { "" })?;
+    }
+    writeln!(out, "]}}")?;
+    out.flush()?;
+    Ok(file_name)
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,252 @@
+// Estimates for missing daily readings, kept in {city}_fill apart from the daily table so every estimate
+// can be shown or left out. The provenance column says how each value was made:
+//   "interpolated" - straight line between the readings either side of a short gap
+//   "regression"   - a neighbor city's reading that day through a per calendar month least squares fit
+//                    of this city on the neighbor, source names the neighbor
+use std::collections::{BTreeMap, BTreeSet};
+use chrono::{Datelike, NaiveDate};
+use sqlx::{MySql, Pool, Row};
+
+use crate::daily::{self, DailyTemp};
+use crate::trend::linear_fit;
+
+const MIN_OVERLAP_DAYS: usize = 60; // days both cities read in a calendar month before the fit is trusted
+
+type MonthFits = BTreeMap<u32, (f64, f64, f64)>; // calendar month -> (slope, intercept, r squared)
+
+#[derive(Clone, Debug)]
+pub struct Fill {
+    pub date: NaiveDate,
+    pub series: String, // "tmax" or "tmin"
+    pub value: i32,
+    pub provenance: String,
+    pub source: String, // neighbor city for regression, empty for interpolation
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FillOptions {
+    pub max_interp_days: i64, // longest gap filled by interpolation
+    pub min_r2: f64,          // neighbor fits explaining less of the variance than this aren't used
+}
+
+// one value per date, the first row wins when a date is duplicated
+fn series_by_date(days: &[DailyTemp], series: &str) -> BTreeMap<NaiveDate, i32> {
+    let mut values = BTreeMap::new();
+    for day in days {
+        if let Some(value) = if series == "tmax" { day.tmax } else { day.tmin } {
+            values.entry(day.date).or_insert(value);
+        }
+    }
+    values
+}
+
+/// Linear interpolation across every gap of 1..=max_days missing days with readings on both sides
+pub fn interpolate_gaps(days: &[DailyTemp], series: &str, max_days: i64) -> Vec<Fill> {
+    let values: Vec<(NaiveDate, i32)> = series_by_date(days, series).into_iter().collect();
+    let mut fills = Vec::new();
+    for pair in values.windows(2) {
+        let ((before, from), (after, to)) = (pair[0], pair[1]);
+        let span = (after - before).num_days();
+        if span < 2 || span - 1 > max_days {
+            continue;
+        }
+        for (step, date) in before.iter_days().skip(1).take(span as usize - 1).enumerate() {
+            let value = f64::from(from) + f64::from(to - from) * (step + 1) as f64 / span as f64;
+            fills.push(Fill { date, series: series.to_string(), value: value.round() as i32, provenance: "interpolated".to_string(), source: String::new() });
+        }
+    }
+    fills
+}
+
+/// (slope, intercept, r squared) of this city on a neighbor for each calendar month
+fn monthly_fits(target: &BTreeMap<NaiveDate, i32>, neighbor: &BTreeMap<NaiveDate, i32>) -> MonthFits {
+    let mut pairs: BTreeMap<u32, Vec<(i32, f64)>> = BTreeMap::new();
+    for (date, value) in target {
+        if let Some(other) = neighbor.get(date) {
+            pairs.entry(date.month()).or_default().push((*other, f64::from(*value)));
+        }
+    }
+    pairs.into_iter().filter(|(_, points)| points.len() >= MIN_OVERLAP_DAYS).filter_map(|(month, points)| {
+        let (slope, intercept) = linear_fit(&points)?;
+        let mean = points.iter().map(|(_, y)| y).sum::<f64>() / points.len() as f64;
+        let total: f64 = points.iter().map(|(_, y)| (y - mean).powi(2)).sum();
+        let residual: f64 = points.iter().map(|(x, y)| (y - (slope * f64::from(*x) + intercept)).powi(2)).sum();
+        let r2 = if total == 0.0 { 0.0 } else { 1.0 - residual / total };
+        Some((month, (slope, intercept, r2)))
+    }).collect()
+}
+
+/// Every missing day between the first and last reading not already in `filled`, from the nearest
+/// neighbor that read that day and fits well enough. Neighbors are (city, daily temps), nearest first
+pub fn regression_fills(days: &[DailyTemp], series: &str, neighbors: &[(String, Vec<DailyTemp>)], filled: &BTreeSet<NaiveDate>, min_r2: f64) -> Vec<Fill> {
+    let target = series_by_date(days, series);
+    let (Some(first), Some(last)) = (target.keys().next().copied(), target.keys().last().copied()) else {
+        return Vec::new();
+    };
+    let neighbor_fits: Vec<(&String, BTreeMap<NaiveDate, i32>, MonthFits)> = neighbors.iter().map(|(city, neighbor_days)| {
+        let values = series_by_date(neighbor_days, series);
+        let fits = monthly_fits(&target, &values);
+        (city, values, fits)
+    }).collect();
+    let mut fills = Vec::new();
+    for date in first.iter_days().take_while(|d| *d <= last) {
+        if target.contains_key(&date) || filled.contains(&date) {
+            continue;
+        }
+        let estimate = neighbor_fits.iter().find_map(|(city, values, fits)| {
+            let (slope, intercept, r2) = fits.get(&date.month())?;
+            if *r2 < min_r2 {
+                return None;
+            }
+            let other = values.get(&date)?;
+            Some(((slope * f64::from(*other) + intercept).round() as i32, city.to_string()))
+        });
+        if let Some((value, source)) = estimate {
+            fills.push(Fill { date, series: series.to_string(), value, provenance: "regression".to_string(), source });
+        }
+    }
+    fills
+}
+
+/// Interpolation for short gaps, then neighbors for whatever is left, both series
+pub fn compute_fills(days: &[DailyTemp], neighbors: &[(String, Vec<DailyTemp>)], options: &FillOptions) -> Vec<Fill> {
+    let mut fills = Vec::new();
+    for series in ["tmax", "tmin"] {
+        let interpolated = interpolate_gaps(days, series, options.max_interp_days);
+        let filled: BTreeSet<NaiveDate> = interpolated.iter().map(|f| f.date).collect();
+        fills.extend(interpolated);
+        fills.extend(regression_fills(days, series, neighbors, &filled, options.min_r2));
+    }
+    fills.sort_by(|a, b| (a.date, &a.series).cmp(&(b.date, &b.series)));
+    fills
+}
+
+pub async fn create_fill_table(pool: &Pool<MySql>, city: &str) -> Result<(), sqlx::Error> {
+    let create_stmt = format!(r#"CREATE TABLE if NOT exists `{city}_fill` (
+  `id` int(11) NOT NULL,
+  `tdate` char(10) NOT NULL,
+  `series` char(4) NOT NULL,
+  `value` smallint(6) NOT NULL,
+  `provenance` varchar(12) NOT NULL,
+  `source` varchar(40) DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `tdate` (`tdate`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#);
+    let _result = sqlx::query(&create_stmt).execute(pool).await?;
+    Ok(())
+}
+
+// replaces every fill for the city
+pub async fn store_fills(pool: &Pool<MySql>, city: &str, fills: &[Fill]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!("DELETE FROM `{city}_fill`")).execute(&mut *tx).await?;
+    let insert_stmt = format!("INSERT INTO `{city}_fill` (id, tdate, series, value, provenance, source) VALUES (?, ?, ?, ?, ?, ?)");
+    for (idx, fill) in fills.iter().enumerate() {
+        sqlx::query(&insert_stmt)
+            .bind(idx as i32 + 1)
+            .bind(fill.date.format("%Y-%m-%d").to_string())
+            .bind(&fill.series)
+            .bind(fill.value)
+            .bind(&fill.provenance)
+            .bind((!fill.source.is_empty()).then_some(&fill.source))
+            .execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_fills(pool: &Pool<MySql>, city: &str) -> Result<Vec<Fill>, sqlx::Error> {
+    let query_stmt_string = format!("SELECT tdate, series, value, provenance, source FROM {city}_fill ORDER BY tdate");
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_stmt_string)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().filter_map(|row| {
+        let tdate: &str = row.get("tdate");
+        Some(Fill {
+            date: daily::parse_tdate(tdate)?,
+            series: row.get("series"),
+            value: row.get("value"),
+            provenance: row.get("provenance"),
+            source: row.try_get::<String, _>("source").unwrap_or_default(),
+        })
+    }).collect())
+}
+
+/// Days with every fill put in its empty slot, readings are never overwritten. Dates with no row get one
+pub fn apply_fills(days: &[DailyTemp], fills: &[Fill]) -> Vec<DailyTemp> {
+    let mut by_date: BTreeMap<NaiveDate, Vec<DailyTemp>> = BTreeMap::new();
+    for day in days {
+        by_date.entry(day.date).or_default().push(day.clone());
+    }
+    for fill in fills {
+        let rows = by_date.entry(fill.date).or_insert_with(|| vec![DailyTemp { date: fill.date, tmax: None, tmin: None, tavg: None }]);
+        for row in rows.iter_mut() {
+            let slot = if fill.series == "tmax" { &mut row.tmax } else { &mut row.tmin };
+            slot.get_or_insert(fill.value);
+        }
+    }
+    by_date.into_values().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, dom: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, dom).unwrap()
+    }
+
+    fn day(date: NaiveDate, tmax: Option<i32>) -> DailyTemp {
+        DailyTemp { date, tmax, tmin: None, tavg: None }
+    }
+
+    fn filled(fills: &[Fill]) -> Vec<(NaiveDate, i32)> {
+        fills.iter().map(|f| (f.date, f.value)).collect()
+    }
+
+    #[test]
+    fn interpolation_stops_at_max_days() {
+        let days = [
+            day(date(2001, 3, 1), Some(40)), day(date(2001, 3, 4), Some(49)), // 2 missing days
+            day(date(2001, 3, 8), Some(60)),                                   // 3 missing days, over the limit
+            day(date(2001, 3, 9), None), day(date(2001, 3, 10), Some(61)),     // a row without a reading is still a gap
+        ];
+        let fills = interpolate_gaps(&days, "tmax", 2);
+        assert_eq!(filled(&fills), vec![(date(2001, 3, 2), 43), (date(2001, 3, 3), 46), (date(2001, 3, 9), 61)]); // 60.5 rounds up
+        assert!(fills.iter().all(|f| f.provenance == "interpolated" && f.source.is_empty()));
+        assert!(interpolate_gaps(&days, "tmax", 0).is_empty());
+    }
+
+    // every January day of 2000-2002, target = 2 * neighbor - 10, noisy has nothing to do with either
+    fn januaries(value: impl Fn(NaiveDate) -> i32) -> Vec<DailyTemp> {
+        (2000..=2002).flat_map(|year| date(year, 1, 1).iter_days().take(31)).map(|d| day(d, Some(value(d)))).collect()
+    }
+
+    #[test]
+    fn regression_uses_the_first_neighbor_that_fits() {
+        let neighbor = |d: NaiveDate| 20 + (d.day() % 9) as i32 + (d.year() - 2000) * 3;
+        let mut target = januaries(|d| 2 * neighbor(d) - 10);
+        target.retain(|d| !(date(2001, 1, 10)..=date(2001, 1, 14)).contains(&d.date));
+        let neighbors = vec![
+            ("Noisy".to_string(), januaries(|d| if d.day() % 2 == 0 { 30 } else { 70 })),
+            ("Good".to_string(), januaries(neighbor)),
+        ];
+        let already = BTreeSet::from([date(2001, 1, 10)]);
+        let fills = regression_fills(&target, "tmax", &neighbors, &already, 0.9);
+        let expected: Vec<(NaiveDate, i32)> = date(2001, 1, 11).iter_days().take(4).map(|d| (d, 2 * neighbor(d) - 10)).collect();
+        assert_eq!(filled(&fills), expected);
+        assert!(fills.iter().all(|f| f.provenance == "regression" && f.source == "Good"));
+        // under 60 days of overlap in a month nothing is trusted
+        let short: Vec<DailyTemp> = neighbors[1].1.iter().filter(|d| d.date.year() == 2001).cloned().collect();
+        assert!(regression_fills(&target, "tmax", &[("Short".to_string(), short)], &already, 0.0).is_empty());
+    }
+
+    #[test]
+    fn fills_never_overwrite_a_reading() {
+        let days = [day(date(2001, 5, 1), Some(70)), day(date(2001, 5, 1), None)];
+        let fill = |date, value| Fill { date, series: "tmax".to_string(), value, provenance: "interpolated".to_string(), source: String::new() };
+        let applied = apply_fills(&days, &[fill(date(2001, 5, 1), 99), fill(date(2001, 5, 2), 71)]);
+        let values: Vec<(NaiveDate, Option<i32>)> = applied.iter().map(|d| (d.date, d.tmax)).collect();
+        assert_eq!(values, vec![(date(2001, 5, 1), Some(70)), (date(2001, 5, 1), Some(99)), (date(2001, 5, 2), Some(71))]);
+    }
+}
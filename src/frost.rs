@@ -0,0 +1,209 @@
+// Last spring freeze, first fall freeze and growing season length per year from daily tmin.
+// Spring is Jan 1 - Jun 30 and fall is Jul 1 - Dec 31, every city in the project is in the northern hemisphere.
+// A date that a missing day could have changed is reported as undetermined rather than guessed.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use chrono::{Datelike, NaiveDate};
+use plotters::prelude::*;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT, BOTTOM_LINE_Y};
+use crate::daily::DailyTemp;
+use crate::trend::linear_fit;
+use crate::units::Unit;
+use crate::yearchart::{self, value_y, year_x};
+
+const SPRING_COLOR: RGBColor = RGBColor(30, 80, 200);
+const FALL_COLOR: RGBColor = RGBColor(220, 120, 0);
+const UNDETERMINED_COLOR: RGBColor = RGBColor(150, 150, 150);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrostDate {
+    Date(NaiveDate),
+    NoFreeze,     // every day of the half year was read and none reached the threshold
+    Undetermined, // missing days could hide the real date
+}
+
+impl FrostDate {
+    fn csv(&self) -> String {
+        match self {
+            FrostDate::Date(date) => date.format("%Y-%m-%d").to_string(),
+            FrostDate::NoFreeze => "none".to_string(),
+            FrostDate::Undetermined => "undetermined".to_string(),
+        }
+    }
+
+    fn day_of_year(&self) -> Option<f64> {
+        match self {
+            FrostDate::Date(date) => Some(f64::from(date.ordinal())),
+            _ => None,
+        }
+    }
+}
+
+pub struct FrostYear {
+    pub year: i32,
+    pub last_spring: FrostDate,
+    pub first_fall: FrostDate,
+    pub season_days: Option<i64>, // days between the two freezes, only when both dates are known
+}
+
+pub fn frost_years(days: &[DailyTemp], threshold: i32, first_year: i32, last_year: i32) -> Vec<FrostYear> {
+    let tmins: BTreeMap<NaiveDate, i32> = days.iter().filter_map(|d| Some((d.date, d.tmin?))).collect();
+    (first_year..=last_year).filter_map(|year| {
+        let jan_1 = NaiveDate::from_ymd_opt(year, 1, 1)?;
+        let jun_30 = NaiveDate::from_ymd_opt(year, 6, 30)?;
+        let jul_1 = NaiveDate::from_ymd_opt(year, 7, 1)?;
+        let dec_31 = NaiveDate::from_ymd_opt(year, 12, 31)?;
+        tmins.range(jan_1..=dec_31).next()?; // no readings at all, nothing to report
+        // walk back from Jun 30 for spring and forward from Jul 1 for fall, a gap before a freeze is found spoils the date
+        let last_spring = find_freeze(&tmins, std::iter::successors(Some(jun_30), |d| d.pred_opt()).take_while(|d| *d >= jan_1), threshold);
+        let first_fall = find_freeze(&tmins, jul_1.iter_days().take_while(|d| *d <= dec_31), threshold);
+        let season_days = match (last_spring, first_fall) {
+            (FrostDate::Date(spring), FrostDate::Date(fall)) => Some((fall - spring).num_days() - 1),
+            _ => None,
+        };
+        Some(FrostYear { year, last_spring, first_fall, season_days })
+    }).collect()
+}
+
+fn find_freeze(tmins: &BTreeMap<NaiveDate, i32>, dates: impl Iterator<Item = NaiveDate>, threshold: i32) -> FrostDate {
+    for date in dates {
+        match tmins.get(&date) {
+            Some(tmin) if *tmin <= threshold => return FrostDate::Date(date),
+            Some(_) => continue,
+            None => return FrostDate::Undetermined,
+        }
+    }
+    FrostDate::NoFreeze
+}
+
+pub fn write_frost_csv(city: &str, by_threshold: &[(i32, Vec<FrostYear>)], unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_frost_dates{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "threshold,year,last_spring_freeze,first_fall_freeze,growing_season_days")?;
+    for (threshold, years) in by_threshold {
+        let threshold = unit.format_temp(f64::from(*threshold), 0);
+        for fy in years {
+            let season = fy.season_days.map(|d| d.to_string()).unwrap_or_default();
+            writeln!(out, "{threshold},{},{},{},{season}", fy.year, fy.last_spring.csv(), fy.first_fall.csv())?;
+        }
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+/// Day of year of both freezes for every year, with undetermined years marked along the bottom,
+/// and a second chart of the growing season length with its trend
+pub fn draw_frost_dates(city: &str, threshold: i32, years: &[FrostYear], unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some(first), Some(last)) = (years.first().map(|y| y.year), years.last().map(|y| y.year)) else {
+        return Ok(());
+    };
+    let spring: Vec<(i32, f64)> = years.iter().filter_map(|y| Some((y.year, y.last_spring.day_of_year()?))).collect();
+    let fall: Vec<(i32, f64)> = years.iter().filter_map(|y| Some((y.year, y.first_fall.day_of_year()?))).collect();
+    let y_scale = yearchart::value_scale(spring.iter().chain(&fall).map(|(_, d)| *d), 10.0);
+
+    let file_name = format!("imgs/{city}_frost_dates_{threshold}{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first}-{last}  Freeze Dates (tmin <= {}), Day of Year", unit.temp_text(f64::from(threshold)));
+    yearchart::draw_year_chart_base(&dwg, &title_text, first, last, &y_scale)?;
+    yearchart::draw_year_line(&dwg, &spring, first, last, &y_scale, SPRING_COLOR.stroke_width(2))?;
+    yearchart::draw_year_line(&dwg, &fall, first, last, &y_scale, FALL_COLOR.stroke_width(2))?;
+    for fy in years {
+        let x = year_x(f64::from(fy.year), first, last);
+        for (date, color) in [(fy.last_spring, SPRING_COLOR), (fy.first_fall, FALL_COLOR)] {
+            match date {
+                FrostDate::Date(_) => {
+                    let y = value_y(date.day_of_year().unwrap_or_default(), &y_scale);
+                    dwg.draw(&Circle::new((x, y), 3, color.filled()))?;
+                },
+                FrostDate::Undetermined => { // tick under the plot so the hole is visible
+                    dwg.draw(&Cross::new((x, BOTTOM_LINE_Y - 8), 4, UNDETERMINED_COLOR.stroke_width(2)))?;
+                },
+                FrostDate::NoFreeze => {},
+            }
+        }
+    }
+    let legend = vec![
+        ("Last spring freeze".to_string(), SPRING_COLOR),
+        ("First fall freeze".to_string(), FALL_COLOR),
+        ("x: a date couldn't be determined, days are missing".to_string(), UNDETERMINED_COLOR),
+    ];
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+
+    let seasons: Vec<(i32, f64)> = years.iter().filter_map(|y| Some((y.year, y.season_days? as f64))).collect();
+    if seasons.is_empty() {
+        return Ok(());
+    }
+    let y_scale = yearchart::value_scale(seasons.iter().map(|(_, d)| *d), 10.0);
+    let file_name = format!("imgs/{city}_growing_season_{threshold}{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first}-{last}  Growing Season Days (tmin <= {})", unit.temp_text(f64::from(threshold)));
+    yearchart::draw_year_chart_base(&dwg, &title_text, first, last, &y_scale)?;
+    yearchart::draw_year_line(&dwg, &seasons, first, last, &y_scale, RGBColor(0, 130, 60).stroke_width(2))?;
+    let mut legend = vec![("Days between last spring and first fall freeze".to_string(), RGBColor(0, 130, 60))];
+    if let Some((slope, intercept)) = linear_fit(&seasons) {
+        let (start, end) = (seasons[0].0, seasons[seasons.len() - 1].0);
+        let line: Vec<(i32, i32)> = [start, end].iter()
+            .map(|year| (year_x(f64::from(*year), first, last), value_y(slope * f64::from(*year) + intercept, &y_scale)))
+            .collect();
+        dwg.draw(&PathElement::new(line, BLACK.stroke_width(2)))?;
+        legend.push((format!("Trend {:+.1} days per decade", slope * 10.0), BLACK));
+    }
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, dom: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, dom).unwrap()
+    }
+
+    // every day of the year at 50 except the freezes, leaving out the days in `missing`
+    fn year_of_days(year: i32, freezes: &[NaiveDate], missing: &[NaiveDate]) -> Vec<DailyTemp> {
+        date(year, 1, 1).iter_days().take_while(|d| d.year() == year)
+            .filter(|d| !missing.contains(d))
+            .map(|d| DailyTemp { date: d, tmax: Some(60), tmin: Some(if freezes.contains(&d) { 30 } else { 50 }), tavg: None })
+            .collect()
+    }
+
+    #[test]
+    fn freezes_and_season_length() {
+        let days = year_of_days(2001, &[date(2001, 2, 1), date(2001, 3, 10), date(2001, 11, 5), date(2001, 12, 1)], &[]);
+        let years = frost_years(&days, 32, 2001, 2001);
+        assert_eq!(years[0].last_spring, FrostDate::Date(date(2001, 3, 10)));
+        assert_eq!(years[0].first_fall, FrostDate::Date(date(2001, 11, 5)));
+        assert_eq!(years[0].season_days, Some(239)); // Mar 11 through Nov 4
+    }
+
+    #[test]
+    fn gap_before_the_freeze_is_undetermined() {
+        // spring is read back from Jun 30, so a gap in May hides whether there was a later freeze
+        let days = year_of_days(2001, &[date(2001, 3, 10), date(2001, 11, 5)], &[date(2001, 5, 1), date(2001, 12, 1)]);
+        let years = frost_years(&days, 32, 2001, 2001);
+        assert_eq!(years[0].last_spring, FrostDate::Undetermined);
+        // the December gap is after the first fall freeze, it can't change it
+        assert_eq!(years[0].first_fall, FrostDate::Date(date(2001, 11, 5)));
+        assert_eq!(years[0].season_days, None);
+    }
+
+    #[test]
+    fn no_freeze_and_years_without_readings() {
+        let days = year_of_days(2001, &[], &[]);
+        let years = frost_years(&days, 32, 2000, 2002);
+        assert_eq!(years.len(), 1);
+        assert_eq!((years[0].year, years[0].last_spring, years[0].first_fall), (2001, FrostDate::NoFreeze, FrostDate::NoFreeze));
+        // the threshold itself counts as a freeze
+        assert_eq!(frost_years(&days, 50, 2001, 2001)[0].last_spring, FrostDate::Date(date(2001, 6, 30)));
+    }
+}
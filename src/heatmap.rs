@@ -0,0 +1,202 @@
+// Whole record on one image: a heatmap with a row per year and a column per bucket,
+// and the one row "warming stripes" of annual means. Condenses a city's ~360 bar charts into two pictures.
+use std::collections::BTreeMap;
+use sqlx::{MySql, Pool};
+use plotters::prelude::*;
+use plotters::coord::Shift;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT, AXIS_WIDTH, AXIS_HEIGHT, TOP_MARGIN, LEFT_MARGIN, BOTTOM_LINE_Y,
+            draw_title, title_period};
+use crate::calendar;
+use crate::daily;
+use crate::periods::{self, BucketTemps};
+use crate::units::Unit;
+
+const LEGEND_SPACE: i32 = 90; // room at the right of the heatmap for the color legend
+const HEAT_WIDTH: i32 = AXIS_WIDTH - LEGEND_SPACE;
+const COOL_END: (f64, f64, f64) = (33.0, 102.0, 172.0);
+const MIDDLE: (f64, f64, f64) = (247.0, 247.0, 247.0);
+const WARM_END: (f64, f64, f64) = (178.0, 24.0, 43.0);
+const NO_DATA: RGBColor = RGBColor(200, 200, 200);
+const NOTE_SPACE: i32 = 30; // stripes start below a line explaining the colors
+
+#[allow(clippy::too_many_arguments)]
+pub async fn draw_heatmap(pool: &Pool<MySql>,
+                          city: &str,
+                          period: &str,
+                          tperiod: &str,
+                          first_year: i32,
+                          last_year: i32,
+                          value: &str,
+                          baseline: (i32, i32),
+                          unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let city_period = format!("{city}_{period}");
+    let buckets = periods::bucket_count(period);
+    let all_years = unit.years(&periods::temps_by_year(period, &periods::get_all_temps(pool, tperiod, &city_period).await?));
+    let years: BTreeMap<i32, BucketTemps> = all_years.range(first_year..=last_year).map(|(y, t)| (*y, t.clone())).collect();
+
+    // cells holds the value chosen for the color of every year/bucket
+    let (cells, value_text): (BTreeMap<i32, Vec<Option<f64>>>, String) = match value {
+        "tmax" | "tmin" | "tmean" => {
+            (years.iter().map(|(y, t)| (*y, t.series(value).to_vec())).collect(), format!("Avg {}", daily::series_text(value)))
+        },
+        "tmax_anomaly" | "tmin_anomaly" | "tmean_anomaly" => {
+            let series = value.trim_end_matches("_anomaly");
+            let base = periods::mean_temps(&all_years, buckets, baseline.0, baseline.1);
+            let cells = years.iter().map(|(y, t)| (*y, periods::departures(t, &base).series(series).to_vec())).collect();
+            (cells, format!("Avg {} vs {}-{}", daily::series_text(series), baseline.0, baseline.1))
+        },
+        _ => return Err(format!("Unknown heatmap value {value}").into()),
+    };
+    let Some((low, high)) = color_range(cells.values().flatten().flatten().copied(), value.ends_with("anomaly")) else {
+        println!("No {period} data for {city} {first_year}-{last_year}, no heatmap drawn");
+        return Ok(());
+    };
+
+    let file_name = format!("imgs/{city}_{period}_{value}_heatmap{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first_year}-{last_year}  {} {value_text} ({})", title_period(period), unit.symbol());
+    draw_title(&dwg, &title_text, ("sans-serif", 36).into_font().color(&BLACK))?;
+
+    let cell_width = f64::from(HEAT_WIDTH) / buckets as f64;
+    let cell_height = f64::from(AXIS_HEIGHT) / cells.len() as f64;
+    for (row, (year, values)) in cells.iter().enumerate() {
+        let y0 = TOP_MARGIN + (row as f64 * cell_height).round() as i32;
+        let y1 = TOP_MARGIN + ((row + 1) as f64 * cell_height).round() as i32;
+        for (col, cell) in values.iter().enumerate() {
+            let x0 = LEFT_MARGIN + (col as f64 * cell_width).round() as i32;
+            let x1 = LEFT_MARGIN + ((col + 1) as f64 * cell_width).round() as i32;
+            let color = match cell {
+                Some(v) => heat_color(*v, low, high),
+                None => NO_DATA,
+            };
+            dwg.draw(&Rectangle::new([(x0, y0), (x1, y1)], Into::<ShapeStyle>::into(color).filled()))?;
+        }
+        if year % 10 == 0 || row == 0 {
+            draw_year_label(&dwg, *year, y0 + (cell_height / 2.0).round() as i32)?;
+        }
+    }
+    draw_bucket_labels(&dwg, period, buckets, cell_width)?;
+    draw_color_legend(&dwg, low, high)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+// One stripe per year colored by the annual mean temperature compared to the mean of all those years. The mean
+// series when the table has it, (avg hi + avg low) / 2 for tables aggregate hasn't rebuilt
+pub async fn draw_stripes(pool: &Pool<MySql>,
+                          city: &str,
+                          period: &str,
+                          tperiod: &str,
+                          first_year: i32,
+                          last_year: i32,
+                          unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let city_period = format!("{city}_{period}");
+    let all_years = unit.years(&periods::temps_by_year(period, &periods::get_all_temps(pool, tperiod, &city_period).await?));
+    let has_mean = all_years.values().any(|t| t.tmean.iter().any(|v| v.is_some()));
+    let annual: BTreeMap<i32, Option<f64>> = if has_mean {
+        let means: BTreeMap<i32, f64> = periods::annual_series(&all_years, "tmean").into_iter().collect();
+        (first_year..=last_year).map(|year| (year, means.get(&year).copied())).collect()
+    } else {
+        periods::annual_means(&all_years).range(first_year..=last_year)
+            .map(|(year, (hi, lo))| (*year, hi.zip(*lo).map(|(h, l)| (h + l) / 2.0)))
+            .collect()
+    };
+    let Some(record_mean) = periods::mean(annual.values().flatten().copied()) else {
+        println!("No complete years of {period} data for {city}, no stripes drawn");
+        return Ok(());
+    };
+    let Some((low, high)) = color_range(annual.values().flatten().map(|v| v - record_mean), true) else {
+        return Ok(());
+    };
+
+    let file_name = format!("imgs/{city}_stripes{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first_year}-{last_year}  Annual Mean Temperature");
+    draw_title(&dwg, &title_text, ("sans-serif", 36).into_font().color(&BLACK))?;
+
+    let stripe_width = f64::from(AXIS_WIDTH) / f64::from(last_year - first_year + 1);
+    let label_style = ("sans-serif", 16).into_font().color(&BLACK);
+    for year in first_year..=last_year {
+        let col = year - first_year;
+        let x0 = LEFT_MARGIN + (f64::from(col) * stripe_width).round() as i32;
+        let x1 = LEFT_MARGIN + (f64::from(col + 1) * stripe_width).round() as i32;
+        let color = match annual.get(&year).copied().flatten() {
+            Some(v) => heat_color(v - record_mean, low, high),
+            None => NO_DATA, // missing or incomplete year
+        };
+        dwg.draw(&Rectangle::new([(x0, TOP_MARGIN + NOTE_SPACE), (x1, BOTTOM_LINE_Y)], Into::<ShapeStyle>::into(color).filled()))?;
+        if year % 10 == 0 {
+            dwg.draw_text(&year.to_string(), &label_style, (x0 - 15, BOTTOM_LINE_Y + 8))?;
+        }
+    }
+    let note = format!("Color: difference from the {first_year}-{last_year} mean of {record_mean:.1} {}, gray years are incomplete", unit.symbol());
+    dwg.draw_text(&note, &label_style, (LEFT_MARGIN, TOP_MARGIN + 4))?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+// Value range the colors are spread over, centered on zero for anomalies so white means "same as baseline"
+fn color_range(values: impl Iterator<Item = f64>, centered: bool) -> Option<(f64, f64)> {
+    let (low, high) = values.fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    if low > high {
+        return None;
+    }
+    if centered {
+        let biggest = low.abs().max(high.abs()).max(0.1);
+        Some((-biggest, biggest))
+    } else if low == high {
+        Some((low - 1.0, high + 1.0))
+    } else {
+        Some((low, high))
+    }
+}
+
+// Blue through white to red as value goes from low to high
+pub fn heat_color(value: f64, low: f64, high: f64) -> RGBColor {
+    let t = ((value - low) / (high - low)).clamp(0.0, 1.0);
+    let (from, to, frac) = if t < 0.5 { (COOL_END, MIDDLE, t * 2.0) } else { (MIDDLE, WARM_END, (t - 0.5) * 2.0) };
+    let mix = |a: f64, b: f64| (a + (b - a) * frac).round() as u8;
+    RGBColor(mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+pub fn draw_year_label(dwg: &DrawingArea<BitMapBackend, Shift>, year: i32, y_center: i32) -> Result<(), Box<dyn std::error::Error>> {
+    let label_style = ("sans-serif", 16).into_font().color(&BLACK);
+    let label = year.to_string();
+    let (label_width, label_height) = dwg.estimate_text_size(&label, &label_style)?;
+    dwg.draw_text(&label, &label_style, (LEFT_MARGIN - 10 - label_width as i32, y_center - label_height as i32 / 2))?;
+    Ok(())
+}
+
+fn draw_bucket_labels(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, buckets: usize, cell_width: f64) -> Result<(), Box<dyn std::error::Error>> {
+    let label_style = ("sans-serif", 14).into_font().color(&BLACK);
+    for col in 0..buckets {
+        let label = calendar::bucket_label(period, col + 1);
+        let (label_width, _) = dwg.estimate_text_size(&label, &label_style)?;
+        let x = LEFT_MARGIN + ((col as f64 + 0.5) * cell_width).round() as i32 - label_width as i32 / 2;
+        dwg.draw_text(&label, &label_style, (x, BOTTOM_LINE_Y + 8))?;
+    }
+    Ok(())
+}
+
+// Vertical color bar at the right with the high value on top
+fn draw_color_legend(dwg: &DrawingArea<BitMapBackend, Shift>, low: f64, high: f64) -> Result<(), Box<dyn std::error::Error>> {
+    let label_style = ("sans-serif", 14).into_font().color(&BLACK);
+    let x0 = LEFT_MARGIN + HEAT_WIDTH + 20;
+    let x1 = x0 + 20;
+    for step in 0..AXIS_HEIGHT {
+        let value = high - (high - low) * f64::from(step) / f64::from(AXIS_HEIGHT);
+        dwg.draw(&PathElement::new(
+            vec![(x0, TOP_MARGIN + step), (x1, TOP_MARGIN + step)],
+            Into::<ShapeStyle>::into(heat_color(value, low, high)).stroke_width(1),
+        ))?;
+    }
+    for (value, y) in [(high, TOP_MARGIN), ((high + low) / 2.0, TOP_MARGIN + AXIS_HEIGHT / 2), (low, BOTTOM_LINE_Y - 14)] {
+        dwg.draw_text(&format!("{value:.1}"), &label_style, (x1 + 4, y))?;
+    }
+    Ok(())
+}
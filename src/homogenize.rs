@@ -0,0 +1,289 @@
+// Breakpoints from station moves and instrument changes, found with the Standard Normal Homogeneity Test
+// (Alexandersson 1986) on annual mean anomalies from the {city}_month table. With neighbor cities from the
+// station registry the test runs on city minus the neighbors' mean, so a shift in the regional climate
+// cancels out and only this station's jumps are left. Without neighbors it runs on the city alone and a
+// real climate shift can look like a break, the report says which was used.
+// Adjusted values go in {city}_week_adj / _fort_adj / _month_adj, the raw tables are never touched.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use sqlx::{MySql, Pool};
+use plotters::prelude::*;
+use plotters::element::DashedPathElement;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT, TOP_MARGIN, BOTTOM_LINE_Y};
+use crate::periods::{self, BucketTemps};
+use crate::units::Unit;
+use crate::yearchart::{self, year_x};
+
+const HI_COLOR: RGBColor = RGBColor(200, 30, 30);
+const LOW_COLOR: RGBColor = RGBColor(30, 80, 200);
+const BREAK_COLOR: RGBColor = RGBColor(90, 90, 90);
+
+type YearSeries = Vec<(i32, f64)>;
+
+// approximate 95% critical values of the SNHT statistic by series length (Khaliq & Ouarda 2007), interpolated between
+const SNHT_CRITICAL: [(usize, f64); 10] = [
+    (10, 5.70), (20, 6.95), (30, 7.65), (40, 8.10), (50, 8.45),
+    (70, 8.80), (100, 9.15), (150, 9.55), (250, 9.90), (500, 10.40),
+];
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub series: &'static str, // "tmax" or "tmin"
+    pub year: i32,            // first year after the break
+    pub magnitude: f64,       // mean after - mean before, °F
+    pub snht: f64,
+}
+
+/// Annual mean departure from the whole-record monthly means, (tmax, tmin), complete years only
+pub fn annual_anomalies(months: &BTreeMap<i32, BucketTemps>) -> (YearSeries, YearSeries) {
+    let years: Vec<i32> = months.keys().copied().collect();
+    let (Some(first), Some(last)) = (years.first(), years.last()) else { return (Vec::new(), Vec::new()); };
+    let base = periods::mean_temps(months, periods::bucket_count("Month"), *first, *last);
+    let departures: BTreeMap<i32, BucketTemps> = months.iter().map(|(year, temps)| (*year, periods::departures(temps, &base))).collect();
+    let annual = periods::annual_means(&departures);
+    let hi = annual.iter().filter_map(|(year, (h, _))| Some((*year, (*h)?))).collect();
+    let low = annual.iter().filter_map(|(year, (_, l))| Some((*year, (*l)?))).collect();
+    (hi, low)
+}
+
+/// Candidate minus the mean of whichever references have that year, years no reference has are dropped
+pub fn difference_series(candidate: &[(i32, f64)], references: &[Vec<(i32, f64)>]) -> Vec<(i32, f64)> {
+    let lookups: Vec<BTreeMap<i32, f64>> = references.iter().map(|r| r.iter().copied().collect()).collect();
+    candidate.iter().filter_map(|(year, value)| {
+        let reference = periods::mean(lookups.iter().filter_map(|r| r.get(year).copied()))?;
+        Some((*year, value - reference))
+    }).collect()
+}
+
+fn critical_value(n: usize) -> f64 {
+    let (first, last) = (SNHT_CRITICAL[0], SNHT_CRITICAL[SNHT_CRITICAL.len() - 1]);
+    if n <= first.0 { return first.1; }
+    if n >= last.0 { return last.1; }
+    SNHT_CRITICAL.windows(2).find(|pair| n <= pair[1].0).map(|pair| {
+        let ((n0, t0), (n1, t1)) = (pair[0], pair[1]);
+        t0 + (t1 - t0) * (n - n0) as f64 / (n1 - n0) as f64
+    }).unwrap_or(last.1)
+}
+
+/// (split index, T) of the most likely single shift, both sides at least min_len long
+fn snht(values: &[f64], min_len: usize) -> Option<(usize, f64)> {
+    let n = values.len();
+    if n < 2 * min_len.max(1) {
+        return None;
+    }
+    let mean = periods::mean(values.iter().copied())?;
+    let sd = periods::mean(values.iter().map(|v| (v - mean).powi(2)))?.sqrt();
+    if sd == 0.0 {
+        return None;
+    }
+    let z: Vec<f64> = values.iter().map(|v| (v - mean) / sd).collect();
+    (min_len.max(1)..=n - min_len.max(1)).map(|k| {
+        let z1 = z[..k].iter().sum::<f64>() / k as f64;
+        let z2 = z[k..].iter().sum::<f64>() / (n - k) as f64;
+        (k, k as f64 * z1 * z1 + (n - k) as f64 * z2 * z2)
+    }).max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Binary segmentation: test the whole series, split at a significant break and test each side again.
+/// Magnitudes are measured between the final neighboring segments
+pub fn find_breakpoints(series: &'static str, points: &[(i32, f64)], min_len: usize) -> Vec<Breakpoint> {
+    let values: Vec<f64> = points.iter().map(|(_, v)| *v).collect();
+    let mut splits: Vec<(usize, f64)> = Vec::new();
+    let mut segments = vec![(0, values.len())];
+    while let Some((start, end)) = segments.pop() {
+        let Some((k, t)) = snht(&values[start..end], min_len) else { continue; };
+        if t > critical_value(end - start) {
+            splits.push((start + k, t));
+            segments.push((start, start + k));
+            segments.push((start + k, end));
+        }
+    }
+    splits.sort_by_key(|(idx, _)| *idx);
+    let bounds: Vec<usize> = std::iter::once(0).chain(splits.iter().map(|(idx, _)| *idx)).chain(std::iter::once(values.len())).collect();
+    splits.iter().enumerate().filter_map(|(i, (idx, t))| {
+        let before = periods::mean(values[bounds[i]..bounds[i + 1]].iter().copied())?;
+        let after = periods::mean(values[bounds[i + 1]..bounds[i + 2]].iter().copied())?;
+        Some(Breakpoint { series, year: points[*idx].0, magnitude: after - before, snht: *t })
+    }).collect()
+}
+
+/// Amount added to a year so everything lines up with the latest segment, the sum of every later break
+pub fn adjustment(breaks: &[Breakpoint], series: &str, year: i32) -> f64 {
+    breaks.iter().filter(|b| b.series == series && b.year > year).map(|b| b.magnitude).sum()
+}
+
+/// Every bucket shifted by its year's adjustment, the mean by the average of the hi and low adjustments
+pub fn adjust_years(years: &BTreeMap<i32, BucketTemps>, breaks: &[Breakpoint]) -> BTreeMap<i32, BucketTemps> {
+    years.iter().map(|(year, temps)| {
+        let shift = |values: &[Option<f64>], amount: f64| values.iter().map(|v| v.map(|t| t + amount)).collect();
+        let (hi, low) = (adjustment(breaks, "tmax", *year), adjustment(breaks, "tmin", *year));
+        (*year, BucketTemps { tmax: shift(&temps.tmax, hi), tmin: shift(&temps.tmin, low), tmean: shift(&temps.tmean, (hi + low) / 2.0) })
+    }).collect()
+}
+
+pub async fn create_adjusted_table(pool: &Pool<MySql>, city: &str, period: &str) -> Result<(), sqlx::Error> {
+    let create_stmt = format!(r#"CREATE TABLE if NOT exists `{}_{}_adj` (
+  `id` int(11) NOT NULL,
+  `station` char(12) DEFAULT NULL,
+  `tyear` smallint(6) NOT NULL,
+  `{}` smallint(6) NOT NULL,
+  `tmax` smallint(6) DEFAULT NULL,
+  `tmin` smallint(6) DEFAULT NULL,
+  `tmean` smallint(6) DEFAULT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#, city, period.to_lowercase(), periods::period_column(period));
+    let _result = sqlx::query(&create_stmt).execute(pool).await?;
+    Ok(())
+}
+
+// same layout as the raw period table so get_temps and the bar charts can read it as "{city}_{period}_adj"
+pub async fn store_adjusted(pool: &Pool<MySql>, city: &str, period: &str, years: &BTreeMap<i32, BucketTemps>) -> Result<(), sqlx::Error> {
+    let table = format!("{city}_{}_adj", period.to_lowercase());
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!("DELETE FROM `{table}`")).execute(&mut *tx).await?;
+    let insert_stmt = format!("INSERT INTO `{table}` (id, tyear, {}, tmax, tmin, tmean) VALUES (?, ?, ?, ?, ?, ?)", periods::period_column(period));
+    let mut id = 0;
+    for (year, temps) in years {
+        for (idx, ((hi, low), mean)) in temps.tmax.iter().zip(&temps.tmin).zip(&temps.tmean).enumerate() {
+            if hi.is_none() && low.is_none() && mean.is_none() {
+                continue;
+            }
+            id += 1;
+            sqlx::query(&insert_stmt)
+                .bind(id)
+                .bind(year)
+                .bind(idx as i32 + 1)
+                .bind(hi.map(|v| v.round() as i32))
+                .bind(low.map(|v| v.round() as i32))
+                .bind(mean.map(|v| v.round() as i32))
+                .execute(&mut *tx).await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub fn write_breakpoints_csv(city: &str, breaks: &[Breakpoint], reference: &str, unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_breakpoints{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "series,first_year_after,magnitude,snht,reference")?;
+    for b in breaks {
+        writeln!(out, "{},{},{},{:.2},\"{reference}\"", b.series, b.year, unit.format_delta(b.magnitude, 2), b.snht)?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+/// Raw annual anomalies faint, adjusted solid, a dashed line at each break labeled with its size. raw and breaks are °F
+pub fn draw_homogenized(city: &str, raw: &[(&'static str, Vec<(i32, f64)>)], breaks: &[Breakpoint], unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let years = raw.iter().flat_map(|(_, points)| points.iter().map(|(year, _)| *year));
+    let (first, last) = years.fold((i32::MAX, i32::MIN), |(f, l), y| (f.min(y), l.max(y)));
+    if first > last {
+        println!("No complete years for {city}, no homogenization chart drawn");
+        return Ok(());
+    }
+    let adjusted: Vec<Vec<(i32, f64)>> = raw.iter()
+        .map(|(series, points)| points.iter().map(|(year, v)| (*year, unit.delta(v + adjustment(breaks, series, *year)))).collect())
+        .collect();
+    let raw: Vec<(&str, Vec<(i32, f64)>)> = raw.iter()
+        .map(|(series, points)| (*series, points.iter().map(|(year, v)| (*year, unit.delta(*v))).collect()))
+        .collect();
+    let y_scale = yearchart::value_scale(raw.iter().flat_map(|(_, p)| p.iter()).chain(adjusted.iter().flatten()).map(|(_, v)| *v), 1.0);
+    let file_name = format!("imgs/{city}_homogenized{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first}-{last}  Annual Anomaly, Raw and Adjusted ({})", unit.symbol());
+    yearchart::draw_year_chart_base(&dwg, &title_text, first, last, &y_scale)?;
+    let mut legend = Vec::new();
+    for ((series, points), adjusted_points) in raw.iter().zip(&adjusted) {
+        let color = if *series == "tmax" { HI_COLOR } else { LOW_COLOR };
+        yearchart::draw_year_line(&dwg, points, first, last, &y_scale, color.mix(0.35).stroke_width(1))?;
+        yearchart::draw_year_line(&dwg, adjusted_points, first, last, &y_scale, color.stroke_width(2))?;
+        legend.push((format!("{series} adjusted (faint: raw)"), color));
+    }
+    let label_style = ("sans-serif", 14).into_font().color(&BLACK);
+    for b in breaks {
+        let x = year_x(f64::from(b.year) - 0.5, first, last);
+        dwg.draw(&DashedPathElement::new(vec![(x, TOP_MARGIN), (x, BOTTOM_LINE_Y)], 8, 6, BREAK_COLOR.stroke_width(1)))?;
+        let y = if b.series == "tmax" { BOTTOM_LINE_Y - 40 } else { BOTTOM_LINE_Y - 20 };
+        dwg.draw_text(&format!("{} {:+.1}", b.series, unit.delta(b.magnitude)), &label_style, (x + 4, y))?;
+    }
+    legend.push(("Dashed: breakpoint, labeled with the size of the jump".to_string(), BREAK_COLOR));
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1900 on, small alternating noise with `step` added from index `at`
+    fn step_series(len: usize, at: usize, step: f64) -> Vec<(i32, f64)> {
+        (0..len).map(|i| {
+            let noise = if i % 2 == 0 { 0.1 } else { -0.1 };
+            (1900 + i as i32, noise + if i >= at { step } else { 0.0 })
+        }).collect()
+    }
+
+    #[test]
+    fn step_up_is_found() {
+        let breaks = find_breakpoints("tmax", &step_series(40, 20, 2.0), 5);
+        assert_eq!(breaks.len(), 1);
+        assert_eq!(breaks[0].year, 1920);
+        assert!((breaks[0].magnitude - 2.0).abs() < 1e-9, "magnitude = {}", breaks[0].magnitude);
+        assert!(breaks[0].snht > critical_value(40));
+    }
+
+    #[test]
+    fn step_down_keeps_its_sign() {
+        let breaks = find_breakpoints("tmin", &step_series(30, 12, -1.5), 5);
+        assert_eq!(breaks.len(), 1);
+        assert_eq!(breaks[0].year, 1912);
+        assert!((breaks[0].magnitude + 1.5).abs() < 1e-9, "magnitude = {}", breaks[0].magnitude);
+    }
+
+    #[test]
+    fn flat_series_has_no_break() {
+        assert!(find_breakpoints("tmax", &step_series(40, 40, 0.0), 5).is_empty());
+        let constant: Vec<(i32, f64)> = (1900..1940).map(|year| (year, 60.0)).collect();
+        assert!(find_breakpoints("tmax", &constant, 5).is_empty());
+    }
+
+    #[test]
+    fn snht_min_len_edges() {
+        let values = [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+        // exactly 2 * min_len long leaves one split, z is -1 then 1 so T = 5 + 5
+        assert_eq!(snht(&values, 5), Some((5, 10.0)));
+        assert_eq!(snht(&values[..9], 5), None);
+        // min_len 0 still keeps a value on each side
+        assert_eq!(snht(&[0.0, 1.0], 0), Some((1, 2.0)));
+        assert_eq!(snht(&[0.0], 0), None);
+    }
+
+    #[test]
+    fn break_near_the_end_is_held_to_min_len() {
+        // the real step is 2 years from the end, min_len 5 won't split there
+        let breaks = find_breakpoints("tmax", &step_series(30, 28, 3.0), 5);
+        assert!(breaks.iter().all(|b| b.year <= 1925), "{breaks:?}");
+    }
+
+    #[test]
+    fn adjustment_sums_only_later_breaks_of_the_series() {
+        let breaks = [
+            Breakpoint { series: "tmax", year: 1950, magnitude: 1.0, snht: 12.0 },
+            Breakpoint { series: "tmax", year: 1980, magnitude: -0.5, snht: 11.0 },
+            Breakpoint { series: "tmin", year: 1960, magnitude: 3.0, snht: 15.0 },
+        ];
+        assert_eq!(adjustment(&breaks, "tmax", 1940), 0.5);
+        assert_eq!(adjustment(&breaks, "tmax", 1950), -0.5); // the break year is already after the break
+        assert_eq!(adjustment(&breaks, "tmax", 1979), -0.5);
+        assert_eq!(adjustment(&breaks, "tmax", 1980), 0.0);
+        assert_eq!(adjustment(&breaks, "tmin", 1940), 3.0);
+        assert_eq!(adjustment(&breaks, "tmean", 1940), 0.0);
+    }
+}
@@ -0,0 +1,162 @@
+// Loads a NOAA Climate Data Online daily csv into the raw {city} table. The columns are found by header name
+// (STATION, DATE, TMAX, TMAX_ATTRIBUTES, TMIN, TMIN_ATTRIBUTES, TAVG, PRCP, SNOW, SNWD) so the extra columns CDO
+// adds are ignored and files without TAVG or the precipitation elements still load. Order the file in standard
+// units so TMAX / TMIN / TAVG are whole degrees F and PRCP / SNOW / SNWD are inches.
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use sqlx::{MySql, Pool, Row};
+
+use crate::daily::{self, DailyTemp};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportRow {
+    pub station: String,
+    pub tdate: String, // 2020-09-05
+    pub tmax: Option<i32>,
+    pub tmin: Option<i32>,
+    pub tmax_attributes: String, // M,Q,S,T flags as CDO writes them, ex. ",,W,2400"
+    pub tmin_attributes: String,
+    pub tavg: Option<i32>, // reported daily mean, most stations only have it from the late 1990s
+    pub prcp: Option<f64>, // inches of rain and melted snow
+    pub snow: Option<f64>, // inches of snowfall
+    pub snwd: Option<f64>, // inches of snow on the ground
+}
+
+/// Every row with a readable DATE, in file order. Rows without one are counted in the second value
+pub fn read_cdo_csv(path: &str) -> Result<(Vec<ImportRow>, usize), Box<dyn std::error::Error>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = split_csv_line(&lines.next().ok_or(format!("{path} is empty"))??);
+    let column = |name: &str| header.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let (Some(station_col), Some(date_col)) = (column("STATION"), column("DATE")) else {
+        return Err(format!("{path} has no STATION or DATE column").into());
+    };
+    let columns = [column("TMAX"), column("TMIN"), column("TMAX_ATTRIBUTES"), column("TMIN_ATTRIBUTES"),
+                   column("PRCP"), column("SNOW"), column("SNWD"), column("TAVG")];
+    let mut rows = Vec::new();
+    let mut skipped = 0;
+    for line in lines {
+        let fields = split_csv_line(&line?);
+        let field = |col: Option<usize>| col.and_then(|c| fields.get(c)).map(|f| f.trim()).unwrap_or("");
+        let tdate = field(Some(date_col));
+        if daily::parse_tdate(tdate).is_none() {
+            skipped += 1;
+            continue;
+        }
+        rows.push(ImportRow {
+            station: field(Some(station_col)).to_string(),
+            tdate: tdate[0..10].to_string(),
+            tmax: field(columns[0]).parse::<f64>().ok().map(|t| t.round() as i32),
+            tmin: field(columns[1]).parse::<f64>().ok().map(|t| t.round() as i32),
+            tmax_attributes: field(columns[2]).to_string(),
+            tmin_attributes: field(columns[3]).to_string(),
+            tavg: field(columns[7]).parse::<f64>().ok().map(|t| t.round() as i32),
+            prcp: field(columns[4]).parse::<f64>().ok(),
+            snow: field(columns[5]).parse::<f64>().ok(),
+            snwd: field(columns[6]).parse::<f64>().ok(),
+        });
+    }
+    Ok((rows, skipped))
+}
+
+/// What's in the {city} table now, as if it had been read from a file. Columns a table doesn't have
+/// (attributes, tavg and precipitation on tables loaded before import existed) come back empty
+pub async fn get_daily_rows(pool: &Pool<MySql>, city: &str) -> Result<Vec<ImportRow>, sqlx::Error> {
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&format!("SELECT * FROM {city} ORDER BY tdate"))
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(|row| {
+        let text = |name: &str| row.try_get::<String, _>(name).map(|s| s.trim().to_string()).unwrap_or_default();
+        ImportRow {
+            station: text("station"),
+            tdate: text("tdate").chars().take(10).collect(),
+            tmax: row.try_get("tmax").ok(),
+            tmin: row.try_get("tmin").ok(),
+            tmax_attributes: text("tmax_attributes"),
+            tmin_attributes: text("tmin_attributes"),
+            tavg: row.try_get("tavg").ok(),
+            prcp: row.try_get("prcp").ok(),
+            snow: row.try_get("snow").ok(),
+            snwd: row.try_get("snwd").ok(),
+        }
+    }).collect())
+}
+
+/// Rows as DailyTemp for the analysis code, rows with an unreadable date are dropped
+pub fn to_daily_temps(rows: &[ImportRow]) -> Vec<DailyTemp> {
+    let mut days: Vec<DailyTemp> = rows.iter()
+        .filter_map(|row| Some(DailyTemp { date: daily::parse_tdate(&row.tdate)?, tmax: row.tmax, tmin: row.tmin, tavg: row.tavg }))
+        .collect();
+    days.sort_by_key(|d| d.date);
+    days
+}
+
+// CDO quotes every field and the NAME and attribute fields have commas in them
+pub fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => { field.push('"'); chars.next(); },
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+pub async fn create_daily_table(pool: &Pool<MySql>, city: &str) -> Result<(), sqlx::Error> {
+    let create_stmt = format!(r#"CREATE TABLE if NOT exists `{city}` (
+  `id` int(11) NOT NULL,
+  `station` char(12) DEFAULT NULL,
+  `tdate` char(10) NOT NULL,
+  `tmax` smallint(6) DEFAULT NULL,
+  `tmin` smallint(6) DEFAULT NULL,
+  `tmax_attributes` varchar(16) DEFAULT NULL,
+  `tmin_attributes` varchar(16) DEFAULT NULL,
+  `tavg` smallint(6) DEFAULT NULL,
+  `prcp` double DEFAULT NULL,
+  `snow` double DEFAULT NULL,
+  `snwd` double DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `tdate` (`tdate`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#);
+    let _result = sqlx::query(&create_stmt).execute(pool).await?;
+    // tables made before import (or before tavg and precipitation) get the columns they're missing
+    let alter_stmt = format!(r#"ALTER TABLE `{city}`
+  ADD COLUMN IF NOT EXISTS `tmax_attributes` varchar(16) DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `tmin_attributes` varchar(16) DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `tavg` smallint(6) DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `prcp` double DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `snow` double DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `snwd` double DEFAULT NULL;"#);
+    let _result = sqlx::query(&alter_stmt).execute(pool).await?;
+    Ok(())
+}
+
+// replaces everything in the {city} table with the file's rows
+pub async fn store_daily_rows(pool: &Pool<MySql>, city: &str, rows: &[ImportRow]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!("DELETE FROM `{city}`")).execute(&mut *tx).await?;
+    let insert_stmt = format!("INSERT INTO `{city}` (id, station, tdate, tmax, tmin, tmax_attributes, tmin_attributes, tavg, prcp, snow, snwd) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)");
+    for (idx, row) in rows.iter().enumerate() {
+        sqlx::query(&insert_stmt)
+            .bind(idx as i32 + 1)
+            .bind(&row.station)
+            .bind(&row.tdate)
+            .bind(row.tmax)
+            .bind(row.tmin)
+            .bind(&row.tmax_attributes)
+            .bind(&row.tmin_attributes)
+            .bind(row.tavg)
+            .bind(row.prcp)
+            .bind(row.snow)
+            .bind(row.snwd)
+            .execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,223 @@
+// Tamper evidence for the source files. Every import records a SHA-256 digest of each station-year in
+// hash_ledger, and verify recomputes them from a fresh download to list exactly which station-years differ.
+// A digest covers the year's rows sorted by date as "tdate,tmax,tmin,tmax_attributes,tmin_attributes\n",
+// so the file's row order, quoting and extra columns don't change it but any temperature or flag does.
+// TAVG and PRCP / SNOW / SNWD are left out so digests recorded before those were imported still compare.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use sha2::{Digest, Sha256};
+use sqlx::{MySql, Pool, Row};
+
+use crate::import::ImportRow;
+
+/// Digest and row count for one station-year
+#[derive(Clone, Debug, PartialEq)]
+pub struct YearDigest {
+    pub digest: String, // lower case hex
+    pub rows: i32,
+}
+
+pub fn station_year_digests(rows: &[ImportRow]) -> BTreeMap<(String, i32), YearDigest> {
+    let mut by_year: BTreeMap<(String, i32), Vec<&ImportRow>> = BTreeMap::new();
+    for row in rows {
+        let Some(year) = row.tdate.get(0..4).and_then(|y| y.parse::<i32>().ok()) else { continue; };
+        by_year.entry((row.station.clone(), year)).or_default().push(row);
+    }
+    by_year.into_iter().map(|(key, mut year_rows)| {
+        // full row as a tie breaker so duplicated dates hash the same whatever order they came in
+        year_rows.sort_by_key(|r| canonical_line(r));
+        let mut hasher = Sha256::new();
+        for row in &year_rows {
+            hasher.update(canonical_line(row).as_bytes());
+        }
+        let digest = hasher.finalize().iter().map(|b| format!("{b:02x}")).collect();
+        (key, YearDigest { digest, rows: year_rows.len() as i32 })
+    }).collect()
+}
+
+fn canonical_line(row: &ImportRow) -> String {
+    let temp = |t: Option<i32>| t.map(|v| v.to_string()).unwrap_or_default();
+    format!("{},{},{},{},{}\n", row.tdate, temp(row.tmax), temp(row.tmin), row.tmax_attributes, row.tmin_attributes)
+}
+
+pub async fn create_ledger_table(pool: &Pool<MySql>) -> Result<(), sqlx::Error> {
+    let create_stmt = r#"CREATE TABLE if NOT exists `hash_ledger` (
+  `import_id` varchar(26) NOT NULL,
+  `city` varchar(40) NOT NULL,
+  `source_file` varchar(255) DEFAULT NULL,
+  `station` char(12) NOT NULL,
+  `tyear` smallint(6) NOT NULL,
+  `digest` char(64) NOT NULL,
+  `rows_count` int(11) NOT NULL,
+  PRIMARY KEY (`import_id`, `city`, `station`, `tyear`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#;
+    let _result = sqlx::query(create_stmt).execute(pool).await?;
+    Ok(())
+}
+
+/// Adds this import's digests, earlier imports stay in the ledger. import_id is the UTC time to the microsecond,
+/// 2024-05-01 20:45:00.123456, fixed width so the latest import is also the largest id
+pub async fn store_digests(pool: &Pool<MySql>, city: &str, source_file: &str, digests: &BTreeMap<(String, i32), YearDigest>) -> Result<String, sqlx::Error> {
+    let import_id = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S%.6f").to_string();
+    let mut tx = pool.begin().await?;
+    let insert_stmt = "INSERT INTO `hash_ledger` (import_id, city, source_file, station, tyear, digest, rows_count) VALUES (?, ?, ?, ?, ?, ?, ?)";
+    for ((station, year), year_digest) in digests {
+        sqlx::query(insert_stmt)
+            .bind(&import_id)
+            .bind(city)
+            .bind(source_file)
+            .bind(station)
+            .bind(year)
+            .bind(&year_digest.digest)
+            .bind(year_digest.rows)
+            .execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(import_id)
+}
+
+/// Digests from the city's most recent import with its import_id, None if it was never imported
+pub async fn last_import_digests(pool: &Pool<MySql>, city: &str) -> Result<Option<(String, BTreeMap<(String, i32), YearDigest>)>, sqlx::Error> {
+    let last: Vec<sqlx::mysql::MySqlRow> = sqlx::query("SELECT MAX(import_id) AS import_id FROM hash_ledger WHERE city = ?")
+        .bind(city)
+        .fetch_all(pool)
+        .await?;
+    let Some(import_id) = last.first().and_then(|row| row.try_get::<String, _>("import_id").ok()) else {
+        return Ok(None);
+    };
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query("SELECT station, tyear, digest, rows_count FROM hash_ledger WHERE city = ? AND import_id = ?")
+        .bind(city)
+        .bind(&import_id)
+        .fetch_all(pool)
+        .await?;
+    let digests = rows.iter().map(|row| {
+        let station: String = row.get("station");
+        ((station.trim().to_string(), row.get("tyear")), YearDigest { digest: row.get("digest"), rows: row.get("rows_count") })
+    }).collect();
+    Ok(Some((import_id, digests)))
+}
+
+/// Station-year that differs between the ledger and a fresh file
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub station: String,
+    pub year: i32,
+    pub kind: &'static str, // "changed", "added" (only in the fresh file), "removed" (only in the ledger)
+    pub ledger_rows: Option<i32>,
+    pub fresh_rows: Option<i32>,
+}
+
+pub fn compare_digests(ledger: &BTreeMap<(String, i32), YearDigest>, fresh: &BTreeMap<(String, i32), YearDigest>) -> Vec<Change> {
+    let mut keys: Vec<&(String, i32)> = ledger.keys().chain(fresh.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter().filter_map(|key| {
+        let (old, new) = (ledger.get(key), fresh.get(key));
+        let kind = match (old, new) {
+            (Some(old), Some(new)) if old.digest == new.digest => return None,
+            (Some(_), Some(_)) => "changed",
+            (None, Some(_)) => "added",
+            (Some(_), None) => "removed",
+            (None, None) => return None,
+        };
+        Some(Change { station: key.0.clone(), year: key.1, kind, ledger_rows: old.map(|d| d.rows), fresh_rows: new.map(|d| d.rows) })
+    }).collect()
+}
+
+pub fn write_verify_csv(city: &str, changes: &[Change]) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_verify.csv");
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "station,year,change,ledger_rows,fresh_rows")?;
+    let count = |rows: Option<i32>| rows.map(|r| r.to_string()).unwrap_or_default();
+    for change in changes {
+        writeln!(out, "{},{},{},{},{}", change.station, change.year, change.kind, count(change.ledger_rows), count(change.fresh_rows))?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import;
+
+    // writes a csv to the temp dir and reads it back the way import does
+    fn read_csv(name: &str, text: &str) -> Vec<ImportRow> {
+        let path = std::env::temp_dir().join(format!("weather3_ledger_{}_{name}.csv", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let (rows, _) = import::read_cdo_csv(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        rows
+    }
+
+    const ORIGINAL: &str = "\"STATION\",\"NAME\",\"DATE\",\"TMAX\",\"TMAX_ATTRIBUTES\",\"TMIN\",\"TMIN_ATTRIBUTES\"
+\"USW00023174\",\"LOS ANGELES, CA US\",\"2020-01-01\",\"68\",\",,W,2400\",\"48\",\",,W,2400\"
+\"USW00023174\",\"LOS ANGELES, CA US\",\"2020-01-02\",\"70\",\",,W,2400\",\"49\",\",,W,2400\"
+\"USW00023174\",\"LOS ANGELES, CA US\",\"2021-01-01\",\"65\",\",,W,2400\",\"45\",\",,W,2400\"
+\"USC00045115\",\"LOS ANGELES DOWNTOWN, CA US\",\"2020-01-01\",\"71\",\",,7,0800\",\"50\",\",,7,0800\"
+";
+
+    // same observations, rows shuffled, fields only quoted where they need it, columns moved and extra ones added
+    const RESHUFFLED: &str = "DATE,STATION,TMIN,TMIN_ATTRIBUTES,TMAX,TMAX_ATTRIBUTES,ELEVATION,TAVG
+2021-01-01,USW00023174,45,\",,W,2400\",65,\",,W,2400\",29.6,55
+2020-01-01,USC00045115,50,\",,7,0800\",71,\",,7,0800\",70.1,
+2020-01-02,USW00023174,49,\",,W,2400\",70,\",,W,2400\",29.6,59
+2020-01-01,USW00023174,48,\",,W,2400\",68,\",,W,2400\",29.6,58
+";
+
+    fn digest(station: &str, year: i32) -> YearDigest {
+        YearDigest { digest: format!("{station}{year}"), rows: 1 }
+    }
+
+    #[test]
+    fn digest_ignores_row_order_quoting_and_extra_columns() {
+        let original = station_year_digests(&read_csv("original", ORIGINAL));
+        let reshuffled = station_year_digests(&read_csv("reshuffled", RESHUFFLED));
+        assert_eq!(original.len(), 3);
+        assert_eq!(original, reshuffled);
+        assert_eq!(original[&("USW00023174".to_string(), 2020)].rows, 2);
+    }
+
+    #[test]
+    fn digest_changes_with_one_flag() {
+        let original = station_year_digests(&read_csv("flag_before", ORIGINAL));
+        let flagged = station_year_digests(&read_csv("flag_after", &ORIGINAL.replacen("\"70\",\",,W,2400\"", "\"70\",\",I,W,2400\"", 1)));
+        let changes = compare_digests(&original, &flagged);
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].station.as_str(), changes[0].year, changes[0].kind), ("USW00023174", 2020, "changed"));
+    }
+
+    #[test]
+    fn rows_without_a_year_are_skipped() {
+        let mut rows = read_csv("short_dates", ORIGINAL);
+        rows[0].tdate = String::new(); // what get_daily_rows returns for an unreadable tdate
+        rows[1].tdate = "é".to_string();
+        let digests = station_year_digests(&rows);
+        assert_eq!(digests[&("USW00023174".to_string(), 2021)].rows, 1);
+        assert!(!digests.contains_key(&("USW00023174".to_string(), 2020)));
+    }
+
+    #[test]
+    fn compare_lists_changed_added_and_removed() {
+        let ledger: BTreeMap<(String, i32), YearDigest> = [
+            (("A".to_string(), 2000), digest("A", 2000)),
+            (("A".to_string(), 2001), digest("A", 2001)),
+            (("B".to_string(), 2000), digest("B", 2000)),
+        ].into_iter().collect();
+        let mut fresh = ledger.clone();
+        fresh.get_mut(&("A".to_string(), 2001)).unwrap().digest = "different".to_string();
+        fresh.remove(&("B".to_string(), 2000));
+        fresh.insert(("C".to_string(), 2002), YearDigest { digest: "new".to_string(), rows: 3 });
+        let changes: Vec<String> = compare_digests(&ledger, &fresh).iter()
+            .map(|c| format!("{} {} {} {:?} {:?}", c.station, c.year, c.kind, c.ledger_rows, c.fresh_rows))
+            .collect();
+        assert_eq!(changes, [
+            "A 2001 changed Some(1) Some(1)",
+            "B 2000 removed Some(1) None",
+            "C 2002 added None Some(3)",
+        ]);
+        assert!(compare_digests(&ledger, &ledger).is_empty());
+    }
+}
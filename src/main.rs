@@ -4,6 +4,8 @@ use std::env;
 use plotters::prelude::*;
 use plotters::coord::Shift;
 
+use units::Unit;
+
 const TOP_MARGIN: i32 = 60;
 const BOTTOM_MARGIN: i32 = 40;
 const LEFT_MARGIN: i32 = 70;
@@ -14,8 +16,39 @@ const AXIS_WIDTH: i32 = DWG_WIDTH - LEFT_MARGIN - RIGHT_MARGIN;
 const AXIS_HEIGHT: i32 = DWG_HEIGHT - TOP_MARGIN - BOTTOM_MARGIN;
 const H_TICK_WIDTH: i32 = AXIS_WIDTH / 4;
 const V_TICK_HEIGHT: i32 = AXIS_HEIGHT / 10;
-const TOP_LINE_Y: i32 = 0 + TOP_MARGIN; //x height of top line of chart, might NOT = TOP_MARGIN
+const TOP_LINE_Y: i32 = TOP_MARGIN; //x height of top line of chart, might NOT = TOP_MARGIN
 const BOTTOM_LINE_Y: i32 = TOP_LINE_Y + AXIS_HEIGHT;
+const MEAN_COLOR: RGBColor = RGBColor(230, 140, 0);
+
+mod animation;
+mod aggregate;
+mod anomaly;
+mod calendar;
+mod coverage;
+mod daily;
+mod dataset;
+mod degree_days;
+mod diff;
+mod dtr;
+mod export;
+mod fill;
+mod frost;
+mod heatmap;
+mod homogenize;
+mod import;
+mod ledger;
+mod normals;
+mod periods;
+mod precip;
+mod qc;
+mod records;
+mod spells;
+mod stations;
+mod stats;
+mod thresholds;
+mod trend;
+mod units;
+mod yearchart;
 
 #[tokio::main]
 async fn main() -> Result<(), sqlx::Error> {
@@ -31,24 +64,78 @@ async fn main() -> Result<(), sqlx::Error> {
         .connect(&database_url)
         .await?;
 
-    let period = "Month"; // options are "Week", "Fort", "Month"
+    // first command line arg picks what to generate, no arg draws the single year chart
+    let args: Vec<String> = env::args().collect();
+    let mode = args.get(1).map(|m| m.as_str()).unwrap_or("chart"); // options are "chart", "animate", "anomaly", "heatmap", "trend", "stats", "normals", "records", "thresholds", "frost", "degreedays", "spells", "dtr", "qc", "aggregate", "coverage", "stations", "fill", "import", "verify", "diff", "homogenize", "precip", "export", "parquet_export", "parquet_import"
+
+    let period = "Month"; // options are "Week", "Fort", "Month", and with aggregated once aggregate has built them "Week53", "IsoWeek", "Fort27", "Season" (see calendar.rs)
     let city  = "Los_Angeles_CA"; //SQL ignores upper/lower case for table names & in name_of_city column
-    let city_period = format!("{city}_{period}");
-    let tperiod = "tmonth"; // column names in selected db: can be tmonth, tfort, or tweek
+    let unit = Unit::F; // options are Unit::F, Unit::C, Unit::K (K is for the csv exports). Only output is converted, the db stays °F
+    let temp_series = ["tmax", "tmean", "tmin"]; // chart, animate, anomaly, trend, normals & stats: any of "tmax", "tmean", "tmin". tmean is only in the _agg tables aggregate builds, empty in the original ones
+    let adjusted = false; // chart only: true draws the homogenized {city}_{period}_adj table instead of the raw averages
+    let aggregated = false; // chart, dtr & export: true reads the {city}_{period}_agg tables rebuilt by aggregate instead of the original averages
+    let city_period = if aggregated { aggregate::agg_table(city, period) } else { format!("{city}_{period}") };
+    let tperiod = periods::period_column(period); // column names in selected db: tmonth, tfort, tweek or tseason
     let mut first_year = 1899; // using a date before 20th century make sure earliest date for that city is used
     let mut last_year = 2030; // using a future date makes sure the latest valid date for that city is used
+    let anim_options = animation::AnimationOptions {
+        frame_delay_ms: 200, // 200ms = 5 frames per second, roadmap suggests 3-10 fps
+        trailing_years: 10, // outline bars for the mean of the previous N years, 0 turns them off
+        show_record_mean: true, // lines for the mean of every year on record
+    };
+    let baseline = (1991, 2020); // anomaly, heatmap, spells & precip: years averaged for the baseline, ex. (1901, 1930) or (1991, 2020)
+    let smoothing_years = 11; // trend & dtr: centered moving average over this many years, 0 = no smoothing
+    let significance = 0.05; // stats only: Mann-Kendall p values below this count as a real trend
+    let normals_step = 30; // normals only: years between normal start years, 30 = 1901-1930, 1931-1960 ... 10 = 1901-1930, 1911-1940 ...
+    let min_normal_years = 24; // normals only: buckets with fewer years (WMO asks for 80% of 30) are left off the chart
+    let day_thresholds = [ // thresholds only: days counted per year and decade, series "tmax", "tmin" or "tmean", values in °F
+        thresholds::Threshold { series: "tmax", above: true, value: 90 },
+        thresholds::Threshold { series: "tmax", above: true, value: 100 },
+        thresholds::Threshold { series: "tmin", above: false, value: 32 },
+        thresholds::Threshold { series: "tmin", above: false, value: 0 },
+        thresholds::Threshold { series: "tmin", above: true, value: 70 }, // tropical nights
+    ];
+    let completeness = 0.9; // thresholds & coverage: years with fewer days read than this are shown as partial
+    let frost_thresholds = [32, 28]; // frost only: tmin (°F) at or below this is a freeze
+    let dd_base = 65.0; // degreedays only: base temperature in °F for heating and cooling degree days, shown converted to unit
+    let spell_rules = [ // spells only: absolute thresholds in °F, percentiles are per calendar day over the baseline years
+        spells::SpellRule { hot: true, threshold: spells::SpellThreshold::Percentile(90.0), min_days: 3 },
+        spells::SpellRule { hot: false, threshold: spells::SpellThreshold::Percentile(10.0), min_days: 3 },
+        spells::SpellRule { hot: true, threshold: spells::SpellThreshold::Absolute(95), min_days: 3 },
+    ];
+    let spell_window = 7; // spells only: days either side of a calendar day used for its percentile
+    let qc_options = qc::QcOptions {
+        repeat_days: 10, // qc only: same value this many days in a row is flagged, GHCN uses 10
+        jump_z: 5.0, // qc only: day to day changes further than this many standard deviations from the month's usual change
+    };
+    let qc_exclude = ["tmin_gt_tmax", "implausible", "repeated", "duplicate_date", "jump", "ghcn_qflag"]; // aggregate only: readings flagged by these checks are left out of the averages, [] keeps everything
+    let fill_options = fill::FillOptions {
+        max_interp_days: 3, // fill only: gaps up to this many days are interpolated, longer ones come from neighbor cities
+        min_r2: 0.8, // fill only: a neighbor's fit for a month has to explain this much of the variance to be used
+    };
+    let fill_max_km = 100.0; // fill & homogenize: cities with a station within this distance are neighbors
+    let min_segment_years = 5; // homogenize only: shortest run of years allowed on either side of a breakpoint
+    let include_filled = false; // aggregate only: true averages in the estimates from fill, false uses readings only
+    let agg_periods = ["Week", "Fort", "Month"]; // aggregate only: {city}_{period}_agg tables rebuilt, can add "Week53", "IsoWeek", "Fort27", "Season". The original tables are left alone
+    let stations_file = "data/ghcnd-stations.txt"; // stations only: NOAA station list, https://www.ncei.noaa.gov/pub/data/ghcn/daily/ghcnd-stations.txt
+    let import_file = "data/Los_Angeles_CA.csv"; // import & verify: NOAA CDO daily csv for the city, verify wants a fresh download of it
+    let (diff_old, diff_new) = ("db", import_file); // diff only: the two versions compared, "db" or the path of a CDO csv
+    let gap_count = 10; // coverage only: how many of the longest gaps to list per series
+    let precip_elements = ["prcp", "snow", "snwd"]; // precip only: elements charted, the tables always get all three
+    let precip_min_coverage = 0.8; // precip only: share of a bucket's days that need a reading before a prcp or snow total counts, short buckets are left out and marked gray
+    let export_cities: Vec<&str> = vec![city]; // export only: cities written to one file, ex. vec!["Los_Angeles_CA", "Spokane_WA"], an empty vec exports every city in city_names
+    let export_periods = ["Week", "Fort", "Month"]; // export only: period tables exported, with aggregated can add "Week53", "IsoWeek", "Fort27", "Season" once aggregate has built them
+    let export_years = (1899, 2030); // export only: first and last year exported
+    let dataset_dir = "data/parquet"; // parquet_export and parquet_import: partitioned as {dataset_dir}/city={city}/period={period}/part-0.parquet, layout in dataset.rs
+    let dataset_periods = ["Week", "Fort", "Month"]; // parquet_export only: period tables written next to the daily table, same choices as export_periods, add "_agg" for the tables aggregate builds ex. "Month_agg". parquet_import loads whatever is in dataset_dir
+    let heatmap_value = "tmax_anomaly"; // heatmap only: cell color, options are "tmax", "tmin", "tmean", "tmax_anomaly", "tmin_anomaly", "tmean_anomaly"
 
-    let city_low; // must be fn_main scope so just declare it here
-    let city_high; // must be fn_main scope so just declare it here
-    
-    let fn_get_min_max: Result<(i32, i32), sqlx::Error> = get_city_min_max(&pool, city).await;
-    match fn_get_min_max { // city_low & city_high here must be initialized in this block to make compiler happy
-        Ok(_) => {let min_max: &(i32, i32) = &fn_get_min_max.unwrap();
-                city_low = min_max.0; 
-                city_high = min_max.1;
-                println!("Low: {city_low}  High: {city_high}")},
-        Err(e) =>  {city_low = 0; city_high = 0; eprintln!("Error getting City min max: {}",e)}
-    }  
+    let (city_low, city_high) = match get_city_min_max(&pool, city).await {
+        Ok(min_max) => { println!("Low: {}  High: {}", min_max.0, min_max.1);
+                         min_max },
+        Err(e) => { eprintln!("Error getting City min max: {}",e); 
+                    (0, 0) }
+    };
 
     let first_year_result: Result<Vec<sqlx::mysql::MySqlRow>, sqlx::Error> = get_first_year(&pool, city).await;
     match first_year_result {
@@ -78,60 +165,618 @@ async fn main() -> Result<(), sqlx::Error> {
         Err(e) => eprintln!("Error executing function: {}", e),
     } 
 
-    let file_name = format!("imgs/{city}_{first_year}_{period}.png");
-
     // calc these here so available to the functions
-    let y_lowest = city_low-10;
-    let y_highest = city_high + 5;
-    let y_range =  y_highest - y_lowest; //neg y_lowest increases y_range
-    let pixel_per_degree: f64 = f64::from(AXIS_HEIGHT) / f64::from(y_range);
-    let mut zero_line_offset = 0.0;
-    if y_lowest < 0  { 
-        zero_line_offset = (f64::from(y_lowest) * pixel_per_degree).abs(); //
-    } else if y_lowest == 0 {
-        zero_line_offset = 0.0;
-    } else {
-        let z_diff = 0 - y_lowest -1;
-        zero_line_offset = f64::from(z_diff) * pixel_per_degree;
-    }
-    println!("Axis Height: {AXIS_HEIGHT} Y range: {y_range} degrees. Pixels per degree: {pixel_per_degree}. Zero offset: {zero_line_offset}");
+    let y_scale = calc_y_scale(city_low, city_high, unit);
 
-    let title_period; 
-    match period {
-        "Week" => title_period = "Weekly",
-        "Fort" => title_period = "Fortnightly",
-        "Month" => title_period = "Montly",
-        _ => title_period = "Unknown Period",
-    }
-    let title_text = format!("{first_year} {city}  {title_period} Avg Temperatures");
-    let title_style = ("sans-serif", 36).into_font().color(&BLACK);
-    let x_axis_style = ("sans-serif", 14).into_font().color(&BLACK);
-    let y_axis_style = ("sans-serif", 18).into_font().color(&BLACK);
-
-    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
-    dwg.fill(&WHITE).expect("Failed to fill dwg"); //this automatically makes a rectangle size of drawing area and fills it with white
-
-    // Draw axis lines on the drawing area
-    draw_axes(&dwg).expect("Failed to draw axes");
-    
-    // Draw horizontal and verticlal grid lines with tick marks
-    draw_grids(&dwg).expect("Failed to draw grids");
+    match mode {
+        "animate" => {
+            // every frame uses the same city y scale so bar heights can be compared year to year
+            let anim_result = animation::animate_years(&pool, city, period, tperiod, first_year, last_year, &y_scale, &anim_options, &temp_series, unit).await;
+            match anim_result {
+                Ok(_) => println!("Animated {city} {period} {first_year}-{last_year}"),
+                Err(e) => eprintln!("Error animating years: {}", e),
+            }
+        },
+        "anomaly" => {
+            let anomaly_result = anomaly::draw_anomaly_charts(&pool, city, period, tperiod, first_year, last_year, baseline, &temp_series, unit).await;
+            match anomaly_result {
+                Ok(_) => println!("Anomaly charts for {city} {period} {first_year}-{last_year} done"),
+                Err(e) => eprintln!("Error drawing anomaly charts: {}", e),
+            }
+        },
+        "heatmap" => {
+            let heatmap_result = heatmap::draw_heatmap(&pool, city, period, tperiod, first_year, last_year, heatmap_value, baseline, unit).await;
+            match heatmap_result {
+                Ok(_) => println!("Heatmap for {city} {period} {first_year}-{last_year} done"),
+                Err(e) => eprintln!("Error drawing heatmap: {}", e),
+            }
+            let stripes_result = heatmap::draw_stripes(&pool, city, period, tperiod, first_year, last_year, unit).await;
+            match stripes_result {
+                Ok(_) => println!("Stripes for {city} {first_year}-{last_year} done"),
+                Err(e) => eprintln!("Error drawing stripes: {}", e),
+            }
+        },
+        "trend" => {
+            let trend_result = trend::draw_annual_trend(&pool, city, period, tperiod, first_year, last_year, smoothing_years, &temp_series, unit).await;
+            match trend_result {
+                Ok(_) => println!("Annual trend for {city} {first_year}-{last_year} done"),
+                Err(e) => eprintln!("Error drawing annual trend: {}", e),
+            }
+        },
+        "stats" => {
+            // every period table, not just the one picked above
+            match stats::bucket_trends(&pool, city, first_year, last_year, &temp_series).await {
+                Ok(trends) => {
+                    stats::print_trends(city, &trends, unit);
+                    match stats::write_trends_csv(city, &trends, unit) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing trends csv: {}", e),
+                    }
+                    stats::draw_month_slopes(city, first_year, last_year, &trends, significance, unit).expect("Draw month slopes failed");
+                },
+                Err(e) => eprintln!("Error getting bucket trends: {}", e),
+            }
+        },
+        "normals" => {
+            if let Err(e) = normals::create_normals_table(&pool, city).await {
+                eprintln!("Error creating normals table: {}", e);
+            }
+            // store normals for every period table, chart the one picked above
+            for normal_period in ["Week", "Fort", "Month"] {
+                let rows_result = periods::get_all_temps(&pool, periods::period_column(normal_period), &format!("{city}_{normal_period}")).await;
+                match rows_result {
+                    Ok(rows) => {
+                        let city_normals = normals::compute_normals(normal_period, &periods::temps_by_year(normal_period, &rows), normals_step);
+                        match normals::store_normals(&pool, city, normal_period, &city_normals).await {
+                            Ok(_) => println!("Stored {} {normal_period} normals for {city}", city_normals.len()),
+                            Err(e) => eprintln!("Error storing normals: {}", e),
+                        }
+                        if normal_period == period {
+                            normals::draw_normals(city, period, &y_scale, &city_normals, min_normal_years, &temp_series, unit).expect("Draw normals failed");
+                        }
+                    },
+                    Err(e) => eprintln!("Error getting {normal_period} temperatures from db: {}", e),
+                }
+            }
+        },
+        "records" => {
+            match daily::get_daily_temps(&pool, city).await {
+                Ok(days) => {
+                    let city_records = records::daily_records(&days);
+                    let year_counts = records::records_per_year(&days);
+                    match records::write_records_csv(city, &city_records, unit) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing records csv: {}", e),
+                    }
+                    match records::write_year_counts_csv(city, &year_counts) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing records per year csv: {}", e),
+                    }
+                    records::draw_record_ratio(city, &year_counts).expect("Draw record ratio failed");
+                },
+                Err(e) => eprintln!("Error getting daily temperatures from db: {}", e),
+            }
+        },
+        "thresholds" => {
+            match daily::get_daily_temps(&pool, city).await {
+                Ok(days) => {
+                    let yearly: Vec<_> = day_thresholds.iter().map(|t| thresholds::yearly_counts(&days, t, first_year, last_year)).collect();
+                    match thresholds::write_counts_csv(city, &day_thresholds, &yearly, completeness, unit) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing threshold csv: {}", e),
+                    }
+                    for (threshold, counts) in day_thresholds.iter().zip(&yearly) {
+                        thresholds::draw_counts(city, threshold, counts, completeness, unit).expect("Draw threshold counts failed");
+                    }
+                },
+                Err(e) => eprintln!("Error getting daily temperatures from db: {}", e),
+            }
+        },
+        "frost" => {
+            match daily::get_daily_temps(&pool, city).await {
+                Ok(days) => {
+                    let by_threshold: Vec<_> = frost_thresholds.iter().map(|t| (*t, frost::frost_years(&days, *t, first_year, last_year))).collect();
+                    match frost::write_frost_csv(city, &by_threshold, unit) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing frost csv: {}", e),
+                    }
+                    for (threshold, years) in &by_threshold {
+                        let undetermined = years.iter().filter(|y| y.last_spring == frost::FrostDate::Undetermined || y.first_fall == frost::FrostDate::Undetermined).count();
+                        println!("{}{}: {} years, {undetermined} with a date lost to missing data", unit.format_temp(f64::from(*threshold), 0), unit.symbol(), years.len());
+                        frost::draw_frost_dates(city, *threshold, years, unit).expect("Draw frost dates failed");
+                    }
+                },
+                Err(e) => eprintln!("Error getting daily temperatures from db: {}", e),
+            }
+        },
+        "degreedays" => {
+            match daily::get_daily_temps(&pool, city).await {
+                Ok(days) => {
+                    if let Err(e) = degree_days::create_degree_day_tables(&pool, city).await {
+                        eprintln!("Error creating degree day tables: {}", e);
+                    }
+                    let mut biggest: f64 = 0.0; // largest bucket total of the charted period, keeps every year on one scale
+                    for dd_period in ["Week", "Fort", "Month"] {
+                        let (heating, cooling) = degree_days::degree_days_by_bucket(dd_period, &days, dd_base);
+                        if dd_period == period {
+                            biggest = heating.values().chain(cooling.values()).flat_map(|b| b.sums.iter()).fold(0.0, |big, v| big.max(*v));
+                        }
+                        match degree_days::store_degree_days(&pool, city, dd_period, &heating, &cooling).await {
+                            Ok(_) => println!("Stored {dd_period} degree days for {city}"),
+                            Err(e) => eprintln!("Error storing {dd_period} degree days: {}", e),
+                        }
+                    }
+                    let annual = degree_days::annual_degree_days(&days, dd_base);
+                    match degree_days::store_annual_degree_days(&pool, city, &annual).await {
+                        Ok(_) => println!("Stored annual degree days for {city}"),
+                        Err(e) => eprintln!("Error storing annual degree days: {}", e),
+                    }
+                    degree_days::draw_annual_degree_days(city, dd_base, &annual, unit).expect("Draw annual degree days failed");
+                    match degree_days::get_degree_days(&pool, city, period, first_year).await {
+                        Ok(rows) => degree_days::draw_degree_day_charts(city, period, first_year, dd_base, &rows, biggest, unit).expect("Draw degree days failed"),
+                        Err(e) => eprintln!("Error getting degree days from db: {}", e),
+                    }
+                },
+                Err(e) => eprintln!("Error getting daily temperatures from db: {}", e),
+            }
+        },
+        "spells" => {
+            match daily::get_daily_temps(&pool, city).await {
+                Ok(days) => {
+                    let by_rule: Vec<Vec<spells::Spell>> = spell_rules.iter().map(|rule| {
+                        let day_thresholds = spells::day_thresholds(&days, rule, baseline, spell_window);
+                        if day_thresholds.iter().any(|t| t.is_none()) {
+                            println!("{city} has calendar days without data in {}-{}, no events on those days", baseline.0, baseline.1);
+                        }
+                        spells::find_spells(&days, rule, &day_thresholds)
+                    }).collect();
+                    match spells::write_spells_csv(city, &spell_rules, &by_rule, unit) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing spells csv: {}", e),
+                    }
+                    match spells::write_spell_counts_csv(city, &spell_rules, &by_rule, unit) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing spells per year csv: {}", e),
+                    }
+                    spells::draw_spell_timeline(city, first_year, last_year, &spell_rules, &by_rule, unit).expect("Draw spell timeline failed");
+                },
+                Err(e) => eprintln!("Error getting daily temperatures from db: {}", e),
+            }
+        },
+        "dtr" => {
+            match daily::get_daily_temps(&pool, city).await {
+                Ok(days) => match dtr::write_daily_dtr_csv(city, &dtr::daily_dtr(&days), unit) {
+                    Ok(file_name) => println!("Wrote {file_name}"),
+                    Err(e) => eprintln!("Error writing daily dtr csv: {}", e),
+                },
+                Err(e) => eprintln!("Error getting daily temperatures from db: {}", e),
+            }
+            match periods::get_all_temps(&pool, tperiod, &city_period).await {
+                Ok(rows) => {
+                    let years = periods::temps_by_year(period, &rows);
+                    match dtr::write_period_dtr_csv(city, period, &years, unit) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing {period} dtr csv: {}", e),
+                    }
+                    let annual = dtr::annual_dtr(&years, first_year, last_year);
+                    dtr::draw_dtr_trend(city, first_year, last_year, &annual, smoothing_years, unit).expect("Draw dtr trend failed");
+                },
+                Err(e) => eprintln!("Error getting {period} temperatures from db: {}", e),
+            }
+            // every city in city_names over its whole record
+            match list_cities(&pool).await {
+                Ok(city_list) => {
+                    let mut cities = Vec::new();
+                    for a_city in city_list {
+                        let c_name: String = a_city.get("name_of_city");
+                        match periods::get_all_temps(&pool, tperiod, &format!("{c_name}_{period}")).await {
+                            Ok(rows) => {
+                                let annual = dtr::annual_dtr(&periods::temps_by_year(period, &rows), i32::MIN, i32::MAX);
+                                cities.push((c_name, annual));
+                            },
+                            Err(e) => eprintln!("Skipping {c_name}, error getting {period} temperatures: {}", e),
+                        }
+                    }
+                    dtr::draw_dtr_comparison(&cities, smoothing_years, unit).expect("Draw dtr comparison failed");
+                },
+                Err(e) => eprintln!("Cities not found, {} ", e),
+            }
+        },
+        "qc" => {
+            match daily::get_daily_temps(&pool, city).await {
+                Ok(days) => {
+                    let ghcn_flags = match qc::daily_ghcn_flags(&pool, city).await {
+                        Ok(ghcn_flags) => ghcn_flags,
+                        Err(_) => { println!("{city} has no GHCN attribute columns, quality flags not checked");
+                                    Vec::new() },
+                    };
+                    let flags = qc::run_checks(&days, &ghcn_flags, &qc_options);
+                    for (check, count) in qc::count_by_check(&flags) {
+                        println!("{check}: {count} readings flagged");
+                    }
+                    if let Err(e) = qc::create_qc_table(&pool, city).await {
+                        eprintln!("Error creating qc table: {}", e);
+                    }
+                    match qc::store_flags(&pool, city, &flags).await {
+                        Ok(_) => println!("Stored {} qc flags for {city}", flags.len()),
+                        Err(e) => eprintln!("Error storing qc flags: {}", e),
+                    }
+                },
+                Err(e) => eprintln!("Error getting daily temperatures from db: {}", e),
+            }
+        },
+        "aggregate" => {
+            // rebuilds the {city}_{period}_agg tables from the daily table, run qc first to leave flagged readings out
+            match daily::get_daily_temps(&pool, city).await {
+                Ok(days) => {
+                    let days = match qc::get_flagged(&pool, city, &qc_exclude).await {
+                        Ok(flagged) => { println!("Leaving out {} flagged readings", flagged.len());
+                                         qc::exclude_flagged(&days, &flagged) },
+                        Err(e) => { eprintln!("Error getting qc flags, run qc first: {}", e);
+                                    days },
+                    };
+                    let days = if include_filled {
+                        match fill::get_fills(&pool, city).await {
+                            Ok(fills) => { println!("Adding {} estimated readings", fills.len());
+                                           fill::apply_fills(&days, &fills) },
+                            Err(e) => { eprintln!("Error getting fills, run fill first: {}", e);
+                                        days },
+                        }
+                    } else {
+                        days
+                    };
+                    let reported = days.iter().filter(|d| d.tmean().is_some_and(|(_, source)| source == daily::MeanSource::Reported)).count();
+                    println!("{reported} of {} days have a reported TAVG, the other means are (tmax + tmin) / 2", days.len());
+                    for agg_period in agg_periods {
+                        match aggregate::rebuild_period_table(&pool, city, agg_period, &days).await {
+                            Ok(rows) => println!("Rebuilt {} with {rows} rows, {}", aggregate::agg_table(city, agg_period), calendar::describe(agg_period)),
+                            Err(e) => eprintln!("Error rebuilding {agg_period} table: {}", e),
+                        }
+                    }
+                },
+                Err(e) => eprintln!("Error getting daily temperatures from db: {}", e),
+            }
+        },
+        "coverage" => {
+            // every city in city_names, each over its own first to last year
+            match list_cities(&pool).await {
+                Ok(city_list) => {
+                    for a_city in city_list {
+                        let c_name: String = a_city.get("name_of_city");
+                        match daily::get_daily_temps(&pool, &c_name).await {
+                            Ok(days) => {
+                                let readings = coverage::readings_by_date(&days);
+                                let Some((c_first_year, c_last_year)) = coverage::record_years(&readings) else {
+                                    println!("No daily data for {c_name}");
+                                    continue;
+                                };
+                                let months = coverage::monthly_coverage(&readings, c_first_year, c_last_year);
+                                let mut gaps = coverage::longest_gaps(&readings, "tmax", gap_count);
+                                gaps.extend(coverage::longest_gaps(&readings, "tmin", gap_count));
+                                for report in [coverage::write_coverage_text(&c_name, &months, &gaps, completeness),
+                                               coverage::write_coverage_csv(&c_name, &months, completeness),
+                                               coverage::write_gaps_csv(&c_name, &gaps)] {
+                                    match report {
+                                        Ok(file_name) => println!("Wrote {file_name}"),
+                                        Err(e) => eprintln!("Error writing coverage report: {}", e),
+                                    }
+                                }
+                                let filled = match fill::get_fills(&pool, &c_name).await {
+                                    Ok(fills) => fills.iter().map(|f| f.date).collect(),
+                                    Err(_) => std::collections::BTreeSet::new(), // fill hasn't been run for this city
+                                };
+                                coverage::draw_coverage(&c_name, &readings, &filled, c_first_year, c_last_year).expect("Draw coverage failed");
+                            },
+                            Err(e) => eprintln!("Error getting daily temperatures for {c_name}: {}", e),
+                        }
+                    }
+                },
+                Err(e) => eprintln!("Cities not found, {} ", e),
+            }
+        },
+        "stations" => {
+            match stations::read_stations_file(stations_file, "US") {
+                Ok(station_list) => {
+                    if let Err(e) = stations::create_stations_table(&pool).await {
+                        eprintln!("Error creating stations table: {}", e);
+                    }
+                    match stations::store_stations(&pool, &station_list).await {
+                        Ok(_) => println!("Stored {} stations", station_list.len()),
+                        Err(e) => eprintln!("Error storing stations: {}", e),
+                    }
+                },
+                Err(e) => eprintln!("Error reading {stations_file}: {}", e),
+            }
+        },
+        "fill" => {
+            match daily::get_daily_temps(&pool, city).await {
+                Ok(days) => {
+                    // flagged readings shouldn't anchor an estimate
+                    let days = match qc::get_flagged(&pool, city, &qc_exclude).await {
+                        Ok(flagged) => qc::exclude_flagged(&days, &flagged),
+                        Err(_) => days,
+                    };
+                    let neighbor_list = match stations::neighbor_cities(&pool, city, fill_max_km).await {
+                        Ok(neighbor_list) => neighbor_list,
+                        Err(e) => { eprintln!("Error finding neighbor cities, run stations first: {}", e);
+                                    Vec::new() },
+                    };
+                    let mut neighbors = Vec::new();
+                    for (neighbor, km) in neighbor_list {
+                        match daily::get_daily_temps(&pool, &neighbor).await {
+                            Ok(neighbor_days) => { println!("Neighbor {neighbor} {km:.0} km");
+                                                   neighbors.push((neighbor, neighbor_days)) },
+                            Err(e) => eprintln!("Error getting daily temperatures for {neighbor}: {}", e),
+                        }
+                    }
+                    let fills = fill::compute_fills(&days, &neighbors, &fill_options);
+                    let interpolated = fills.iter().filter(|f| f.provenance == "interpolated").count();
+                    println!("{interpolated} readings interpolated, {} from neighbors", fills.len() - interpolated);
+                    if let Err(e) = fill::create_fill_table(&pool, city).await {
+                        eprintln!("Error creating fill table: {}", e);
+                    }
+                    match fill::store_fills(&pool, city, &fills).await {
+                        Ok(_) => println!("Stored {} fills for {city}", fills.len()),
+                        Err(e) => eprintln!("Error storing fills: {}", e),
+                    }
+                },
+                Err(e) => eprintln!("Error getting daily temperatures from db: {}", e),
+            }
+        },
+        "import" => {
+            match import::read_cdo_csv(import_file) {
+                Ok((rows, skipped)) => {
+                    if skipped > 0 {
+                        println!("Skipped {skipped} rows of {import_file} without a readable DATE");
+                    }
+                    // the ledger only records imports that made it into the daily table
+                    if let Err(e) = import::create_daily_table(&pool, city).await {
+                        eprintln!("Error creating daily table, nothing imported: {}", e);
+                        return Ok(());
+                    }
+                    match import::store_daily_rows(&pool, city, &rows).await {
+                        Ok(_) => println!("Imported {} rows into {city}", rows.len()),
+                        Err(e) => { eprintln!("Error storing daily rows, no digests recorded: {}", e);
+                                    return Ok(()) },
+                    }
+                    let digests = ledger::station_year_digests(&rows);
+                    if let Err(e) = ledger::create_ledger_table(&pool).await {
+                        eprintln!("Error creating hash ledger: {}", e);
+                    }
+                    match ledger::store_digests(&pool, city, import_file, &digests).await {
+                        Ok(import_id) => println!("Recorded {} station-year digests as import {import_id}", digests.len()),
+                        Err(e) => eprintln!("Error storing digests: {}", e),
+                    }
+                },
+                Err(e) => eprintln!("Error reading {import_file}: {}", e),
+            }
+        },
+        "verify" => {
+            // nothing is written to the daily table, only the report
+            match import::read_cdo_csv(import_file) {
+                Ok((rows, _)) => {
+                    let fresh = ledger::station_year_digests(&rows);
+                    match ledger::last_import_digests(&pool, city).await {
+                        Ok(Some((import_id, recorded))) => {
+                            let changes = ledger::compare_digests(&recorded, &fresh);
+                            println!("{import_file} against import {import_id}: {} station-years recorded, {} in the file, {} differ", recorded.len(), fresh.len(), changes.len());
+                            for change in &changes {
+                                println!("  {} {} {}", change.station, change.year, change.kind);
+                            }
+                            match ledger::write_verify_csv(city, &changes) {
+                                Ok(file_name) => println!("Wrote {file_name}"),
+                                Err(e) => eprintln!("Error writing verify csv: {}", e),
+                            }
+                        },
+                        Ok(None) => println!("{city} has no imports in the hash ledger, nothing to verify against"),
+                        Err(e) => eprintln!("Error getting ledger digests: {}", e),
+                    }
+                },
+                Err(e) => eprintln!("Error reading {import_file}: {}", e),
+            }
+        },
+        "diff" => {
+            match (diff::load_version(&pool, city, diff_old).await, diff::load_version(&pool, city, diff_new).await) {
+                (Ok(old), Ok(new)) => {
+                    let diffs = diff::diff_rows(&old, &new);
+                    let years = diff::diffs_per_year(&diffs);
+                    println!("{diff_old} -> {diff_new}: {} observations differ", diffs.len());
+                    for (year, counts) in &years {
+                        println!("  {year}: {} added, {} removed, {} changed", counts.added, counts.removed, counts.changed);
+                    }
+                    match diff::write_diff_csv(city, &diffs, unit) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing diff csv: {}", e),
+                    }
+                    match diff::write_year_diff_csv(city, &years) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing diff per year csv: {}", e),
+                    }
+                    diff::draw_period_shifts(city, period, &diff::period_shifts(period, &old, &new), unit).expect("Draw period shifts failed");
+                },
+                (Err(e), _) => eprintln!("Error loading {diff_old}: {}", e),
+                (_, Err(e)) => eprintln!("Error loading {diff_new}: {}", e),
+            }
+        },
+        "homogenize" => {
+            // breaks are found on the month table, the adjustments are applied to every period table
+            match periods::get_all_temps(&pool, "tmonth", &format!("{city}_Month")).await {
+                Ok(rows) => {
+                    let (hi, low) = homogenize::annual_anomalies(&periods::temps_by_year("Month", &rows));
+                    let neighbor_list = match stations::neighbor_cities(&pool, city, fill_max_km).await {
+                        Ok(neighbor_list) => neighbor_list,
+                        Err(e) => { eprintln!("Error finding neighbor cities, testing {city} alone: {}", e);
+                                    Vec::new() },
+                    };
+                    let (mut ref_hi, mut ref_low, mut ref_names) = (Vec::new(), Vec::new(), Vec::new());
+                    for (neighbor, _) in &neighbor_list {
+                        if let Ok(neighbor_rows) = periods::get_all_temps(&pool, "tmonth", &format!("{neighbor}_Month")).await {
+                            let (n_hi, n_low) = homogenize::annual_anomalies(&periods::temps_by_year("Month", &neighbor_rows));
+                            ref_hi.push(n_hi);
+                            ref_low.push(n_low);
+                            ref_names.push(neighbor.clone());
+                        }
+                    }
+                    let reference = if ref_names.is_empty() { "none, absolute test".to_string() } else { format!("neighbors {}", ref_names.join(" ")) };
+                    let mut breaks = Vec::new();
+                    for (series, points, refs) in [("tmax", &hi, &ref_hi), ("tmin", &low, &ref_low)] {
+                        let tested = if refs.is_empty() { points.clone() } else { homogenize::difference_series(points, refs) };
+                        breaks.extend(homogenize::find_breakpoints(series, &tested, min_segment_years));
+                    }
+                    println!("{} breakpoints for {city}, reference {reference}", breaks.len());
+                    for b in &breaks {
+                        println!("  {} {}: {}{}{} (SNHT {:.1})", b.series, b.year, if b.magnitude < 0.0 { "" } else { "+" }, unit.format_delta(b.magnitude, 2), unit.symbol(), b.snht);
+                    }
+                    match homogenize::write_breakpoints_csv(city, &breaks, &reference, unit) {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Error writing breakpoints csv: {}", e),
+                    }
+                    homogenize::draw_homogenized(city, &[("tmax", hi), ("tmin", low)], &breaks, unit).expect("Draw homogenized failed");
+                    for adj_period in ["Week", "Fort", "Month"] {
+                        let rows_result = periods::get_all_temps(&pool, periods::period_column(adj_period), &format!("{city}_{adj_period}")).await;
+                        match rows_result {
+                            Ok(period_rows) => {
+                                let adjusted = homogenize::adjust_years(&periods::temps_by_year(adj_period, &period_rows), &breaks);
+                                if let Err(e) = homogenize::create_adjusted_table(&pool, city, adj_period).await {
+                                    eprintln!("Error creating adjusted table: {}", e);
+                                }
+                                match homogenize::store_adjusted(&pool, city, adj_period, &adjusted).await {
+                                    Ok(_) => println!("Stored {city}_{}_adj", adj_period.to_lowercase()),
+                                    Err(e) => eprintln!("Error storing adjusted {adj_period} temps: {}", e),
+                                }
+                            },
+                            Err(e) => eprintln!("Error getting {adj_period} temperatures from db: {}", e),
+                        }
+                    }
+                },
+                Err(e) => eprintln!("Error getting Month temperatures from db: {}", e),
+            }
+        },
+        "precip" => {
+            // totals tables for every period, bar and anomaly charts for the selected one
+            match precip::get_daily_precip(&pool, city).await {
+                Ok(days) => {
+                    for precip_period in ["Week", "Fort", "Month"] {
+                        match precip::rebuild_precip_table(&pool, city, precip_period, &days, precip_min_coverage).await {
+                            Ok(rows) => println!("Rebuilt {city}_precip_{} with {rows} rows", precip_period.to_lowercase()),
+                            Err(e) => eprintln!("Error rebuilding {precip_period} precipitation table: {}", e),
+                        }
+                    }
+                    for element in precip_elements {
+                        let years = precip::values_by_year(period, &days, element, precip_min_coverage);
+                        let short = precip::short_by_year(period, &days, element, precip_min_coverage);
+                        let short_count = short.values().flatten().filter(|s| **s).count();
+                        if short_count > 0 {
+                            println!("{short_count} {period} {element} buckets have under {:.0}% of days reported and are left out", precip_min_coverage * 100.0);
+                        }
+                        let biggest = years.values().flatten().flatten().fold(0.0_f64, |big, v| big.max(*v));
+                        match years.get(&first_year) {
+                            Some(values) => precip::draw_precip_chart(city, period, first_year, element, values, short.get(&first_year).map(|s| s.as_slice()).unwrap_or(&[]), biggest).expect("Draw precipitation failed"),
+                            None => println!("No {element} for {city} in {first_year}"),
+                        }
+                        if let Err(e) = precip::draw_precip_anomaly_charts(city, period, element, &years, &short, first_year, last_year, baseline) {
+                            eprintln!("Error drawing {element} anomaly charts: {}", e);
+                        }
+                    }
+                },
+                Err(e) => eprintln!("Error getting daily precipitation from db, run import first: {}", e),
+            }
+        },
+        "export" => {
+            // period averages of every city and period into one csv and one json, schema in export.rs
+            let cities: Vec<String> = if export_cities.is_empty() {
+                match list_cities(&pool).await {
+                    Ok(city_list) => city_list.iter().map(|a_city| a_city.get("name_of_city")).collect(),
+                    Err(e) => { eprintln!("Cities not found, {} ", e);
+                                Vec::new() },
+                }
+            } else {
+                export_cities.iter().map(|c| c.to_string()).collect()
+            };
+            let mut rows = Vec::new();
+            for c_name in &cities {
+                for export_period in export_periods {
+                    match export::export_rows(&pool, c_name, export_period, aggregated, export_years.0, export_years.1).await {
+                        Ok(period_rows) => { println!("{} {export_period} buckets for {c_name}", period_rows.len());
+                                             rows.extend(period_rows) },
+                        Err(e) => eprintln!("Skipping {c_name} {export_period}, error getting averages: {}", e),
+                    }
+                }
+            }
+            let name = if cities.len() == 1 { cities[0].as_str() } else { "cities" };
+            match export::write_export_csv(name, &rows, unit) {
+                Ok(file_name) => println!("Wrote {file_name}"),
+                Err(e) => eprintln!("Error writing export csv: {}", e),
+            }
+            match export::write_export_json(name, &rows, unit) {
+                Ok(file_name) => println!("Wrote {file_name}"),
+                Err(e) => eprintln!("Error writing export json: {}", e),
+            }
+        },
+        "parquet_export" => {
+            // daily and period tables of every city in city_names
+            let cities: Vec<String> = match list_cities(&pool).await {
+                Ok(city_list) => city_list.iter().map(|a_city| a_city.get("name_of_city")).collect(),
+                Err(e) => { eprintln!("Cities not found, {} ", e);
+                            Vec::new() },
+            };
+            for c_name in &cities {
+                match dataset::export_daily(&pool, dataset_dir, c_name).await {
+                    Ok(file_name) => println!("Wrote {file_name}"),
+                    Err(e) => eprintln!("Skipping {c_name} daily, error writing parquet: {}", e),
+                }
+                for dataset_period in dataset_periods {
+                    match dataset::export_period(&pool, dataset_dir, c_name, dataset_period).await {
+                        Ok(file_name) => println!("Wrote {file_name}"),
+                        Err(e) => eprintln!("Skipping {c_name} {dataset_period}, error writing parquet: {}", e),
+                    }
+                }
+            }
+        },
+        "parquet_import" => {
+            // replaces the tables of every partition found in dataset_dir
+            match dataset::partitions(dataset_dir) {
+                Ok(partitions) => for (c_name, dataset_period, file) in partitions {
+                    let imported = if dataset_period == dataset::DAILY {
+                        dataset::import_daily(&pool, &c_name, &file).await
+                    } else {
+                        dataset::import_period(&pool, &c_name, &dataset_period, &file).await
+                    };
+                    match imported {
+                        Ok(count) => println!("Loaded {count} {dataset_period} rows for {c_name} from {}", file.display()),
+                        Err(e) => eprintln!("Skipping {}, error importing: {}", file.display(), e),
+                    }
+                },
+                Err(e) => eprintln!("Error reading {dataset_dir}: {}", e),
+            }
+        },
+        _ => {
+            let (chart_table, file_suffix, title_suffix) = if adjusted {
+                (format!("{city}_{period}_adj"), "_adj", ", Adjusted")
+            } else if aggregated {
+                (city_period.clone(), "_agg", ", Rebuilt")
+            } else {
+                (city_period.clone(), "", "")
+            };
+            let file_name = format!("imgs/{city}_{first_year}_{period}{file_suffix}{}.png", unit.file_suffix());
+            let title_text = format!("{first_year} {city}  {} Avg Temperatures{title_suffix} ({})", title_period(period), unit.symbol());
 
-    // Draw title
-    draw_title(&dwg, &title_text, title_style).expect("Failed to draw title");
+            let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+            dwg.fill(&WHITE).expect("Failed to fill dwg"); //this automatically makes a rectangle size of drawing area and fills it with white
 
-    // Draw axis labels
-    draw_axis_labels(&dwg, x_axis_style, y_axis_style, period, y_lowest, y_highest, y_range).expect("Failed to draw axis labels");
+            // Draw axes, grids, title and axis labels
+            draw_chart_base(&dwg, &title_text, period, &y_scale).expect("Failed to draw chart base");
 
-    
-    let fn_result: Result<Vec<sqlx::mysql::MySqlRow>, sqlx::Error> = get_temps(&pool, tperiod, &city_period, first_year).await;
-    match fn_result {
-        Ok(_) => { 
-            print_avgs(period, &city_period, first_year, &fn_result.as_ref().unwrap());
-            draw_hi_temps(&dwg, period, zero_line_offset, pixel_per_degree, &fn_result.as_ref().unwrap()).expect("Draw Hi Temps Failed"); 
-            draw_low_temps(&dwg, period, zero_line_offset, pixel_per_degree, &fn_result.as_ref().unwrap()).expect("Draw Low Temps Failed");
-        }
-        Err(e) => eprintln!("Error getting temperatures from db: {}", e),
+            let fn_result: Result<Vec<sqlx::mysql::MySqlRow>, sqlx::Error> = get_temps(&pool, tperiod, &chart_table, first_year).await;
+            match fn_result {
+                Ok(_) => { 
+                    print_avgs(period, &chart_table, first_year, fn_result.as_ref().unwrap());
+                    draw_series_temps(&dwg, period, &y_scale, fn_result.as_ref().unwrap(), &temp_series, unit).expect("Draw Temps Failed");
+                }
+                Err(e) => eprintln!("Error getting temperatures from db: {}", e),
+            }
+            dwg.present().expect("Failed Chart drawing");
+        },
     }
      //the following functions seem to work fine and don't need more testing
     /*let city_list_result: Result<Vec<MySqlRow>, sqlx::Error> = list_cities(&pool).await;
@@ -159,168 +804,151 @@ async fn main() -> Result<(), sqlx::Error> {
         Err(e) => eprint!("Error creating subtables: {e}"),
     }
 */
-    dwg.present().expect("Failed Chart drawing");
 
     Ok(())
 }
-// ======================================================
 
-fn draw_hi_temps(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, z_line_offset: f64,  pixel_per_degree: f64, rows: &Vec<MySqlRow>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut y_adj: i32;
+/// Y axis scale for one city, shared by every chart of that city so they can be compared
+#[derive(Clone, Copy)]
+struct YScale {
+    lowest: i32,
+    highest: i32,
+    range: i32,
+    pixel_per_degree: f64,
+    zero_line_offset: f64,
+}
+
+// city_names holds °F, so do the 10 below / 5 above margins
+fn calc_y_scale(city_low: i32, city_high: i32, unit: Unit) -> YScale {
+    let y_lowest = (unit.temp(f64::from(city_low)) - unit.delta(10.0)).floor() as i32;
+    let y_highest = (unit.temp(f64::from(city_high)) + unit.delta(5.0)).ceil() as i32;
+    let y_range =  y_highest - y_lowest; //neg y_lowest increases y_range
+    let pixel_per_degree: f64 = f64::from(AXIS_HEIGHT) / f64::from(y_range);
+    let zero_line_offset = if y_lowest < 0  { 
+        (f64::from(y_lowest) * pixel_per_degree).abs()
+    } else if y_lowest == 0 {
+        0.0
+    } else {
+        let z_diff = 0 - y_lowest -1;
+        f64::from(z_diff) * pixel_per_degree
+    };
+    println!("Axis Height: {AXIS_HEIGHT} Y range: {y_range} {}. Pixels per degree: {pixel_per_degree}. Zero offset: {zero_line_offset}", unit.symbol());
+    YScale { lowest: y_lowest, highest: y_highest, range: y_range, pixel_per_degree, zero_line_offset }
+}
+
+fn title_period(period: &str) -> &'static str {
     match period {
-        "Week" => {    
-            for i in 1..53 {
-                let x = i * (AXIS_WIDTH / 52) + LEFT_MARGIN;
-                let idx: usize = i.try_into().unwrap();
-                let tmp: i32; // get ready to hold the hi_temp to display
-                let hi_result = rows[idx-1].try_get("tmax");
-                match hi_result {
-                    Ok(_) => { tmp = hi_result.unwrap(); } //set tmp to hi_temp
-                    Err(_) => { continue; }
-                }
-                let y: f64 = f64::from(tmp) * pixel_per_degree; //calc how tall this line should be
-                if z_line_offset <= 0.0 { // negative offsets are temps above 0 degrees F
-                    y_adj = ((y + z_line_offset) + pixel_per_degree).round() as i32;                   
-                } else {
-                    y_adj = (y + z_line_offset).round() as i32;
-                }
-                //println!("BOTTOM_LINE: {BOTTOM_LINE_Y}  Bar length: {y}  zero line: {z_line_offset}  y_adj: {y_adj}");
-                dwg.draw(&Rectangle::new(
-                    [(x, BOTTOM_LINE_Y - 2), (x+8, BOTTOM_LINE_Y - y_adj)],
-                    Into::<ShapeStyle>::into(&RED).filled(),
-                ))?
-            }   
-        },
-        "Fort" => {    
-            for i in 1..27 {
-                let x = i * (AXIS_WIDTH / 26) + LEFT_MARGIN - 16;//-16 is a fundge factor to position bars correctly
-                let idx: usize = i.try_into().unwrap();
-                let tmp: i32;
-                let hi_result = rows[idx-1].try_get("tmax");
-                match hi_result {
-                    Ok(_) => { tmp = hi_result.unwrap(); }
-                    Err(_) => { continue; }
-                }
-                let y: f64 = f64::from(tmp) * pixel_per_degree;
-                if z_line_offset <= 0.0 { // negative offsets are temps above 0 degrees F
-                    y_adj = ((y + z_line_offset) + pixel_per_degree).round() as i32;                   
-                } else {
-                    y_adj = (y + z_line_offset).round() as i32;
-                }
-                //let y_adj = ((y + z_line_offset) + pixel_per_degree).round() as i32;
-                //println!("BOTTOM_LINE: {BOTTOM_LINE_Y}  Bar length: {y}  zero line: {z_line_offset}  y_adj: {y_adj}");
-                dwg.draw(&Rectangle::new(
-                    [(x, BOTTOM_LINE_Y - 2), (x+18, BOTTOM_LINE_Y - y_adj)],
-                    Into::<ShapeStyle>::into(&RED).filled(),
-                ))?
-            }   
-        },
-        "Month" => {
-            for i in 1..13 {
-                let x = i * (AXIS_WIDTH / 12) + LEFT_MARGIN - 50; //-50 is a fundge factor to position bars correctly
-                let idx: usize = i.try_into().unwrap();
-                let tmp: i32;
-                let hi_result = rows[idx-1].try_get("tmax");
-                match hi_result {
-                    Ok(_) => { tmp = hi_result.unwrap(); }
-                    Err(_) => { continue; }
-                }
-                let y: f64 = f64::from(tmp) * pixel_per_degree;
-                if z_line_offset <= 0.0 { // negative offsets are temps above 0 degrees F
-                    y_adj = ((y + z_line_offset) + pixel_per_degree).round() as i32;                   
-                } else {
-                    y_adj = (y + z_line_offset).round() as i32;
-                }
-                //let y_adj = ((y + z_line_offset) + pixel_per_degree).round() as i32;
-                //println!("BOTTOM_LINE: {BOTTOM_LINE_Y}  Bar length: {y}  zero line: {z_line_offset}  y_adj: {y_adj}");
-                dwg.draw(&Rectangle::new(
-                    [(x, BOTTOM_LINE_Y - 2), (x+30, BOTTOM_LINE_Y - y_adj)], //2nd y, bigger number = shorter bars
-                    Into::<ShapeStyle>::into(&RED).filled(),
-                ))?;
-            }
-        },
-        _ => println!("Unknown Period"),
+        "Week" => "Weekly",
+        "Fort" => "Fortnightly",
+        "Month" => "Montly",
+        "Week53" => "Weekly (53)",
+        "IsoWeek" => "ISO Weekly",
+        "Fort27" => "Fortnightly (27)",
+        "Season" => "Seasonal",
+        _ => "Unknown Period",
     }
+}
+
+// Everything on a chart except the temperature bars
+fn draw_chart_base(dwg: &DrawingArea<BitMapBackend, Shift>, title_text: &str, period: &str, y_scale: &YScale) -> Result<(), Box<dyn std::error::Error>> {
+    let title_style = ("sans-serif", 36).into_font().color(&BLACK);
+    let x_axis_style = ("sans-serif", 14).into_font().color(&BLACK);
+    let y_axis_style = ("sans-serif", 18).into_font().color(&BLACK);
+
+    // Draw axis lines on the drawing area
+    draw_axes(dwg)?;
+    
+    // Draw horizontal and verticlal grid lines with tick marks
+    draw_grids(dwg)?;
+
+    // Draw title
+    draw_title(dwg, title_text, title_style)?;
+
+    // Draw axis labels
+    draw_axis_labels(dwg, x_axis_style, y_axis_style, period, y_scale.lowest, y_scale.highest, y_scale.range)?;
     Ok(())
 }
+// ======================================================
 
-fn draw_low_temps(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, z_line_offset: f64, pixel_per_degree: f64, rows: &Vec<MySqlRow>) -> Result<(), Box<dyn std::error::Error>>  {
-    let mut y_adj: i32;
+fn draw_hi_temps(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, y_scale: &YScale, rows: &[MySqlRow], unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    draw_temp_bars(dwg, period, "tmax", RED, y_scale, rows, |t| unit.temp(t))
+}
+
+fn draw_low_temps(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, y_scale: &YScale, rows: &[MySqlRow], unit: Unit) -> Result<(), Box<dyn std::error::Error>>  {
+    draw_temp_bars(dwg, period, "tmin", GREEN, y_scale, rows, |t| unit.temp(t))
+}
+
+fn draw_mean_temps(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, y_scale: &YScale, rows: &[MySqlRow], unit: Unit) -> Result<(), Box<dyn std::error::Error>>  {
+    draw_temp_bars(dwg, period, "tmean", MEAN_COLOR, y_scale, rows, |t| unit.temp(t))
+}
+
+// the picked series, always hi then mean then low so the shorter bars are drawn over the taller ones
+fn draw_series_temps(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, y_scale: &YScale, rows: &[MySqlRow], series: &[&str], unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    for name in daily::SERIES.iter().filter(|s| series.contains(s)) {
+        match *name {
+            "tmax" => draw_hi_temps(dwg, period, y_scale, rows, unit)?,
+            "tmean" => draw_mean_temps(dwg, period, y_scale, rows, unit)?,
+            _ => draw_low_temps(dwg, period, y_scale, rows, unit)?,
+        }
+    }
+    Ok(())
+}
+
+// one filled bar per row, placed by the row's bucket column (tweek, tfort ...) so missing buckets leave a gap
+// instead of shifting the later bars. convert takes the stored °F value to the y scale's unit
+fn draw_temp_bars(dwg: &DrawingArea<BitMapBackend, Shift>, period: &str, column: &str, color: RGBColor, y_scale: &YScale, rows: &[MySqlRow], convert: impl Fn(f64) -> f64) -> Result<(), Box<dyn std::error::Error>> {
+    let buckets = calendar::bucket_count(period) as i32;
+    if buckets == 0 {
+        println!("Unknown Period");
+    }
+    for row in rows {
+        let Ok(bucket) = row.try_get::<i32, _>(calendar::period_column(period)) else { continue; };
+        if bucket < 1 || bucket > buckets {
+            continue;
+        }
+        let Some((x, width)) = bar_x_width(period, bucket) else { continue; };
+        let tmp: i32 = match row.try_get(column) {
+            Ok(temp) => temp,
+            Err(_) => continue,
+        };
+        let y_adj = bar_height(convert(f64::from(tmp)), y_scale.zero_line_offset, y_scale.pixel_per_degree);
+        //println!("BOTTOM_LINE: {BOTTOM_LINE_Y}  zero line: {}  y_adj: {y_adj}", y_scale.zero_line_offset);
+        dwg.draw(&Rectangle::new(
+            [(x, BOTTOM_LINE_Y - 2), (x + width, BOTTOM_LINE_Y - y_adj)], //2nd y, bigger number = shorter bars
+            Into::<ShapeStyle>::into(&color).filled(),
+        ))?;
+    }
+    Ok(())
+}
+
+// x position and width of the bar for bucket i (1 based). Week, Fort and Month keep the spacing the charts
+// always had (-16 and -50 are fudge factors to position bars correctly), other periods get one evenly
+// spaced slot per bucket with the bar centered in it
+fn bar_x_width(period: &str, i: i32) -> Option<(i32, i32)> {
     match period {
-        "Week" => {
-            for i in 1..53 {
-                let x = i * (AXIS_WIDTH / 52) +  LEFT_MARGIN;
-                let idx: usize = i.try_into().unwrap();
-                //let tmp: i32 = rows[idx-1].get("tmin");
-                let tmp: i32;
-                let low_result = rows[idx-1].try_get("tmin");
-                match low_result {
-                    Ok(_) =>  { tmp = low_result.unwrap(); }
-                    Err(_) => { continue; }
-                }
-                let y: f64 = f64::from(tmp) * pixel_per_degree;
-                if z_line_offset <= 0.0 { // negative offsets are temps above 0 degrees F
-                    y_adj = ((y + z_line_offset) + pixel_per_degree).round() as i32;                   
-                } else {
-                    y_adj = (y + z_line_offset).round() as i32;
-                }
-                //println!("bottom line: {BOTTOM_LINE_Y}  Bar length: {y}  zero line: {z_line_offset}  y_adj: {y_adj}");
-                dwg.draw(&Rectangle::new(
-                    [(x, BOTTOM_LINE_Y - 2), (x+8, BOTTOM_LINE_Y - y_adj)],
-                    Into::<ShapeStyle>::into(&GREEN).filled(),
-                ))?
-            }
-        },
-        "Fort" => {
-            for i in 1..27 {
-                let x = i * (AXIS_WIDTH / 26) +  LEFT_MARGIN - 16;
-                let idx: usize = i.try_into().unwrap();
-                // let tmp: i32 = rows[idx-1].get("tmin");
-                let tmp: i32;
-                let low_result = rows[idx-1].try_get("tmin");
-                match low_result {
-                    Ok(_) =>  { tmp = low_result.unwrap(); }
-                    Err(_) => { continue; }
-                }
-                let y: f64 = f64::from(tmp) * pixel_per_degree;
-                if z_line_offset <= 0.0 { // negative offsets are temps above 0 degrees F
-                    y_adj = ((y + z_line_offset) + pixel_per_degree).round() as i32;                   
-                } else {
-                    y_adj = (y + z_line_offset).round() as i32;
-                }
-                //println!("bottom line: {BOTTOM_LINE_Y}  Bar length: {y}  zero line: {z_line_offset}  y_adj: {y_adj}");
-                dwg.draw(&Rectangle::new(
-                    [(x, BOTTOM_LINE_Y - 2), (x+18, BOTTOM_LINE_Y - y_adj)], //2nd y, bigger number = shorter bars
-                    Into::<ShapeStyle>::into(&GREEN).filled(),
-                ))?
-            }
-        },
-        "Month" => {
-            for i in 1..13 {
-                let x = i * (AXIS_WIDTH / 12) + LEFT_MARGIN - 50;
-                let idx: usize = i.try_into().unwrap();
-                let tmp: i32;
-                let low_result = rows[idx-1].try_get("tmin");
-                match low_result {
-                    Ok(_) =>  { tmp = low_result.unwrap(); }
-                    Err(_) => { continue; }
-                }
-                let y: f64 = f64::from(tmp) * pixel_per_degree;
-                if z_line_offset <= 0.0 { // negative offsets are temps above 0 degrees F
-                    y_adj = ((y + z_line_offset) + pixel_per_degree).round() as i32;                   
-                } else {
-                    y_adj = (y + z_line_offset).round() as i32;
-                }
-                //println!("bottom line: {BOTTOM_LINE_Y}  Bar length: {y}  zero line: {z_line_offset}  y_adj: {y_adj}");
-                dwg.draw(&Rectangle::new(
-                    [(x, BOTTOM_LINE_Y - 2), (x+30, BOTTOM_LINE_Y - y_adj)], //2nd y, bigger number = shorter bars
-                    Into::<ShapeStyle>::into(&GREEN).filled(),
-                ))?;
+        "Week" => Some((i * (AXIS_WIDTH / 52) + LEFT_MARGIN, 8)),
+        "Fort" => Some((i * (AXIS_WIDTH / 26) + LEFT_MARGIN - 16, 18)),
+        "Month" => Some((i * (AXIS_WIDTH / 12) + LEFT_MARGIN - 50, 30)),
+        _ => {
+            let buckets = calendar::bucket_count(period) as i32;
+            if buckets == 0 {
+                return None;
             }
+            let slot = AXIS_WIDTH / buckets;
+            let width = (slot * 2 / 5).max(3);
+            Some(((i - 1) * slot + LEFT_MARGIN + (slot - width) / 2, width))
         },
-        _ => println!("Unknown Period"),
     }
-    Ok(())    
+}
+
+// pixel height of a bar for temp, same zero line handling as draw_hi_temps & draw_low_temps
+fn bar_height(temp: f64, z_line_offset: f64, pixel_per_degree: f64) -> i32 {
+    let y = temp * pixel_per_degree;
+    if z_line_offset <= 0.0 { // negative offsets are temps above 0 degrees F
+        ((y + z_line_offset) + pixel_per_degree).round() as i32
+    } else {
+        (y + z_line_offset).round() as i32
+    }
 }
 
 fn draw_axes(dwg: &DrawingArea<BitMapBackend, Shift>) -> Result<(), Box<dyn std::error::Error>> {
@@ -383,10 +1011,10 @@ fn draw_grids(dwg: &DrawingArea<BitMapBackend, Shift>) -> Result<(), Box<dyn std
 }
 
 fn draw_title(dwg: &DrawingArea<BitMapBackend, Shift>, title_text: &str, title_style: TextStyle) -> Result<(), Box<dyn std::error::Error>> {
-    let (title_width, title_height) = dwg.estimate_text_size(&title_text, &title_style)?;
+    let (title_width, title_height) = dwg.estimate_text_size(title_text, &title_style)?;
     
-    dwg.draw_text(&title_text, &title_style,
-        ((DWG_WIDTH / 2) as i32 - (title_width as i32 / 2), title_height as i32 - 10),
+    dwg.draw_text(title_text, &title_style,
+        ((DWG_WIDTH / 2) - (title_width as i32 / 2), title_height as i32 - 10),
     )?; 
     Ok(())
 }
@@ -400,7 +1028,7 @@ fn draw_axis_labels(dwg: &DrawingArea<BitMapBackend, Shift>,
                          y_range: i32) -> Result<(), Box<dyn std::error::Error>> {
     match period {
         "Week" => {
-            let (_x_label_width, x_label_height) = dwg.estimate_text_size(&format!("55"), &x_axis_style)?;
+            let (_x_label_width, x_label_height) = dwg.estimate_text_size("55", &x_axis_style)?;
             //println!("x_label_width: {}, x_label_height: {}", _x_label_width, x_label_height);
             for i in 1..53 {
                 let x = i * (AXIS_WIDTH / 52) + LEFT_MARGIN;
@@ -409,7 +1037,7 @@ fn draw_axis_labels(dwg: &DrawingArea<BitMapBackend, Shift>,
             }
         },
         "Fort" => {
-            let (_x_label_width, x_label_height) = dwg.estimate_text_size(&format!("55"), &x_axis_style)?;
+            let (_x_label_width, x_label_height) = dwg.estimate_text_size("55", &x_axis_style)?;
             //println!("x_label_width: {}, x_label_height: {}", _x_label_width, x_label_height);
             for i in 1..27 {
                 let x = i * (AXIS_WIDTH / 26) + LEFT_MARGIN - 15;
@@ -419,40 +1047,62 @@ fn draw_axis_labels(dwg: &DrawingArea<BitMapBackend, Shift>,
         },
         "Month" =>  {   
             for i   in 1..13 {
-                let month_abbr = match i {
-                    1 => "Jan",
-                    2 => "Feb",
-                    3 => "Mar",
-                    4 => "Apr",
-                    5 => "May",
-                    6 => "Jun",
-                    7 => "Jul",
-                    8 => "Aug",
-                    9 => "Sep",
-                    10 => "Oct",
-                    11 => "Nov",
-                    12 => "Dec",
-                    _ => "",
-                };
+                let month_abbr = month_abbr(i);
                 let x = i * (AXIS_WIDTH / 12) + LEFT_MARGIN - 45;
-                dwg.draw_text(&month_abbr, &x_axis_style, (x, AXIS_HEIGHT + TOP_MARGIN + 10))?;
+                dwg.draw_text(month_abbr, &x_axis_style, (x, AXIS_HEIGHT + TOP_MARGIN + 10))?;
+            }
+        },
+        _ => {
+            // centered under each bar, same labels the heatmap uses
+            let buckets = calendar::bucket_count(period);
+            if buckets == 0 {
+                println!("Unknown Period");
+            }
+            for i in 1..=buckets {
+                let Some((x, width)) = bar_x_width(period, i as i32) else { continue; };
+                let label = calendar::bucket_label(period, i);
+                let (label_width, _label_height) = dwg.estimate_text_size(&label, &x_axis_style)?;
+                dwg.draw_text(&label, &x_axis_style, (x + width / 2 - label_width as i32 / 2, AXIS_HEIGHT + TOP_MARGIN + 10))?;
             }
         },
-        _ => println!("Unknown Period"),
     }
 
+    draw_y_labels(dwg, &y_axis_style, y_highest, y_range)?;
+    Ok(())
+}
+
+// Temperature labels at the 10 horizontal grid lines, y_highest at the top
+fn draw_y_labels(dwg: &DrawingArea<BitMapBackend, Shift>, y_axis_style: &TextStyle, y_highest: i32, y_range: i32) -> Result<(), Box<dyn std::error::Error>> {
     // Draw Y Axis Label
-    let (y_label_width, y_label_height) = dwg.estimate_text_size(&format!("{}", y_highest), &y_axis_style)?;
+    let (y_label_width, y_label_height) = dwg.estimate_text_size(&format!("{}", y_highest), y_axis_style)?;
     let temp: f64 = y_range as f64 / 10.0;
     //let tenth_range: i32 = temp.round() as i32; // amount to adjust for each horizontal grid line
     let tenth_range = temp; // amount to adjust for each horizontal grid line
     for i in 0..10 {
         let y = f64::from(TOP_MARGIN) + (f64::from(i) * f64::from(AXIS_HEIGHT)) / 10.0;
         let i_str = format!("{:.1}", (f64::from(y_highest) - (tenth_range * f64::from(i))));
-        dwg.draw_text(&i_str, &y_axis_style, (LEFT_MARGIN - 24 - y_label_width as i32, y.round() as i32 - (y_label_height/2) as i32))?;
+        dwg.draw_text(&i_str, y_axis_style, (LEFT_MARGIN - 24 - y_label_width as i32, y.round() as i32 - (y_label_height/2) as i32))?;
     }
     Ok(())
 }
+fn month_abbr(month: i32) -> &'static str {
+    match month {
+        1 => "Jan",
+        2 => "Feb",
+        3 => "Mar",
+        4 => "Apr",
+        5 => "May",
+        6 => "Jun",
+        7 => "Jul",
+        8 => "Aug",
+        9 => "Sep",
+        10 => "Oct",
+        11 => "Nov",
+        12 => "Dec",
+        _ => "",
+    }
+}
+
 async fn get_city_min_max(pool: &Pool<MySql>, city: &str) -> Result<(i32, i32), sqlx::Error> {
     let query_string = format!("SELECT min_temp, max_temp FROM city_names WHERE name_of_city = '{}'", city); // Adjust table name as needed
     let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_string)
@@ -466,14 +1116,14 @@ async fn get_city_min_max(pool: &Pool<MySql>, city: &str) -> Result<(i32, i32),
 }
 
 async fn get_temps(pool: &Pool<MySql>, tperiod: &str, city: &str, year: i32) -> Result<Vec<MySqlRow>, sqlx::Error> {
-    let query_string = format!("SELECT tyear, {}, tmax, tmin FROM {} WHERE tyear = {}", tperiod, city, year ); // Adjust table name as needed
+    let query_string = format!("SELECT * FROM {} WHERE tyear = {} ORDER BY {}", city, year, tperiod ); // every column since older tables have no tmean, bars are drawn in row order
     let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_string)
         .fetch_all(pool)
         .await?; // had to make this function return a Result to use the ? operator
     Ok(rows)
 }
 
-fn print_avgs(tperiod: &str, city: &str, year: i32, rows: &Vec<MySqlRow>) {
+fn print_avgs(tperiod: &str, city: &str, year: i32, rows: &[MySqlRow]) {
     if rows.is_empty() {
         println!("No {} data found for {} in {}", tperiod, city, year);
         return;
@@ -483,7 +1133,7 @@ fn print_avgs(tperiod: &str, city: &str, year: i32, rows: &Vec<MySqlRow>) {
     let mut lo_temp: i32;
     for row in rows {
         let year: i32 = row.get("tyear");
-        let week: i32 = row.get(1); // use index instead of tmonth/tfort/tweek
+        let week: i32 = row.get(periods::period_column(tperiod)); // tmonth/tfort/tweek/tseason
         let hi_result = row.try_get("tmax");
         match hi_result {
             Ok(_) => { hi_temp = hi_result.unwrap(); }
@@ -494,16 +1144,22 @@ fn print_avgs(tperiod: &str, city: &str, year: i32, rows: &Vec<MySqlRow>) {
             Ok(_) =>  { lo_temp = low_result.unwrap(); }
             Err(_) => { lo_temp = -999; }
         }
-        println!("{}-{}: Avg Hi={}, Avg Lo={}", year, week, hi_temp, lo_temp);
+        let mean_text = match (row.try_get::<i32, _>("tmean"), row.try_get::<String, _>("tmean_source")) {
+            (Ok(mean), Ok(source)) => format!(", Mean={mean} ({source})"),
+            (Ok(mean), Err(_)) => format!(", Mean={mean}"),
+            _ => String::new(),
+        };
+        println!("{}-{}: Avg Hi={}, Avg Lo={}{}", year, week, hi_temp, lo_temp, mean_text);
     }
 }
 async fn list_cities(pool: &Pool<MySql>) -> Result<Vec<MySqlRow>, sqlx::Error> {
-    let query_string = format!("SELECT name_of_city FROM city_names"); 
-    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_string)
+    let query_string = "SELECT name_of_city FROM city_names"; 
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(query_string)
         .fetch_all(pool)
         .await?; 
     Ok(rows)
 }
+#[allow(dead_code)] // only used from the commented out maintenance block in main
 async fn drop_city_sub_tables(pool: &Pool<MySql>, city: &str) -> Result<(), sqlx::Error>{
     let city_sub_month = format!("{city}_month");
     let city_sub_fort = format!("{city}_fort"); 
@@ -515,6 +1171,7 @@ async fn drop_city_sub_tables(pool: &Pool<MySql>, city: &str) -> Result<(), sqlx
 
     Ok(())
 }
+#[allow(dead_code)] // only used from the commented out maintenance block in main
 async fn create_city_sub_tables(pool: &Pool<MySql>, city: &str) -> Result<(), sqlx::Error> {
     let city_sub_month = format!("{city}_month");
     let city_sub_fort = format!("{city}_fort"); 
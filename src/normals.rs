@@ -0,0 +1,172 @@
+// 30 year climate normals (1901-1930 ... 1991-2020) per bucket for every period table, stored in {city}_normals
+// with the number of years behind each value, and a chart overlaying the successive normals
+use sqlx::{MySql, Pool};
+use plotters::prelude::*;
+use plotters::element::DashedPathElement;
+
+use crate::{YScale, DWG_WIDTH, DWG_HEIGHT, BOTTOM_LINE_Y, bar_height, bar_x_width, draw_chart_base, title_period};
+use crate::heatmap::heat_color;
+use crate::periods::{self, BucketTemps};
+use crate::units::Unit;
+use crate::yearchart;
+
+pub const FIRST_NORMAL_START: i32 = 1901;
+pub const LAST_NORMAL_END: i32 = 2020;
+pub const NORMAL_YEARS: i32 = 30;
+
+/// One normal period's mean for every bucket plus how many years went into each mean
+pub struct Normal {
+    pub start_year: i32,
+    pub end_year: i32,
+    pub temps: BucketTemps,
+    pub tmax_years: Vec<i32>,
+    pub tmin_years: Vec<i32>,
+    pub tmean_years: Vec<i32>,
+}
+
+impl Normal {
+    /// tmax_years, tmin_years or tmean_years
+    pub fn years(&self, series: &str) -> &[i32] {
+        match series {
+            "tmax" => &self.tmax_years,
+            "tmin" => &self.tmin_years,
+            "tmean" => &self.tmean_years,
+            _ => &[],
+        }
+    }
+}
+
+// normals start every `step` years: 10 gives 1901-1930, 1911-1940 ...; 30 gives the non-overlapping 1901-1930, 1931-1960 ...
+pub fn normal_periods(step: i32) -> Vec<(i32, i32)> {
+    let step = step.max(1);
+    (0..).map(|i| FIRST_NORMAL_START + i * step)
+        .map(|start| (start, start + NORMAL_YEARS - 1))
+        .take_while(|(_, end)| *end <= LAST_NORMAL_END)
+        .collect()
+}
+
+pub fn compute_normals(period: &str, years: &std::collections::BTreeMap<i32, BucketTemps>, step: i32) -> Vec<Normal> {
+    let buckets = periods::bucket_count(period);
+    normal_periods(step).into_iter().map(|(start_year, end_year)| {
+        let count = |series: &str, idx: usize| -> i32 {
+            years.range(start_year..=end_year).filter(|(_, t)| t.series(series)[idx].is_some()).count() as i32
+        };
+        Normal {
+            start_year,
+            end_year,
+            temps: periods::mean_temps(years, buckets, start_year, end_year),
+            tmax_years: (0..buckets).map(|idx| count("tmax", idx)).collect(),
+            tmin_years: (0..buckets).map(|idx| count("tmin", idx)).collect(),
+            tmean_years: (0..buckets).map(|idx| count("tmean", idx)).collect(),
+        }
+    }).collect()
+}
+
+pub async fn create_normals_table(pool: &Pool<MySql>, city: &str) -> Result<(), sqlx::Error> {
+    let create_stmt = format!(r#"CREATE TABLE if NOT exists `{city}_normals` (
+  `period` varchar(10) NOT NULL,
+  `start_year` smallint(6) NOT NULL,
+  `end_year` smallint(6) NOT NULL,
+  `bucket` smallint(6) NOT NULL,
+  `tmax` double DEFAULT NULL,
+  `tmin` double DEFAULT NULL,
+  `tmax_years` smallint(6) NOT NULL,
+  `tmin_years` smallint(6) NOT NULL,
+  `tmean` double DEFAULT NULL,
+  `tmean_years` smallint(6) NOT NULL,
+  PRIMARY KEY (`period`, `start_year`, `bucket`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#);
+    let _result = sqlx::query(&create_stmt).execute(pool).await?;
+    Ok(())
+}
+
+// replaces whatever was stored before for this city and period
+pub async fn store_normals(pool: &Pool<MySql>, city: &str, period: &str, normals: &[Normal]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!("DELETE FROM `{city}_normals` WHERE period = ?")).bind(period).execute(&mut *tx).await?;
+    let insert_stmt = format!("INSERT INTO `{city}_normals` (period, start_year, end_year, bucket, tmax, tmin, tmax_years, tmin_years, tmean, tmean_years) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)");
+    for normal in normals {
+        for idx in 0..normal.temps.tmax.len() {
+            sqlx::query(&insert_stmt)
+                .bind(period)
+                .bind(normal.start_year)
+                .bind(normal.end_year)
+                .bind(idx as i32 + 1)
+                .bind(normal.temps.tmax[idx])
+                .bind(normal.temps.tmin[idx])
+                .bind(normal.tmax_years[idx])
+                .bind(normal.tmin_years[idx])
+                .bind(normal.temps.tmean[idx])
+                .bind(normal.tmean_years[idx])
+                .execute(&mut *tx).await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// (bucket index, point) split wherever a bucket is missing, so a line never bridges buckets that were left out
+fn consecutive_runs(points: &[(usize, (i32, i32))]) -> Vec<Vec<(i32, i32)>> {
+    let mut runs: Vec<Vec<(i32, i32)>> = Vec::new();
+    for (i, (idx, point)) in points.iter().enumerate() {
+        if i == 0 || points[i - 1].0 + 1 != *idx {
+            runs.push(Vec::new());
+        }
+        if let Some(run) = runs.last_mut() {
+            run.push(*point);
+        }
+    }
+    runs
+}
+
+/// Each normal as a line across the buckets, oldest blue through newest red. Hi solid, mean dotted, low dashed.
+/// Buckets with fewer than min_years behind them are left out and the line breaks there. y_scale is in unit, the normals °F
+pub fn draw_normals(city: &str, period: &str, y_scale: &YScale, normals: &[Normal], min_years: i32, series: &[&str], unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let file_name = format!("imgs/{city}_{period}_normals{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city}  {} 30 Year Normals ({})", title_period(period), unit.symbol());
+    draw_chart_base(&dwg, &title_text, period, y_scale)?;
+
+    let mut legend = Vec::new();
+    for (n, normal) in normals.iter().enumerate() {
+        let color = heat_color(n as f64, 0.0, (normals.len().max(2) - 1) as f64);
+        let converted = unit.temps(&normal.temps);
+        for name in series {
+            let (temps, years) = (converted.series(name), normal.years(name));
+            let points: Vec<(usize, (i32, i32))> = temps.iter().zip(years).enumerate()
+                .filter(|(_, (temp, count))| temp.is_some() && **count >= min_years)
+                .filter_map(|(idx, (temp, _))| {
+                    let (x, width) = bar_x_width(period, idx as i32 + 1)?;
+                    Some((idx, (x + width / 2, BOTTOM_LINE_Y - bar_height((*temp)?, y_scale.zero_line_offset, y_scale.pixel_per_degree))))
+                })
+                .collect();
+            for run in consecutive_runs(&points) {
+                match *name {
+                    "tmax" => { dwg.draw(&PathElement::new(run, color.stroke_width(2)))?; },
+                    "tmean" => { dwg.draw(&DashedPathElement::new(run, 3, 4, color.stroke_width(2)))?; },
+                    _ => { dwg.draw(&DashedPathElement::new(run, 10, 5, color.stroke_width(2)))?; },
+                }
+            }
+        }
+        let fewest = series.iter().flat_map(|name| normal.years(name)).min().copied().unwrap_or(0);
+        legend.push((format!("{}-{}  (fewest years in a bucket: {fewest})", normal.start_year, normal.end_year), color));
+    }
+    legend.push(("Solid: avg hi, dotted: avg mean, dashed: avg low".to_string(), RGBColor(255, 255, 255)));
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_break_at_missing_buckets() {
+        let points = [(0, (10, 1)), (1, (20, 2)), (3, (40, 4)), (4, (50, 5)), (7, (80, 8))];
+        assert_eq!(consecutive_runs(&points), vec![vec![(10, 1), (20, 2)], vec![(40, 4), (50, 5)], vec![(80, 8)]]);
+        assert!(consecutive_runs(&[]).is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+// Whole-record access to the {city}_{period} tables, for anything that needs more than one year at a time
+use std::collections::BTreeMap;
+use sqlx::{mysql::MySqlRow, MySql, Pool, Row};
+
+pub use crate::calendar::{bucket_count, period_column};
+
+/// Avg hi, low and mean temps for every bucket (week, fort, month or season) of one year, None where the table has no value.
+/// tmean is all None for the original tables, only the {city}_{period}_agg tables aggregate builds have the mean series
+#[derive(Clone, Debug)]
+pub struct BucketTemps {
+    pub tmax: Vec<Option<f64>>,
+    pub tmin: Vec<Option<f64>>,
+    pub tmean: Vec<Option<f64>>,
+}
+
+impl BucketTemps {
+    pub fn empty(buckets: usize) -> BucketTemps {
+        BucketTemps { tmax: vec![None; buckets], tmin: vec![None; buckets], tmean: vec![None; buckets] }
+    }
+
+    /// "tmax", "tmin" or "tmean"
+    pub fn series(&self, series: &str) -> &[Option<f64>] {
+        match series {
+            "tmax" => &self.tmax,
+            "tmin" => &self.tmin,
+            "tmean" => &self.tmean,
+            _ => &[],
+        }
+    }
+
+    /// Same conversion applied to every value of every series
+    pub fn map(&self, f: impl Fn(f64) -> f64) -> BucketTemps {
+        let convert = |values: &[Option<f64>]| values.iter().map(|v| v.map(&f)).collect();
+        BucketTemps { tmax: convert(&self.tmax), tmin: convert(&self.tmin), tmean: convert(&self.tmean) }
+    }
+}
+
+// every column, so tables without tmean (not rebuilt since it was added) still load
+pub async fn get_all_temps(pool: &Pool<MySql>, tperiod: &str, city_period: &str) -> Result<Vec<MySqlRow>, sqlx::Error> {
+    let query_string = format!("SELECT * FROM {city_period} ORDER BY tyear, {tperiod}");
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_string)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows)
+}
+
+// rows from get_all_temps keyed by year, bucket 1 is index 0
+pub fn temps_by_year(period: &str, rows: &[MySqlRow]) -> BTreeMap<i32, BucketTemps> {
+    let buckets = bucket_count(period);
+    let mut years: BTreeMap<i32, BucketTemps> = BTreeMap::new();
+    for row in rows {
+        let year: i32 = row.get("tyear");
+        let bucket: i32 = row.get(period_column(period));
+        if bucket < 1 || bucket as usize > buckets {
+            continue;
+        }
+        let idx = bucket as usize - 1;
+        let temps = years.entry(year).or_insert_with(|| BucketTemps::empty(buckets));
+        temps.tmax[idx] = row.try_get::<i32, _>("tmax").ok().map(f64::from);
+        temps.tmin[idx] = row.try_get::<i32, _>("tmin").ok().map(f64::from);
+        temps.tmean[idx] = row.try_get::<i32, _>("tmean").ok().map(f64::from);
+    }
+    years
+}
+
+/// Per bucket mean of the years first_year..=last_year, skipping missing values
+pub fn mean_temps(years: &BTreeMap<i32, BucketTemps>, buckets: usize, first_year: i32, last_year: i32) -> BucketTemps {
+    let mut means = BucketTemps::empty(buckets);
+    for idx in 0..buckets {
+        means.tmax[idx] = mean(years.range(first_year..=last_year).filter_map(|(_, t)| t.tmax.get(idx).copied().flatten()));
+        means.tmin[idx] = mean(years.range(first_year..=last_year).filter_map(|(_, t)| t.tmin.get(idx).copied().flatten()));
+        means.tmean[idx] = mean(years.range(first_year..=last_year).filter_map(|(_, t)| t.tmean.get(idx).copied().flatten()));
+    }
+    means
+}
+
+pub fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    if count == 0 { None } else { Some(sum / f64::from(count)) }
+}
+
+/// Each bucket's departure from the baseline, None unless both have a value
+pub fn departures(temps: &BucketTemps, baseline: &BucketTemps) -> BucketTemps {
+    let diff = |values: &[Option<f64>], base: &[Option<f64>]| -> Vec<Option<f64>> {
+        values.iter().zip(base).map(|(v, b)| Some((*v)? - (*b)?)).collect()
+    };
+    BucketTemps { tmax: diff(&temps.tmax, &baseline.tmax), tmin: diff(&temps.tmin, &baseline.tmin), tmean: diff(&temps.tmean, &baseline.tmean) }
+}
+
+/// Mean of every bucket in each year, (tmax, tmin). None for a series unless all of that year's buckets have a value,
+/// a year missing its winter months would otherwise look warm
+pub fn annual_means(years: &BTreeMap<i32, BucketTemps>) -> BTreeMap<i32, (Option<f64>, Option<f64>)> {
+    years.iter().map(|(year, temps)| (*year, (complete_mean(&temps.tmax), complete_mean(&temps.tmin)))).collect()
+}
+
+/// annual_means of one series, only the years it has a value
+pub fn annual_series(years: &BTreeMap<i32, BucketTemps>, series: &str) -> Vec<(i32, f64)> {
+    years.iter().filter_map(|(year, temps)| Some((*year, complete_mean(temps.series(series))?))).collect()
+}
+
+fn complete_mean(values: &[Option<f64>]) -> Option<f64> {
+    if values.is_empty() || values.iter().any(|v| v.is_none()) { None } else { mean(values.iter().flatten().copied()) }
+}
@@ -0,0 +1,279 @@
+// Precipitation elements from the daily table (PRCP, SNOW, SNWD in inches), bucketed with the same calendar
+// as the temperatures into {city}_precip_{period}. PRCP and SNOW are totals for the bucket; SNWD is a depth
+// on the ground so summing it means nothing, it gets the bucket's mean depth instead.
+// prcp_days, snow_days and snwd_days say how many days had a reading of each element, so a dry bucket (0.00)
+// and a missing one differ. A PRCP or SNOW total only counts when at least min_coverage of the bucket's days
+// reported, a month with 5 of 31 days isn't a drought. Those buckets are stored NULL and get a gray marker
+// on the charts.
+use std::collections::BTreeMap;
+use chrono::NaiveDate;
+use sqlx::{MySql, Pool, Row};
+use plotters::prelude::*;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT, bar_x_width, draw_chart_base, title_period, YScale, BOTTOM_LINE_Y};
+use crate::aggregate::{self, BucketSums};
+use crate::anomaly;
+use crate::calendar;
+use crate::daily;
+use crate::periods;
+use crate::yearchart;
+
+const PRECIP_COLOR: RGBColor = RGBColor(30, 80, 200);
+const SNOW_COLOR: RGBColor = RGBColor(120, 160, 220);
+const WET_COLOR: RGBColor = RGBColor(30, 130, 60);
+const DRY_COLOR: RGBColor = RGBColor(160, 100, 40);
+const SHORT_COLOR: RGBColor = RGBColor(150, 150, 150);
+
+#[derive(Clone, Debug)]
+pub struct DailyPrecip {
+    pub date: NaiveDate,
+    pub prcp: Option<f64>,
+    pub snow: Option<f64>,
+    pub snwd: Option<f64>,
+}
+
+impl DailyPrecip {
+    pub fn element(&self, element: &str) -> Option<f64> {
+        match element {
+            "prcp" => self.prcp,
+            "snow" => self.snow,
+            "snwd" => self.snwd,
+            _ => None,
+        }
+    }
+}
+
+/// Every day for the city in date order, an error if the table has no precipitation columns (run import)
+pub async fn get_daily_precip(pool: &Pool<MySql>, city: &str) -> Result<Vec<DailyPrecip>, sqlx::Error> {
+    let query_stmt_string = format!("SELECT tdate, prcp, snow, snwd FROM {city} ORDER BY tdate");
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_stmt_string)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().filter_map(|row| {
+        let tdate: &str = row.get("tdate");
+        Some(DailyPrecip {
+            date: daily::parse_tdate(tdate)?,
+            prcp: row.try_get("prcp").ok(), // NULL means not reported that day
+            snow: row.try_get("snow").ok(),
+            snwd: row.try_get("snwd").ok(),
+        })
+    }).collect())
+}
+
+pub fn element_name(element: &str) -> &'static str {
+    match element {
+        "prcp" => "Precipitation",
+        "snow" => "Snowfall",
+        "snwd" => "Snow Depth",
+        _ => "Unknown Element",
+    }
+}
+
+// total for prcp and snow, mean for snwd
+fn is_total(element: &str) -> bool {
+    element != "snwd"
+}
+
+/// Sums and reporting days of one element per bucket of every year
+pub fn precip_by_bucket(period: &str, days: &[DailyPrecip], element: &str) -> BTreeMap<i32, BucketSums> {
+    aggregate::sum_by_bucket(period, days.iter().filter_map(|d| Some((d.date, d.element(element)?))))
+}
+
+// true when a total for bucket idx (0 based) would be built from too few days, never for snwd's mean depth
+fn is_short(period: &str, year: i32, idx: usize, element: &str, days: i32, min_coverage: f64) -> bool {
+    let Some((start, end)) = calendar::bucket_range(period, year, idx + 1) else { return false; };
+    let calendar_days = (end - start).num_days() + 1;
+    is_total(element) && days > 0 && (f64::from(days) / calendar_days as f64) < min_coverage
+}
+
+/// The bucket values that get stored and charted, None where nothing was reported or a total is short of min_coverage
+pub fn bucket_values(period: &str, year: i32, element: &str, sums: &BucketSums, min_coverage: f64) -> Vec<Option<f64>> {
+    sums.sums.iter().zip(&sums.days).enumerate().map(|(idx, (sum, days))| {
+        if *days == 0 || is_short(period, year, idx, element, *days, min_coverage) {
+            return None;
+        }
+        Some(if is_total(element) { *sum } else { sum / f64::from(*days) })
+    }).collect()
+}
+
+/// bucket_values of every year for one element
+pub fn values_by_year(period: &str, days: &[DailyPrecip], element: &str, min_coverage: f64) -> BTreeMap<i32, Vec<Option<f64>>> {
+    precip_by_bucket(period, days, element).iter().map(|(year, sums)| (*year, bucket_values(period, *year, element, sums, min_coverage))).collect()
+}
+
+/// Which buckets of every year had readings but were left out by min_coverage
+pub fn short_by_year(period: &str, days: &[DailyPrecip], element: &str, min_coverage: f64) -> BTreeMap<i32, Vec<bool>> {
+    precip_by_bucket(period, days, element).iter().map(|(year, sums)| {
+        (*year, sums.days.iter().enumerate().map(|(idx, d)| is_short(period, *year, idx, element, *d, min_coverage)).collect())
+    }).collect()
+}
+
+pub async fn create_precip_table(pool: &Pool<MySql>, city: &str, period: &str) -> Result<(), sqlx::Error> {
+    let create_stmt = format!(r#"CREATE TABLE if NOT exists `{}_precip_{}` (
+  `id` int(11) NOT NULL,
+  `station` char(12) DEFAULT NULL,
+  `tyear` smallint(6) NOT NULL,
+  `{}` smallint(6) NOT NULL,
+  `prcp` double DEFAULT NULL,
+  `snow` double DEFAULT NULL,
+  `snwd` double DEFAULT NULL,
+  `prcp_days` smallint(6) NOT NULL,
+  `snow_days` smallint(6) NOT NULL,
+  `snwd_days` smallint(6) NOT NULL,
+  PRIMARY KEY (`id`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#, city, period.to_lowercase(), calendar::period_column(period));
+    let _result = sqlx::query(&create_stmt).execute(pool).await?;
+    Ok(())
+}
+
+/// Replaces everything in {city}_precip_{period}, rounded to hundredths like the daily values
+pub async fn rebuild_precip_table(pool: &Pool<MySql>, city: &str, period: &str, days: &[DailyPrecip], min_coverage: f64) -> Result<usize, sqlx::Error> {
+    let table = format!("{city}_precip_{}", period.to_lowercase());
+    let sums: Vec<BTreeMap<i32, BucketSums>> = ["prcp", "snow", "snwd"].iter().map(|e| precip_by_bucket(period, days, e)).collect();
+    let by_element: Vec<BTreeMap<i32, Vec<Option<f64>>>> = ["prcp", "snow", "snwd"].iter().map(|e| values_by_year(period, days, e, min_coverage)).collect();
+    let mut years: Vec<i32> = by_element.iter().flat_map(|e| e.keys().copied()).collect();
+    years.sort();
+    years.dedup();
+    create_precip_table(pool, city, period).await?;
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!("DELETE FROM `{table}`")).execute(&mut *tx).await?;
+    let insert_stmt = format!("INSERT INTO `{table}` (id, tyear, {}, prcp, snow, snwd, prcp_days, snow_days, snwd_days) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)", calendar::period_column(period));
+    let round = |v: Option<f64>| v.map(|v| (v * 100.0).round() / 100.0);
+    let mut id = 0;
+    for year in years {
+        let value = |element: usize, idx: usize| by_element[element].get(&year).and_then(|v| v.get(idx).copied().flatten());
+        let day_count = |element: usize, idx: usize| sums[element].get(&year).map(|s| s.days[idx]).unwrap_or(0);
+        for idx in 0..calendar::bucket_count(period) {
+            let (p, s, d) = (value(0, idx), value(1, idx), value(2, idx));
+            // short buckets keep their row so the day counts show why the value is NULL
+            if (0..3).all(|element| day_count(element, idx) == 0) {
+                continue;
+            }
+            id += 1;
+            sqlx::query(&insert_stmt)
+                .bind(id)
+                .bind(year)
+                .bind(idx as i32 + 1)
+                .bind(round(p))
+                .bind(round(s))
+                .bind(round(d))
+                .bind(day_count(0, idx))
+                .bind(day_count(1, idx))
+                .bind(day_count(2, idx))
+                .execute(&mut *tx).await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(id as usize)
+}
+
+/// Bars of one element for one year, every year of the city on the same scale (biggest bucket value). short marks
+/// the buckets left out for too few reporting days
+pub fn draw_precip_chart(city: &str, period: &str, year: i32, element: &str, values: &[Option<f64>], short: &[bool], biggest: f64) -> Result<(), Box<dyn std::error::Error>> {
+    let y_scale = yearchart::value_scale([0.0, biggest].into_iter(), 0.0);
+    let file_name = format!("imgs/{city}_{year}_{period}_{element}.png");
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let kind = if is_total(element) { "Total" } else { "Avg" };
+    let title_text = format!("{year} {city}  {} {kind} {} (in)", title_period(period), element_name(element));
+    draw_chart_base(&dwg, &title_text, period, &y_scale)?;
+    draw_precip_bars(&dwg, period, &y_scale, values, if element == "prcp" { PRECIP_COLOR } else { SNOW_COLOR })?;
+    draw_short_markers(&dwg, period, short)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+fn draw_precip_bars(dwg: &DrawingArea<BitMapBackend, plotters::coord::Shift>, period: &str, y_scale: &YScale, values: &[Option<f64>], color: RGBColor) -> Result<(), Box<dyn std::error::Error>> {
+    for (idx, value) in values.iter().enumerate() {
+        let (Some(value), Some((x, width))) = (value, bar_x_width(period, idx as i32 + 1)) else { continue; };
+        let height = (value * y_scale.pixel_per_degree + y_scale.zero_line_offset).round() as i32;
+        dwg.draw(&Rectangle::new(
+            [(x, BOTTOM_LINE_Y - 2), (x + width, BOTTOM_LINE_Y - height)],
+            Into::<ShapeStyle>::into(color).filled(),
+        ))?;
+    }
+    Ok(())
+}
+
+// small gray square on the bottom line under each bucket left out for too few reporting days
+fn draw_short_markers(dwg: &DrawingArea<BitMapBackend, plotters::coord::Shift>, period: &str, short: &[bool]) -> Result<(), Box<dyn std::error::Error>> {
+    for (idx, _) in short.iter().enumerate().filter(|(_, s)| **s) {
+        let Some((x, width)) = bar_x_width(period, idx as i32 + 1) else { continue; };
+        let side = width.clamp(4, 8);
+        let left = x + (width - side) / 2;
+        dwg.draw(&Rectangle::new(
+            [(left, BOTTOM_LINE_Y - 2 - side), (left + side, BOTTOM_LINE_Y - 2)],
+            Into::<ShapeStyle>::into(SHORT_COLOR).stroke_width(2),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Each year's bucket values minus the baseline mean of the bucket, wetter/snowier up in green and drier down in brown.
+/// Buckets in short are left out of the baseline already (they're None) and marked
+#[allow(clippy::too_many_arguments)]
+pub fn draw_precip_anomaly_charts(city: &str, period: &str, element: &str, years: &BTreeMap<i32, Vec<Option<f64>>>, short: &BTreeMap<i32, Vec<bool>>, first_year: i32, last_year: i32, baseline: (i32, i32)) -> Result<(), Box<dyn std::error::Error>> {
+    let buckets = calendar::bucket_count(period);
+    let base: Vec<Option<f64>> = (0..buckets).map(|idx| {
+        periods::mean(years.range(baseline.0..=baseline.1).filter_map(|(_, values)| values.get(idx).copied().flatten()))
+    }).collect();
+    if base.iter().any(|b| b.is_none()) {
+        println!("Baseline {}-{} is missing some {period} {element} buckets for {city}, those bars are left out", baseline.0, baseline.1);
+    }
+    let departures: Vec<(i32, Vec<Option<f64>>)> = years.range(first_year..=last_year).map(|(year, values)| {
+        (*year, values.iter().zip(&base).map(|(v, b)| Some((*v)? - (*b)?)).collect())
+    }).collect();
+    let y_scale = anomaly::symmetric_scale(departures.iter().flat_map(|(_, d)| d.iter()).flatten().copied(), 1);
+    let kind = if is_total(element) { "Total" } else { "Avg" };
+    for (year, departure) in &departures {
+        let file_name = format!("imgs/{city}_{year}_{period}_{element}_anomaly.png");
+        let title_text = format!("{year} {city}  {} {kind} {} vs {}-{} Baseline (in)", title_period(period), element_name(element), baseline.0, baseline.1);
+        let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+        dwg.fill(&WHITE)?;
+        draw_chart_base(&dwg, &title_text, period, &y_scale)?;
+        anomaly::draw_diverging_bars(&dwg, period, &y_scale, departure, WET_COLOR, DRY_COLOR)?;
+        draw_short_markers(&dwg, period, short.get(year).map(|s| s.as_slice()).unwrap_or(&[]))?;
+        dwg.present()?;
+    }
+    println!("Drew {} years of {period} {element} anomaly charts for {city}", departures.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Datelike;
+
+    // the first `count` days of a 2001 month, each with `prcp` and a snow depth of the day of the month
+    fn month_of_days(month: u32, count: usize, prcp: f64) -> Vec<DailyPrecip> {
+        NaiveDate::from_ymd_opt(2001, month, 1).unwrap().iter_days().take(count)
+            .map(|date| DailyPrecip { date, prcp: Some(prcp), snow: None, snwd: Some(f64::from(date.day())) })
+            .collect()
+    }
+
+    #[test]
+    fn totals_need_min_coverage() {
+        let mut days = month_of_days(1, 25, 0.5); // 25 of 31 days
+        days.extend(month_of_days(2, 20, 0.5));   // 20 of 28 days
+        days.extend(month_of_days(4, 1, 0.0));    // 1 dry day of 30
+        let values = &values_by_year("Month", &days, "prcp", 0.8)[&2001];
+        assert_eq!(values[0], Some(12.5));
+        assert_eq!(values[1], None);
+        assert_eq!(values[2], None); // no readings, not short
+        assert_eq!(values[3], None);
+        let short = &short_by_year("Month", &days, "prcp", 0.8)[&2001];
+        assert_eq!(&short[0..4], &[false, true, false, true]);
+        // coverage 0 keeps any bucket with a reading, the dry one as 0.0 rather than missing
+        let values = &values_by_year("Month", &days, "prcp", 0.0)[&2001];
+        assert_eq!((values[1], values[2], values[3]), (Some(10.0), None, Some(0.0)));
+    }
+
+    #[test]
+    fn snow_depth_is_a_mean_and_never_short() {
+        let days = month_of_days(2, 4, 0.0); // depths 1, 2, 3, 4
+        let values = &values_by_year("Month", &days, "snwd", 0.8)[&2001];
+        assert_eq!(values[1], Some(2.5));
+        assert!(short_by_year("Month", &days, "snwd", 0.8)[&2001].iter().all(|s| !s));
+    }
+}
@@ -0,0 +1,300 @@
+// Quality control of the raw daily {city} table before it is averaged. Each suspect reading becomes a row in
+// {city}_qc naming the day, the series and the check that caught it. Flags are only recorded here,
+// the daily table is never changed, aggregation decides whether to leave flagged readings out.
+use std::collections::{BTreeMap, HashSet};
+use chrono::{Datelike, NaiveDate};
+use sqlx::{MySql, Pool, Row};
+
+use crate::daily::{self, DailyTemp};
+use crate::periods;
+
+// beyond the US state records (134 Death Valley, -80 Prospect Creek AK) nothing is a real reading
+pub const LOWEST_PLAUSIBLE: i32 = -80;
+pub const HIGHEST_PLAUSIBLE: i32 = 135;
+
+#[derive(Clone, Copy, Debug)]
+pub struct QcOptions {
+    pub repeat_days: usize, // this many days in a row with the same value is a stuck sensor or a fill
+    pub jump_z: f64,        // day to day change this many standard deviations from the month's usual change
+}
+
+#[derive(Clone, Debug)]
+pub struct QcFlag {
+    pub date: NaiveDate,
+    pub series: &'static str, // "tmax" or "tmin"
+    pub check: &'static str,  // "tmin_gt_tmax", "implausible", "repeated", "duplicate_date", "jump", "ghcn_qflag"
+    pub value: Option<i32>,
+    pub detail: String,
+}
+
+/// Every check over days in date order. `ghcn_flags` are (date, series, flag letter) from daily_ghcn_flags
+pub fn run_checks(days: &[DailyTemp], ghcn_flags: &[(NaiveDate, &'static str, String)], options: &QcOptions) -> Vec<QcFlag> {
+    let mut flags = Vec::new();
+    for day in days {
+        if let (Some(hi), Some(low)) = (day.tmax, day.tmin) && low > hi {
+            for (series, value) in [("tmax", hi), ("tmin", low)] {
+                flags.push(QcFlag { date: day.date, series, check: "tmin_gt_tmax", value: Some(value), detail: format!("tmax {hi} tmin {low}") });
+            }
+        }
+        for (series, value) in [("tmax", day.tmax), ("tmin", day.tmin)] {
+            let Some(value) = value else { continue; };
+            if !(LOWEST_PLAUSIBLE..=HIGHEST_PLAUSIBLE).contains(&value) {
+                flags.push(QcFlag { date: day.date, series, check: "implausible", value: Some(value),
+                                    detail: format!("outside {LOWEST_PLAUSIBLE} to {HIGHEST_PLAUSIBLE}") });
+            }
+        }
+    }
+    flags.extend(duplicate_dates(days));
+    for series in ["tmax", "tmin"] {
+        let values = unique_series(days, series);
+        flags.extend(repeated_values(&values, series, options.repeat_days));
+        flags.extend(jumps(&values, series, options.jump_z));
+    }
+    let by_date: BTreeMap<NaiveDate, &DailyTemp> = days.iter().map(|d| (d.date, d)).collect();
+    for (date, series, flag) in ghcn_flags {
+        let value = by_date.get(date).and_then(|d| if *series == "tmax" { d.tmax } else { d.tmin });
+        flags.push(QcFlag { date: *date, series, check: "ghcn_qflag", value, detail: format!("GHCN quality flag {flag}") });
+    }
+    flags.sort_by(|a, b| (a.date, a.series, a.check).cmp(&(b.date, b.series, b.check)));
+    flags
+}
+
+// every copy of a date that appears more than once, there's no telling which one is right
+fn duplicate_dates(days: &[DailyTemp]) -> Vec<QcFlag> {
+    let mut by_date: BTreeMap<NaiveDate, Vec<&DailyTemp>> = BTreeMap::new();
+    for day in days {
+        by_date.entry(day.date).or_default().push(day);
+    }
+    let mut flags = Vec::new();
+    for (date, copies) in by_date.into_iter().filter(|(_, copies)| copies.len() > 1) {
+        for day in &copies {
+            for (series, value) in [("tmax", day.tmax), ("tmin", day.tmin)] {
+                flags.push(QcFlag { date, series, check: "duplicate_date", value, detail: format!("{} rows for this date", copies.len()) });
+            }
+        }
+    }
+    flags
+}
+
+// one value per date for the run and jump checks, duplicated dates are left out since they're flagged anyway
+fn unique_series(days: &[DailyTemp], series: &str) -> Vec<(NaiveDate, i32)> {
+    let mut counts: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+    for day in days {
+        *counts.entry(day.date).or_default() += 1;
+    }
+    days.iter()
+        .filter(|d| counts.get(&d.date) == Some(&1))
+        .filter_map(|d| Some((d.date, if series == "tmax" { d.tmax } else { d.tmin }?)))
+        .collect()
+}
+
+// runs of consecutive days with the same value, every day of the run is flagged
+fn repeated_values(values: &[(NaiveDate, i32)], series: &'static str, repeat_days: usize) -> Vec<QcFlag> {
+    let mut flags = Vec::new();
+    let mut start = 0;
+    for idx in 1..=values.len() {
+        let continues = idx < values.len()
+            && values[idx].1 == values[start].1
+            && values[idx - 1].0.succ_opt() == Some(values[idx].0);
+        if continues {
+            continue;
+        }
+        let run = &values[start..idx];
+        if repeat_days > 1 && run.len() >= repeat_days {
+            for (date, value) in run {
+                flags.push(QcFlag { date: *date, series, check: "repeated", value: Some(*value), detail: format!("{} days in a row of {value}", run.len()) });
+            }
+        }
+        start = idx;
+    }
+    flags
+}
+
+// change from the day before compared with every change in the same calendar month over the whole record
+fn jumps(values: &[(NaiveDate, i32)], series: &'static str, jump_z: f64) -> Vec<QcFlag> {
+    let changes: Vec<(NaiveDate, i32, f64)> = values.windows(2)
+        .filter(|pair| pair[0].0.succ_opt() == Some(pair[1].0))
+        .map(|pair| (pair[1].0, pair[1].1, f64::from(pair[1].1 - pair[0].1)))
+        .collect();
+    let mut by_month: BTreeMap<u32, Vec<f64>> = BTreeMap::new();
+    for (date, _, change) in &changes {
+        by_month.entry(date.month()).or_default().push(*change);
+    }
+    let climatology: BTreeMap<u32, (f64, f64)> = by_month.iter().filter_map(|(month, month_changes)| {
+        let mean = periods::mean(month_changes.iter().copied())?;
+        let variance = periods::mean(month_changes.iter().map(|c| (c - mean).powi(2)))?;
+        Some((*month, (mean, variance.sqrt())))
+    }).collect();
+    changes.iter().filter_map(|(date, value, change)| {
+        let (mean, sd) = climatology.get(&date.month())?;
+        if *sd == 0.0 {
+            return None;
+        }
+        let z = (change - mean) / sd;
+        (z.abs() > jump_z).then(|| QcFlag { date: *date, series, check: "jump", value: Some(*value), detail: format!("changed {change:+.0} from the day before, z {z:+.1}") })
+    }).collect()
+}
+
+/// GHCN quality flags (the Q in the M,Q,S,T attributes) for tables loaded from NOAA CDO csv files with
+/// tmax_attributes / tmin_attributes columns. Tables without those columns return an error the caller can skip
+pub async fn daily_ghcn_flags(pool: &Pool<MySql>, city: &str) -> Result<Vec<(NaiveDate, &'static str, String)>, sqlx::Error> {
+    let query_stmt_string = format!("SELECT tdate, tmax_attributes, tmin_attributes FROM {city} ORDER BY tdate");
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_stmt_string)
+        .fetch_all(pool)
+        .await?;
+    let mut flags = Vec::new();
+    for row in rows {
+        let tdate: &str = row.get("tdate");
+        let Some(date) = daily::parse_tdate(tdate) else { continue; };
+        for series in ["tmax", "tmin"] {
+            let attributes: Option<String> = row.try_get(format!("{series}_attributes").as_str()).ok();
+            let qflag = attributes.as_deref().and_then(|a| a.split(',').nth(1)).map(str::trim).unwrap_or("");
+            if !qflag.is_empty() {
+                flags.push((date, series, qflag.to_string()));
+            }
+        }
+    }
+    Ok(flags)
+}
+
+pub async fn create_qc_table(pool: &Pool<MySql>, city: &str) -> Result<(), sqlx::Error> {
+    let create_stmt = format!(r#"CREATE TABLE if NOT exists `{city}_qc` (
+  `id` int(11) NOT NULL,
+  `tdate` char(10) NOT NULL,
+  `series` char(4) NOT NULL,
+  `check_name` varchar(20) NOT NULL,
+  `value` smallint(6) DEFAULT NULL,
+  `detail` varchar(80) DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `tdate` (`tdate`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#);
+    let _result = sqlx::query(&create_stmt).execute(pool).await?;
+    Ok(())
+}
+
+// replaces every flag for the city, a rerun after the daily data is fixed clears the old flags
+pub async fn store_flags(pool: &Pool<MySql>, city: &str, flags: &[QcFlag]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!("DELETE FROM `{city}_qc`")).execute(&mut *tx).await?;
+    let insert_stmt = format!("INSERT INTO `{city}_qc` (id, tdate, series, check_name, value, detail) VALUES (?, ?, ?, ?, ?, ?)");
+    for (idx, flag) in flags.iter().enumerate() {
+        sqlx::query(&insert_stmt)
+            .bind(idx as i32 + 1)
+            .bind(flag.date.format("%Y-%m-%d").to_string())
+            .bind(flag.series)
+            .bind(flag.check)
+            .bind(flag.value)
+            .bind(&flag.detail)
+            .execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// (date, series) of every stored flag from one of the listed checks
+pub async fn get_flagged(pool: &Pool<MySql>, city: &str, checks: &[&str]) -> Result<HashSet<(NaiveDate, String)>, sqlx::Error> {
+    let query_stmt_string = format!("SELECT tdate, series, check_name FROM {city}_qc");
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_stmt_string)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().filter_map(|row| {
+        let check: String = row.get("check_name");
+        if !checks.contains(&check.as_str()) {
+            return None;
+        }
+        let tdate: &str = row.get("tdate");
+        Some((daily::parse_tdate(tdate)?, row.get::<String, _>("series")))
+    }).collect())
+}
+
+/// Copy of days with flagged readings set to None, as if they were never read
+pub fn exclude_flagged(days: &[DailyTemp], flagged: &HashSet<(NaiveDate, String)>) -> Vec<DailyTemp> {
+    days.iter().map(|day| DailyTemp {
+        date: day.date,
+        tmax: day.tmax.filter(|_| !flagged.contains(&(day.date, "tmax".to_string()))),
+        tmin: day.tmin.filter(|_| !flagged.contains(&(day.date, "tmin".to_string()))),
+        tavg: day.tavg, // qc doesn't check tavg, a computed mean leaves out the flagged hi or low on its own
+    }).collect()
+}
+
+/// Count of flags per check, for the console summary
+pub fn count_by_check(flags: &[QcFlag]) -> BTreeMap<&'static str, usize> {
+    let mut counts = BTreeMap::new();
+    for flag in flags {
+        *counts.entry(flag.check).or_default() += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(month: u32, dom: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2001, month, dom).unwrap()
+    }
+
+    fn day(date: NaiveDate, tmax: Option<i32>, tmin: Option<i32>) -> DailyTemp {
+        DailyTemp { date, tmax, tmin, tavg: None }
+    }
+
+    const OPTIONS: QcOptions = QcOptions { repeat_days: 4, jump_z: 4.0 };
+
+    fn checks(flags: &[QcFlag]) -> Vec<(NaiveDate, &'static str, &'static str)> {
+        flags.iter().map(|f| (f.date, f.series, f.check)).collect()
+    }
+
+    #[test]
+    fn inverted_and_implausible_readings() {
+        let days = [day(date(1, 1), Some(40), Some(45)), day(date(1, 2), Some(136), Some(-80)), day(date(1, 3), None, Some(-81))];
+        assert_eq!(checks(&run_checks(&days, &[], &OPTIONS)), vec![
+            (date(1, 1), "tmax", "tmin_gt_tmax"),
+            (date(1, 1), "tmin", "tmin_gt_tmax"),
+            (date(1, 2), "tmax", "implausible"),
+            (date(1, 3), "tmin", "implausible"),
+        ]);
+    }
+
+    #[test]
+    fn repeats_need_consecutive_days() {
+        let mut days: Vec<DailyTemp> = (1..=4).map(|dom| day(date(3, dom), Some(50), None)).collect();
+        days.extend((10..=12).map(|dom| day(date(3, dom), Some(60), None))); // 3 in a row is under repeat_days
+        days.push(day(date(3, 14), Some(60), None)); // a day later than the run, not part of it
+        let flags = run_checks(&days, &[], &OPTIONS);
+        assert_eq!(checks(&flags), (1..=4).map(|dom| (date(3, dom), "tmax", "repeated")).collect::<Vec<_>>());
+        assert_eq!(flags[0].detail, "4 days in a row of 50");
+    }
+
+    #[test]
+    fn every_copy_of_a_duplicate_date_is_flagged() {
+        let days = [day(date(5, 1), Some(70), Some(50)), day(date(5, 1), Some(72), None), day(date(5, 2), Some(71), Some(51))];
+        let flags = run_checks(&days, &[], &OPTIONS);
+        assert_eq!(flags.len(), 4);
+        assert!(flags.iter().all(|f| f.date == date(5, 1) && f.check == "duplicate_date"));
+        assert_eq!(flags.iter().filter(|f| f.value.is_none()).count(), 1);
+    }
+
+    #[test]
+    fn jump_is_judged_against_the_months_changes() {
+        // a steady +-1 wiggle through June with one 40 degree spike
+        let mut days: Vec<DailyTemp> = (1..=30).map(|dom| day(date(6, dom), Some(80 + (dom % 2) as i32), None)).collect();
+        days[15].tmax = Some(120);
+        // the spike's own up and down changes widen the month's spread, z is about 3.8
+        let flags = run_checks(&days, &[], &QcOptions { jump_z: 3.0, ..OPTIONS });
+        assert!(checks(&flags).contains(&(date(6, 16), "tmax", "jump")), "{flags:?}");
+        assert!(flags.iter().all(|f| f.date == date(6, 16) || f.date == date(6, 17)), "{flags:?}");
+    }
+
+    #[test]
+    fn ghcn_flags_and_exclusion() {
+        let days = [day(date(7, 1), Some(90), Some(70))];
+        let flags = run_checks(&days, &[(date(7, 1), "tmin", "G".to_string())], &OPTIONS);
+        assert_eq!(checks(&flags), vec![(date(7, 1), "tmin", "ghcn_qflag")]);
+        assert_eq!(flags[0].value, Some(70));
+        assert_eq!(count_by_check(&flags)["ghcn_qflag"], 1);
+
+        let flagged = HashSet::from([(date(7, 1), "tmin".to_string())]);
+        let kept = exclude_flagged(&days, &flagged);
+        assert_eq!((kept[0].tmax, kept[0].tmin), (Some(90), None));
+    }
+}
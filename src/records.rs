@@ -0,0 +1,216 @@
+// Daily records from the raw {city} table: for each calendar day the record high, record low,
+// record high low and record low high with the years they were set, plus how many records each year set
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use chrono::Datelike;
+use plotters::prelude::*;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT, AXIS_WIDTH, AXIS_HEIGHT, TOP_MARGIN, LEFT_MARGIN};
+use crate::anomaly::symmetric_scale;
+use crate::daily::{calendar_day, DailyTemp};
+use crate::units::Unit;
+use crate::yearchart::{self, year_x};
+
+/// Extreme value for one calendar day and every year that reached it
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub value: i32,
+    pub years: Vec<i32>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DayRecords {
+    pub high: Option<Record>,     // highest tmax
+    pub low: Option<Record>,      // lowest tmin
+    pub high_low: Option<Record>, // highest tmin
+    pub low_high: Option<Record>, // lowest tmax
+}
+
+/// New records set in one year: a value beating every earlier year for that calendar day.
+/// The first year a calendar day has data sets nothing, it has nothing to beat
+#[derive(Clone, Debug, Default)]
+pub struct YearRecordCounts {
+    pub highs: i32,
+    pub lows: i32,
+    pub high_lows: i32,
+    pub low_highs: i32,
+}
+
+pub fn daily_records(days: &[DailyTemp]) -> BTreeMap<(u32, u32), DayRecords> {
+    let mut records: BTreeMap<(u32, u32), DayRecords> = BTreeMap::new();
+    for day in days {
+        let year = day.date.year();
+        let entry = records.entry(calendar_day(&day.date)).or_default();
+        if let Some(tmax) = day.tmax {
+            update_record(&mut entry.high, tmax, year, |new, old| new > old);
+            update_record(&mut entry.low_high, tmax, year, |new, old| new < old);
+        }
+        if let Some(tmin) = day.tmin {
+            update_record(&mut entry.low, tmin, year, |new, old| new < old);
+            update_record(&mut entry.high_low, tmin, year, |new, old| new > old);
+        }
+    }
+    records
+}
+
+fn update_record(record: &mut Option<Record>, value: i32, year: i32, beats: fn(i32, i32) -> bool) {
+    match record {
+        None => *record = Some(Record { value, years: vec![year] }),
+        Some(r) if beats(value, r.value) => *record = Some(Record { value, years: vec![year] }),
+        Some(r) if value == r.value && !r.years.contains(&year) => r.years.push(year),
+        _ => {},
+    }
+}
+
+// days must be in date order, like get_daily_temps returns them
+pub fn records_per_year(days: &[DailyTemp]) -> BTreeMap<i32, YearRecordCounts> {
+    let mut running: BTreeMap<(u32, u32), DayRecords> = BTreeMap::new();
+    let mut counts: BTreeMap<i32, YearRecordCounts> = BTreeMap::new();
+    for day in days {
+        let year = day.date.year();
+        let year_counts = counts.entry(year).or_default();
+        let entry = running.entry(calendar_day(&day.date)).or_default();
+        if let Some(tmax) = day.tmax {
+            year_counts.highs += beat_running(&mut entry.high, tmax, year, |new, old| new > old);
+            year_counts.low_highs += beat_running(&mut entry.low_high, tmax, year, |new, old| new < old);
+        }
+        if let Some(tmin) = day.tmin {
+            year_counts.lows += beat_running(&mut entry.low, tmin, year, |new, old| new < old);
+            year_counts.high_lows += beat_running(&mut entry.high_low, tmin, year, |new, old| new > old);
+        }
+    }
+    counts
+}
+
+// 1 when value sets a new record, ties don't count
+fn beat_running(record: &mut Option<Record>, value: i32, year: i32, beats: fn(i32, i32) -> bool) -> i32 {
+    match record {
+        None => { *record = Some(Record { value, years: vec![year] }); 0 },
+        Some(r) if beats(value, r.value) => { *record = Some(Record { value, years: vec![year] }); 1 },
+        _ => 0,
+    }
+}
+
+pub fn write_records_csv(city: &str, records: &BTreeMap<(u32, u32), DayRecords>, unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_daily_records{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "month,day,record_high,record_high_years,record_low,record_low_years,record_high_low,record_high_low_years,record_low_high,record_low_high_years")?;
+    for ((month, day), rec) in records {
+        write!(out, "{month},{day}")?;
+        for record in [&rec.high, &rec.low, &rec.high_low, &rec.low_high] {
+            match record {
+                Some(r) => {
+                    let years: Vec<String> = r.years.iter().map(|y| y.to_string()).collect();
+                    write!(out, ",{},{}", unit.format_temp(f64::from(r.value), 0), years.join(";"))?;
+                },
+                None => write!(out, ",,")?,
+            }
+        }
+        writeln!(out)?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+pub fn write_year_counts_csv(city: &str, counts: &BTreeMap<i32, YearRecordCounts>) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_records_per_year.csv");
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "year,record_highs,record_lows,record_high_lows,record_low_highs")?;
+    for (year, c) in counts {
+        writeln!(out, "{year},{},{},{},{}", c.highs, c.lows, c.high_lows, c.low_highs)?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+/// log2 of record highs / record lows per year as bars up (more highs) or down (more lows).
+/// Years without at least one of each are left out, their ratio can't be drawn
+pub fn draw_record_ratio(city: &str, counts: &BTreeMap<i32, YearRecordCounts>) -> Result<(), Box<dyn std::error::Error>> {
+    let ratios: Vec<(i32, f64)> = counts.iter()
+        .filter(|(_, c)| c.highs > 0 && c.lows > 0)
+        .map(|(year, c)| (*year, (f64::from(c.highs) / f64::from(c.lows)).log2()))
+        .collect();
+    let (Some(first), Some(last)) = (counts.keys().next().copied(), counts.keys().last().copied()) else {
+        println!("No daily data for {city}, no record ratio chart drawn");
+        return Ok(());
+    };
+    let y_scale = symmetric_scale(ratios.iter().map(|(_, r)| *r), 1);
+
+    let file_name = format!("imgs/{city}_record_ratio.png");
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first}-{last}  log2(Record Highs / Record Lows)");
+    yearchart::draw_year_chart_base(&dwg, &title_text, first, last, &y_scale)?;
+
+    let zero_y = TOP_MARGIN + AXIS_HEIGHT - y_scale.zero_line_offset.round() as i32;
+    let half_width = (AXIS_WIDTH / (last - first + 1).max(1) / 2 - 1).max(1);
+    for (year, ratio) in &ratios {
+        let x = year_x(f64::from(*year), first, last);
+        let bar_y = zero_y - (ratio * y_scale.pixel_per_degree).round() as i32;
+        let color = if *ratio >= 0.0 { RGBColor(200, 30, 30) } else { RGBColor(30, 80, 200) };
+        dwg.draw(&Rectangle::new([(x - half_width, zero_y), (x + half_width, bar_y)], color.filled()))?;
+    }
+    dwg.draw(&PathElement::new(
+        vec![(LEFT_MARGIN, zero_y), (LEFT_MARGIN + AXIS_WIDTH, zero_y)],
+        Into::<ShapeStyle>::into(&BLACK).stroke_width(2),
+    ))?;
+    let legend = vec![
+        ("More record highs (1 = twice as many)".to_string(), RGBColor(200, 30, 30)),
+        ("More record lows (-1 = twice as many)".to_string(), RGBColor(30, 80, 200)),
+    ];
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn day(year: i32, month: u32, dom: u32, tmax: Option<i32>, tmin: Option<i32>) -> DailyTemp {
+        DailyTemp { date: NaiveDate::from_ymd_opt(year, month, dom).unwrap(), tmax, tmin, tavg: None }
+    }
+
+    #[test]
+    fn ties_keep_every_year() {
+        let days = [
+            day(2000, 7, 4, Some(95), Some(70)),
+            day(2001, 7, 4, Some(99), Some(65)),
+            day(2002, 7, 4, Some(99), Some(72)),
+            day(2002, 7, 5, Some(80), None),
+        ];
+        let records = daily_records(&days);
+        let july_4 = &records[&(7, 4)];
+        let high = july_4.high.as_ref().unwrap();
+        assert_eq!((high.value, high.years.clone()), (99, vec![2001, 2002]));
+        let low = july_4.low.as_ref().unwrap();
+        assert_eq!((low.value, low.years.clone()), (65, vec![2001]));
+        assert_eq!(july_4.high_low.as_ref().unwrap().years, vec![2002]);
+        assert_eq!(july_4.low_high.as_ref().unwrap().years, vec![2000]);
+        assert!(records[&(7, 5)].low.is_none());
+    }
+
+    #[test]
+    fn first_year_and_ties_set_no_records() {
+        let days = [
+            day(2000, 1, 1, Some(50), Some(30)),
+            day(2001, 1, 1, Some(55), Some(30)), // new high, the low ties
+            day(2002, 1, 1, Some(55), Some(20)), // high ties, new low
+            day(2003, 1, 1, Some(40), Some(25)), // new low high
+        ];
+        let counts = records_per_year(&days);
+        let c = &counts[&2000];
+        assert_eq!((c.highs, c.lows, c.high_lows, c.low_highs), (0, 0, 0, 0));
+        let c = &counts[&2001];
+        assert_eq!((c.highs, c.lows, c.high_lows, c.low_highs), (1, 0, 0, 0));
+        let c = &counts[&2002];
+        assert_eq!((c.highs, c.lows, c.high_lows, c.low_highs), (0, 1, 0, 0));
+        let c = &counts[&2003];
+        assert_eq!((c.highs, c.lows, c.high_lows, c.low_highs), (0, 0, 0, 1));
+    }
+}
@@ -0,0 +1,267 @@
+// Heat waves and cold spells: runs of consecutive days past a threshold in the raw daily table.
+// The threshold is either absolute (tmax above 95) or a percentile for that calendar day
+// (tmax above the 90th percentile of the same day +/- a few days over the reference years).
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use chrono::{Datelike, NaiveDate};
+use plotters::prelude::*;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT, AXIS_WIDTH};
+use crate::daily::DailyTemp;
+use crate::periods;
+use crate::units::Unit;
+use crate::yearchart::{self, value_y, year_x};
+
+const HEAT_COLOR: RGBColor = RGBColor(200, 30, 30);
+const COLD_COLOR: RGBColor = RGBColor(30, 80, 200);
+const HEAT_COLOR_2: RGBColor = RGBColor(240, 150, 0); // second heat or cold rule on the same chart
+const COLD_COLOR_2: RGBColor = RGBColor(0, 170, 190);
+
+#[derive(Clone, Copy, Debug)]
+pub enum SpellThreshold {
+    Absolute(i32),
+    Percentile(f64), // 90.0 for the 90th percentile of each calendar day
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SpellRule {
+    pub hot: bool, // heat waves test tmax above the threshold, cold spells tmin below it
+    pub threshold: SpellThreshold,
+    pub min_days: usize,
+}
+
+impl SpellRule {
+    pub fn label(&self, unit: Unit) -> String {
+        let (kind, series, side) = if self.hot { ("Heat wave", "tmax", "above") } else { ("Cold spell", "tmin", "below") };
+        let threshold = match self.threshold {
+            SpellThreshold::Absolute(value) => unit.temp_text(f64::from(value)),
+            SpellThreshold::Percentile(p) => format!("the {p:.0}th percentile"),
+        };
+        format!("{kind}: {} or more days of {series} {side} {threshold}", self.min_days)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Spell {
+    pub hot: bool,
+    pub start: NaiveDate,
+    pub days: usize,
+    pub peak: i32,           // hottest tmax of a heat wave, coldest tmin of a cold spell
+    pub mean_intensity: f64, // mean °F past the threshold
+}
+
+// Day 1-365, Feb 29 shares Feb 28's slot so every year lines up
+fn day_slot(date: &NaiveDate) -> usize {
+    let ordinal = date.ordinal() as usize;
+    let leap = NaiveDate::from_ymd_opt(date.year(), 2, 29).is_some();
+    if leap && ordinal >= 60 { ordinal - 1 } else { ordinal }
+}
+
+/// Threshold for each day slot (index 0 = Jan 1). Percentiles use every year in `reference`
+/// and the days within `window` days of the slot, wrapping around the new year
+pub fn day_thresholds(days: &[DailyTemp], rule: &SpellRule, reference: (i32, i32), window: usize) -> Vec<Option<f64>> {
+    match rule.threshold {
+        SpellThreshold::Absolute(value) => vec![Some(f64::from(value)); 365],
+        SpellThreshold::Percentile(p) => {
+            let mut slots: Vec<Vec<f64>> = vec![Vec::new(); 365];
+            for day in days.iter().filter(|d| (reference.0..=reference.1).contains(&d.date.year())) {
+                let Some(temp) = (if rule.hot { day.tmax } else { day.tmin }) else { continue; };
+                let slot = day_slot(&day.date) - 1;
+                for offset in 0..=2 * window {
+                    slots[(slot + 365 + offset - window) % 365].push(f64::from(temp));
+                }
+            }
+            slots.iter_mut().map(|values| percentile(values, p)).collect()
+        },
+    }
+}
+
+/// Linear interpolation between the closest ranks
+pub fn percentile(values: &mut [f64], p: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let rank = (p / 100.0).clamp(0.0, 1.0) * (values.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    Some(values[low] + (values[high] - values[low]) * (rank - low as f64))
+}
+
+// days must be in date order. A missing day or reading ends a run
+pub fn find_spells(days: &[DailyTemp], rule: &SpellRule, thresholds: &[Option<f64>]) -> Vec<Spell> {
+    let mut spells = Vec::new();
+    let mut run: Vec<(NaiveDate, i32, f64)> = Vec::new(); // date, temp, degrees past threshold
+    for day in days {
+        let temp = if rule.hot { day.tmax } else { day.tmin };
+        let threshold = thresholds[day_slot(&day.date) - 1];
+        let past = match (temp, threshold) {
+            (Some(t), Some(limit)) if rule.hot && f64::from(t) > limit => Some((t, f64::from(t) - limit)),
+            (Some(t), Some(limit)) if !rule.hot && f64::from(t) < limit => Some((t, limit - f64::from(t))),
+            _ => None,
+        };
+        let continues = run.last().is_some_and(|(last, _, _)| last.succ_opt() == Some(day.date));
+        if !continues || past.is_none() {
+            end_run(&mut run, rule, &mut spells);
+        }
+        if let Some((t, excess)) = past {
+            run.push((day.date, t, excess));
+        }
+    }
+    end_run(&mut run, rule, &mut spells);
+    spells
+}
+
+fn end_run(run: &mut Vec<(NaiveDate, i32, f64)>, rule: &SpellRule, spells: &mut Vec<Spell>) {
+    if run.len() >= rule.min_days.max(1) {
+        let temps = run.iter().map(|(_, t, _)| *t);
+        spells.push(Spell {
+            hot: rule.hot,
+            start: run[0].0,
+            days: run.len(),
+            peak: if rule.hot { temps.max().unwrap_or_default() } else { temps.min().unwrap_or_default() },
+            mean_intensity: periods::mean(run.iter().map(|(_, _, excess)| *excess)).unwrap_or_default(),
+        });
+    }
+    run.clear();
+}
+
+/// (events, event days) per year, by the year the event started
+pub fn spells_per_year(spells: &[Spell]) -> BTreeMap<i32, (i32, i32)> {
+    let mut years: BTreeMap<i32, (i32, i32)> = BTreeMap::new();
+    for spell in spells {
+        let year = years.entry(spell.start.year()).or_default();
+        year.0 += 1;
+        year.1 += spell.days as i32;
+    }
+    years
+}
+
+pub fn write_spells_csv(city: &str, rules: &[SpellRule], by_rule: &[Vec<Spell>], unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_spells{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "rule,kind,start,days,peak,mean_intensity")?;
+    for (rule, spells) in rules.iter().zip(by_rule) {
+        for spell in spells {
+            writeln!(out, "\"{}\",{},{},{},{},{}", rule.label(unit), if spell.hot { "heat" } else { "cold" },
+                     spell.start.format("%Y-%m-%d"), spell.days, unit.format_temp(f64::from(spell.peak), 0), unit.format_delta(spell.mean_intensity, 1))?;
+        }
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+pub fn write_spell_counts_csv(city: &str, rules: &[SpellRule], by_rule: &[Vec<Spell>], unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_spells_per_year{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "rule,year,events,event_days")?;
+    for (rule, spells) in rules.iter().zip(by_rule) {
+        for (year, (events, days)) in spells_per_year(spells) {
+            writeln!(out, "\"{}\",{year},{events},{days}", rule.label(unit))?;
+        }
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+/// Every event as a bar at its year running from its first to last day of the year
+pub fn draw_spell_timeline(city: &str, first_year: i32, last_year: i32, rules: &[SpellRule], by_rule: &[Vec<Spell>], unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let y_scale = yearchart::value_scale([1.0, 366.0].into_iter(), 0.0);
+    let file_name = format!("imgs/{city}_spells{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first_year}-{last_year}  Heat Waves and Cold Spells, Day of Year");
+    yearchart::draw_year_chart_base(&dwg, &title_text, first_year, last_year, &y_scale)?;
+    let half_width = (AXIS_WIDTH / (last_year - first_year + 1).max(1) / 2).max(1);
+    let mut legend = Vec::new();
+    let (mut heat_rules, mut cold_rules) = (0, 0);
+    for (rule, spells) in rules.iter().zip(by_rule) {
+        let color = if rule.hot {
+            heat_rules += 1;
+            if heat_rules == 1 { HEAT_COLOR } else { HEAT_COLOR_2 }
+        } else {
+            cold_rules += 1;
+            if cold_rules == 1 { COLD_COLOR } else { COLD_COLOR_2 }
+        };
+        for spell in spells.iter().filter(|s| (first_year..=last_year).contains(&s.start.year())) {
+            let x = year_x(f64::from(spell.start.year()), first_year, last_year);
+            let start_day = f64::from(spell.start.ordinal());
+            let end_day = start_day + spell.days as f64 - 1.0; // runs past Dec 31 just go off the top
+            dwg.draw(&Rectangle::new(
+                [(x - half_width, value_y(start_day, &y_scale)), (x + half_width, value_y(end_day.min(366.0), &y_scale) - 1)],
+                color.filled(),
+            ))?;
+        }
+        legend.push((format!("{}  ({} events)", rule.label(unit), spells.len()), color));
+    }
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, dom: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, dom).unwrap()
+    }
+
+    // consecutive days from start with these tmax values, None for a missing reading
+    fn run_of_days(start: NaiveDate, tmaxes: &[Option<i32>]) -> Vec<DailyTemp> {
+        start.iter_days().zip(tmaxes).map(|(d, tmax)| DailyTemp { date: d, tmax: *tmax, tmin: None, tavg: None }).collect()
+    }
+
+    const HEAT: SpellRule = SpellRule { hot: true, threshold: SpellThreshold::Absolute(95), min_days: 3 };
+
+    #[test]
+    fn short_runs_and_missing_readings_end_a_spell() {
+        let days = run_of_days(date(2001, 7, 1), &[
+            Some(96), Some(101), Some(97), Some(90), // 3 day wave
+            Some(96), Some(96), None, Some(99),      // broken by the missing reading
+            Some(95), Some(98), Some(99), Some(97),  // 95 isn't above 95, 3 day wave after it
+        ]);
+        let spells = find_spells(&days, &HEAT, &day_thresholds(&days, &HEAT, (2001, 2001), 0));
+        assert_eq!(spells.len(), 2);
+        assert_eq!((spells[0].start, spells[0].days, spells[0].peak), (date(2001, 7, 1), 3, 101));
+        assert!((spells[0].mean_intensity - 3.0).abs() < 1e-9); // (1 + 6 + 2) / 3
+        assert_eq!((spells[1].start, spells[1].days), (date(2001, 7, 10), 3));
+    }
+
+    #[test]
+    fn a_missing_date_ends_a_spell() {
+        let mut days = run_of_days(date(2001, 7, 1), &[Some(96), Some(97)]);
+        days.extend(run_of_days(date(2001, 7, 4), &[Some(98), Some(99)]));
+        assert!(find_spells(&days, &HEAT, &day_thresholds(&days, &HEAT, (2001, 2001), 0)).is_empty());
+    }
+
+    #[test]
+    fn percentile_interpolates_between_ranks() {
+        assert_eq!(percentile(&mut [4.0, 1.0, 3.0, 2.0, 5.0], 50.0), Some(3.0));
+        assert_eq!(percentile(&mut [1.0, 2.0], 90.0), Some(1.9));
+        assert_eq!(percentile(&mut [7.0], 90.0), Some(7.0));
+        assert_eq!(percentile(&mut [], 90.0), None);
+    }
+
+    #[test]
+    fn leap_day_shares_feb_28s_slot() {
+        assert_eq!(day_slot(&date(2000, 2, 29)), day_slot(&date(2000, 2, 28)));
+        assert_eq!(day_slot(&date(2000, 3, 1)), day_slot(&date(2001, 3, 1)));
+        assert_eq!(day_slot(&date(2000, 12, 31)), 365);
+    }
+
+    #[test]
+    fn spells_are_counted_by_start_year() {
+        let spells = [
+            Spell { hot: true, start: date(2001, 7, 1), days: 3, peak: 100, mean_intensity: 2.0 },
+            Spell { hot: true, start: date(2001, 8, 1), days: 4, peak: 99, mean_intensity: 1.0 },
+            Spell { hot: false, start: date(2001, 12, 30), days: 5, peak: 5, mean_intensity: 4.0 },
+        ];
+        let years = spells_per_year(&spells);
+        assert_eq!(years[&2001], (3, 12));
+        assert!(!years.contains_key(&2002));
+    }
+}
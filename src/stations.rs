@@ -0,0 +1,126 @@
+// Station registry: where every GHCN station is, loaded from NOAA's ghcnd-stations.txt into the stations table.
+// A city's station is the station column of its daily table, so cities can be found near each other
+// for gap filling without anything else knowing about coordinates.
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use sqlx::{MySql, Pool, Row};
+
+#[derive(Clone, Debug)]
+pub struct Station {
+    pub station: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation: f64, // meters, -999.9 when unknown
+    pub state: String,
+    pub name: String,
+}
+
+/// Fixed width ghcnd-stations.txt: ID 1-11, LATITUDE 13-20, LONGITUDE 22-30, ELEVATION 32-37, STATE 39-40, NAME 42-71.
+/// Only stations whose id starts with `prefix` ("US" for the US, "" for everything)
+pub fn read_stations_file(path: &str, prefix: &str) -> Result<Vec<Station>, std::io::Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut stations = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let field = |from: usize, to: usize| line.get(from - 1..to.min(line.len())).unwrap_or("").trim().to_string();
+        let station = field(1, 11);
+        if station.is_empty() || !station.starts_with(prefix) {
+            continue;
+        }
+        let (Ok(latitude), Ok(longitude)) = (field(13, 20).parse(), field(22, 30).parse()) else { continue; };
+        stations.push(Station {
+            station,
+            latitude,
+            longitude,
+            elevation: field(32, 37).parse().unwrap_or(-999.9),
+            state: field(39, 40),
+            name: field(42, 71),
+        });
+    }
+    Ok(stations)
+}
+
+pub async fn create_stations_table(pool: &Pool<MySql>) -> Result<(), sqlx::Error> {
+    let create_stmt = r#"CREATE TABLE if NOT exists `stations` (
+  `station` char(12) NOT NULL,
+  `latitude` double NOT NULL,
+  `longitude` double NOT NULL,
+  `elevation` double DEFAULT NULL,
+  `state` char(2) DEFAULT NULL,
+  `name` varchar(40) DEFAULT NULL,
+  PRIMARY KEY (`station`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_uca1400_ai_ci;"#;
+    let _result = sqlx::query(create_stmt).execute(pool).await?;
+    Ok(())
+}
+
+// replaces the whole registry
+pub async fn store_stations(pool: &Pool<MySql>, stations: &[Station]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM `stations`").execute(&mut *tx).await?;
+    let insert_stmt = "INSERT INTO `stations` (station, latitude, longitude, elevation, state, name) VALUES (?, ?, ?, ?, ?, ?)";
+    for station in stations {
+        sqlx::query(insert_stmt)
+            .bind(&station.station)
+            .bind(station.latitude)
+            .bind(station.longitude)
+            .bind(station.elevation)
+            .bind(&station.state)
+            .bind(&station.name)
+            .execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Station id in the city's daily table, None if the column is empty
+pub async fn city_station(pool: &Pool<MySql>, city: &str) -> Result<Option<String>, sqlx::Error> {
+    let query_stmt_string = format!("SELECT station FROM {city} WHERE station IS NOT NULL LIMIT 1");
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query(&query_stmt_string)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.first().map(|row| row.get::<String, _>("station").trim().to_string()))
+}
+
+pub async fn station_location(pool: &Pool<MySql>, station: &str) -> Result<Option<(f64, f64)>, sqlx::Error> {
+    let rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query("SELECT latitude, longitude FROM stations WHERE station = ?")
+        .bind(station)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.first().map(|row| (row.get("latitude"), row.get("longitude"))))
+}
+
+/// Other cities in city_names whose station is within max_km, nearest first, (city, km)
+pub async fn neighbor_cities(pool: &Pool<MySql>, city: &str, max_km: f64) -> Result<Vec<(String, f64)>, sqlx::Error> {
+    let Some(home) = city_station(pool, city).await? else { return Ok(Vec::new()); };
+    let Some(home_location) = station_location(pool, &home).await? else { return Ok(Vec::new()); };
+    let city_rows: Vec<sqlx::mysql::MySqlRow> = sqlx::query("SELECT name_of_city FROM city_names")
+        .fetch_all(pool)
+        .await?;
+    let mut neighbors = Vec::new();
+    for row in city_rows {
+        let other: String = row.get("name_of_city");
+        if other.eq_ignore_ascii_case(city) {
+            continue;
+        }
+        // a city without a daily table or station is just not a neighbor
+        let Ok(Some(station)) = city_station(pool, &other).await else { continue; };
+        let Some(location) = station_location(pool, &station).await? else { continue; };
+        let km = distance_km(home_location, location);
+        if km <= max_km {
+            neighbors.push((other, km));
+        }
+    }
+    neighbors.sort_by(|a, b| a.1.total_cmp(&b.1));
+    Ok(neighbors)
+}
+
+/// Great circle (haversine) distance between two (latitude, longitude) points
+pub fn distance_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (to.1 - from.1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
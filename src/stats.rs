@@ -0,0 +1,253 @@
+// Robust trend statistics for every bucket of every period table: Theil-Sen slope and the Mann-Kendall test.
+// Says which weeks, fortnights or months are warming or cooling and whether that could just be noise.
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use sqlx::{MySql, Pool};
+use plotters::prelude::*;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT, AXIS_HEIGHT, AXIS_WIDTH, TOP_MARGIN, LEFT_MARGIN, bar_x_width, draw_chart_base};
+use crate::anomaly::symmetric_scale;
+use crate::daily;
+use crate::periods;
+use crate::units::Unit;
+use crate::yearchart;
+
+const HI_COLOR: RGBColor = RGBColor(200, 30, 30);
+const LOW_COLOR: RGBColor = RGBColor(30, 80, 200);
+const MEAN_COLOR: RGBColor = RGBColor(230, 140, 0);
+
+/// Trend of one series (tmax, tmean or tmin) in one bucket across the years
+pub struct BucketTrend {
+    pub period: &'static str,
+    pub bucket: usize, // 1 based like the period tables
+    pub series: &'static str,
+    pub sen_slope: Option<f64>, // °F per year
+    pub mk_p_value: Option<f64>,
+    pub years: usize,
+}
+
+pub async fn bucket_trends(pool: &Pool<MySql>, city: &str, first_year: i32, last_year: i32, series: &[&str]) -> Result<Vec<BucketTrend>, sqlx::Error> {
+    let mut trends = Vec::new();
+    for period in ["Week", "Fort", "Month"] {
+        let city_period = format!("{city}_{period}");
+        let all_years = periods::temps_by_year(period, &periods::get_all_temps(pool, periods::period_column(period), &city_period).await?);
+        for idx in 0..periods::bucket_count(period) {
+            for name in daily::SERIES.iter().filter(|s| series.contains(s)) {
+                let points: Vec<(i32, f64)> = all_years.range(first_year..=last_year)
+                    .filter_map(|(year, temps)| temps.series(name)[idx].map(|v| (*year, v)))
+                    .collect();
+                trends.push(BucketTrend {
+                    period,
+                    bucket: idx + 1,
+                    series: name,
+                    sen_slope: theil_sen(&points),
+                    mk_p_value: mann_kendall(&points),
+                    years: points.len(),
+                });
+            }
+        }
+    }
+    Ok(trends)
+}
+
+pub fn print_trends(city: &str, trends: &[BucketTrend], unit: Unit) {
+    println!("Bucket trends for {city} (Sen slope in {} per decade, Mann-Kendall two sided p)", unit.symbol());
+    println!("{:<6} {:>6} {:<5} {:>10} {:>8} {:>6}", "Period", "Bucket", "Temp", "Slope", "p", "Years");
+    for trend in trends {
+        println!("{:<6} {:>6} {:<5} {:>10} {:>8} {:>6}", trend.period, trend.bucket, trend.series,
+                 format_option(trend.sen_slope.map(|s| unit.delta(s * 10.0)), 3), format_option(trend.mk_p_value, 4), trend.years);
+    }
+}
+
+pub fn write_trends_csv(city: &str, trends: &[BucketTrend], unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_bucket_trends{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "city,period,bucket,series,sen_slope_per_decade,mann_kendall_p,years")?;
+    for trend in trends {
+        writeln!(out, "{city},{},{},{},{},{},{}", trend.period, trend.bucket, trend.series,
+                 format_option(trend.sen_slope.map(|s| unit.delta(s * 10.0)), 4), format_option(trend.mk_p_value, 5), trend.years)?;
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+fn format_option(value: Option<f64>, decimals: usize) -> String {
+    match value {
+        Some(v) => format!("{v:.decimals$}"),
+        None => String::new(),
+    }
+}
+
+/// Monthly Sen slopes as a group of bars per month, one per series. Months with p >= significance are drawn faded
+pub fn draw_month_slopes(city: &str, first_year: i32, last_year: i32, trends: &[BucketTrend], significance: f64, unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let months: Vec<&BucketTrend> = trends.iter().filter(|t| t.period == "Month").collect();
+    let y_scale = symmetric_scale(months.iter().filter_map(|t| t.sen_slope.map(|s| unit.delta(s * 10.0))), 1);
+
+    let file_name = format!("imgs/{city}_month_slopes{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first_year}-{last_year}  Sen Slope {} per Decade by Month", unit.symbol());
+    draw_chart_base(&dwg, &title_text, "Month", &y_scale)?;
+
+    let zero_y = TOP_MARGIN + AXIS_HEIGHT - y_scale.zero_line_offset.round() as i32;
+    let series: Vec<&str> = daily::SERIES.iter().copied().filter(|s| months.iter().any(|t| t.series == *s)).collect();
+    for trend in &months {
+        let (Some(slope), Some((x, width))) = (trend.sen_slope, bar_x_width("Month", trend.bucket as i32)) else { continue; };
+        let part = width / series.len().max(1) as i32;
+        let position = series.iter().position(|s| *s == trend.series).unwrap_or(0) as i32;
+        let (x0, color) = (x + part * position, series_color(trend.series));
+        let bar_y = zero_y - (unit.delta(slope * 10.0) * y_scale.pixel_per_degree).round() as i32;
+        let significant = trend.mk_p_value.is_some_and(|p| p < significance);
+        let style = if significant { color.filled() } else { color.mix(0.25).filled() };
+        dwg.draw(&Rectangle::new([(x0, zero_y), (x0 + part, bar_y)], style))?;
+        if !significant { // outline keeps faded bars readable
+            dwg.draw(&Rectangle::new([(x0, zero_y), (x0 + part, bar_y)], color.mix(0.6).stroke_width(1)))?;
+        }
+    }
+    dwg.draw(&PathElement::new(
+        vec![(LEFT_MARGIN, zero_y), (LEFT_MARGIN + AXIS_WIDTH, zero_y)],
+        Into::<ShapeStyle>::into(&BLACK).stroke_width(2),
+    ))?;
+    let mut legend: Vec<(String, RGBColor)> = series.iter().map(|s| (format!("Avg {}", daily::series_text(s)), series_color(s))).collect();
+    legend.push((format!("Faded: not significant, Mann-Kendall p >= {significance}"), RGBColor(220, 220, 220)));
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+fn series_color(series: &str) -> RGBColor {
+    match series {
+        "tmax" => HI_COLOR,
+        "tmean" => MEAN_COLOR,
+        _ => LOW_COLOR,
+    }
+}
+
+/// Theil-Sen slope: median of the slopes between every pair of years. Per year, None with fewer than 2 years
+pub fn theil_sen(points: &[(i32, f64)]) -> Option<f64> {
+    let mut slopes = Vec::new();
+    for (i, (x1, y1)) in points.iter().enumerate() {
+        for (x2, y2) in &points[i + 1..] {
+            if x2 != x1 {
+                slopes.push((y2 - y1) / f64::from(x2 - x1));
+            }
+        }
+    }
+    median(&mut slopes)
+}
+
+pub fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) { Some((values[mid - 1] + values[mid]) / 2.0) } else { Some(values[mid]) }
+}
+
+/// Two sided Mann-Kendall p value using the normal approximation with the tie correction.
+/// Points must be in year order. None with fewer than 3 years or when every value is the same
+pub fn mann_kendall(points: &[(i32, f64)]) -> Option<f64> {
+    let n = points.len();
+    if n < 3 {
+        return None;
+    }
+    let mut s: i64 = 0;
+    for (i, (_, a)) in points.iter().enumerate() {
+        for (_, b) in &points[i + 1..] {
+            s += match b.partial_cmp(a) {
+                Some(std::cmp::Ordering::Greater) => 1,
+                Some(std::cmp::Ordering::Less) => -1,
+                _ => 0,
+            };
+        }
+    }
+    // tied groups reduce the variance of S
+    let mut sorted: Vec<f64> = points.iter().map(|(_, v)| *v).collect();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let mut tie_term = 0.0;
+    let mut run = 1.0;
+    for i in 1..=sorted.len() {
+        if i < sorted.len() && sorted[i] == sorted[i - 1] {
+            run += 1.0;
+        } else {
+            tie_term += run * (run - 1.0) * (2.0 * run + 5.0);
+            run = 1.0;
+        }
+    }
+    let n = n as f64;
+    let variance = (n * (n - 1.0) * (2.0 * n + 5.0) - tie_term) / 18.0;
+    if variance <= 0.0 {
+        return None;
+    }
+    let z = match s {
+        s if s > 0 => (s as f64 - 1.0) / variance.sqrt(),
+        s if s < 0 => (s as f64 + 1.0) / variance.sqrt(),
+        _ => 0.0,
+    };
+    Some((2.0 * (1.0 - normal_cdf(z.abs()))).clamp(0.0, 1.0))
+}
+
+pub fn normal_cdf(z: f64) -> f64 {
+    0.5 * (1.0 + erf(z / std::f64::consts::SQRT_2))
+}
+
+// Abramowitz and Stegun 7.1.26, good to about 1.5e-7 which is plenty for a p value
+fn erf(x: f64) -> f64 {
+    let sign = if x < 0.0 { -1.0 } else { 1.0 };
+    let x = x.abs();
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    sign * (1.0 - poly * (-x * x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theil_sen_ignores_one_outlier() {
+        // y = 2x + 1 with the last point far off, 6 of the 10 pair slopes are exactly 2
+        let points = [(0, 1.0), (1, 3.0), (2, 5.0), (3, 7.0), (4, 100.0)];
+        assert_eq!(theil_sen(&points), Some(2.0));
+    }
+
+    #[test]
+    fn theil_sen_skips_same_year_pairs() {
+        assert_eq!(theil_sen(&[(2000, 1.0), (2000, 5.0)]), None);
+        // slopes 0.5 and 0.1, the same year pair is skipped
+        let slope = theil_sen(&[(2000, 1.0), (2000, 5.0), (2010, 6.0)]).unwrap();
+        assert!((slope - 0.3).abs() < 1e-12, "slope = {slope}");
+    }
+
+    #[test]
+    fn mann_kendall_without_ties() {
+        // S = 10, var = 5 * 4 * 15 / 18, z = (S - 1) / sqrt(var)
+        let points = [(1, 1.0), (2, 2.0), (3, 3.0), (4, 4.0), (5, 5.0)];
+        let p = mann_kendall(&points).unwrap();
+        assert!((p - 0.027486).abs() < 1e-5, "p = {p}");
+    }
+
+    #[test]
+    fn mann_kendall_with_ties() {
+        // S = 9, one pair tied so var = (300 - 2 * 1 * 9) / 18
+        let points = [(1, 1.0), (2, 2.0), (3, 2.0), (4, 3.0), (5, 4.0)];
+        let p = mann_kendall(&points).unwrap();
+        assert!((p - 0.043263).abs() < 1e-5, "p = {p}");
+    }
+
+    #[test]
+    fn mann_kendall_needs_three_points_and_some_spread() {
+        assert_eq!(mann_kendall(&[(1, 1.0), (2, 2.0)]), None);
+        assert_eq!(mann_kendall(&[(1, 3.0), (2, 3.0), (3, 3.0)]), None);
+    }
+
+    #[test]
+    fn erf_matches_reference_values() {
+        for (x, expected) in [(0.0, 0.0), (0.5, 0.520_499_877_8), (1.0, 0.842_700_792_9), (2.0, 0.995_322_265_0), (-1.0, -0.842_700_792_9)] {
+            assert!((erf(x) - expected).abs() < 2e-7, "erf({x}) = {}", erf(x));
+        }
+    }
+}
@@ -0,0 +1,222 @@
+// Days per year and per decade past fixed temperatures (tmax >= 90, tmin <= 32 ...), from the raw daily table.
+// Averages hide extremes. Every count carries the number of days that had a reading so a year with
+// missing summer days isn't mistaken for a cooler year.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use chrono::Datelike;
+use plotters::prelude::*;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT, AXIS_WIDTH, BOTTOM_LINE_Y};
+use crate::daily::{days_in_year, DailyTemp};
+use crate::units::Unit;
+use crate::yearchart::{self, value_y, year_x};
+
+const COUNT_COLOR: RGBColor = RGBColor(200, 30, 30);
+const PARTIAL_COLOR: RGBColor = RGBColor(190, 190, 190);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Threshold {
+    pub series: &'static str, // "tmax", "tmin" or "tmean"
+    pub above: bool,          // true counts days >= value, false days <= value
+    pub value: i32,
+}
+
+impl Threshold {
+    pub fn label(&self, unit: Unit) -> String {
+        format!("{} {} {}", self.series, if self.above { ">=" } else { "<=" }, unit.temp_text(f64::from(self.value)))
+    }
+
+    // for file names, ex. tmax_ge_90, always the °F value
+    pub fn slug(&self) -> String {
+        format!("{}_{}_{}", self.series, if self.above { "ge" } else { "le" }, self.value)
+    }
+
+    fn counts(&self, day: &DailyTemp) -> Option<bool> {
+        let temp = day.value(self.series)?;
+        let value = f64::from(self.value);
+        Some(if self.above { temp >= value } else { temp <= value })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DayCount {
+    pub days: i32,       // days past the threshold
+    pub valid_days: i32, // days with a reading for the threshold's series
+    pub total_days: i32, // days in the year(s), valid or not
+}
+
+impl DayCount {
+    pub fn missing_days(&self) -> i32 {
+        self.total_days - self.valid_days
+    }
+
+    pub fn completeness(&self) -> f64 {
+        if self.total_days == 0 { 0.0 } else { f64::from(self.valid_days) / f64::from(self.total_days) }
+    }
+
+    /// Count scaled up to a full year of readings, what a partial year would likely have been
+    pub fn per_year(&self, years: i32) -> Option<f64> {
+        if self.valid_days == 0 {
+            return None;
+        }
+        Some(f64::from(self.days) / f64::from(self.valid_days) * f64::from(self.total_days) / f64::from(years.max(1)))
+    }
+}
+
+/// Counts for every year first_year..=last_year, including years with no rows at all
+pub fn yearly_counts(days: &[DailyTemp], threshold: &Threshold, first_year: i32, last_year: i32) -> BTreeMap<i32, DayCount> {
+    let mut counts: BTreeMap<i32, DayCount> = (first_year..=last_year)
+        .map(|year| (year, DayCount { total_days: days_in_year(year) as i32, ..Default::default() }))
+        .collect();
+    for day in days {
+        let Some(count) = counts.get_mut(&day.date.year()) else { continue; };
+        if let Some(past) = threshold.counts(day) {
+            count.valid_days += 1;
+            if past {
+                count.days += 1;
+            }
+        }
+    }
+    counts
+}
+
+// keyed by the first year of the decade, ex. 1950 for 1950-1959
+pub fn decade_counts(yearly: &BTreeMap<i32, DayCount>) -> BTreeMap<i32, (DayCount, i32)> {
+    let mut decades: BTreeMap<i32, (DayCount, i32)> = BTreeMap::new();
+    for (year, count) in yearly {
+        let decade = year.div_euclid(10) * 10;
+        let (sum, years) = decades.entry(decade).or_default();
+        sum.days += count.days;
+        sum.valid_days += count.valid_days;
+        sum.total_days += count.total_days;
+        *years += 1;
+    }
+    decades
+}
+
+pub fn write_counts_csv(city: &str, thresholds: &[Threshold], yearly: &[BTreeMap<i32, DayCount>], completeness: f64, unit: Unit) -> Result<String, std::io::Error> {
+    std::fs::create_dir_all("reports")?;
+    let file_name = format!("reports/{city}_threshold_days{}.csv", unit.file_suffix());
+    let mut out = BufWriter::new(File::create(&file_name)?);
+    writeln!(out, "threshold,span,start_year,years,days,valid_days,missing_days,complete,days_per_full_year")?;
+    for (threshold, counts) in thresholds.iter().zip(yearly) {
+        let label = threshold.label(unit);
+        for (year, count) in counts {
+            write_count_row(&mut out, &label, "year", *year, 1, count, completeness)?;
+        }
+        for (decade, (count, years)) in decade_counts(counts) {
+            write_count_row(&mut out, &label, "decade", decade, years, &count, completeness)?;
+        }
+    }
+    out.flush()?;
+    Ok(file_name)
+}
+
+fn write_count_row(out: &mut impl Write, label: &str, span: &str, start_year: i32, years: i32, count: &DayCount, completeness: f64) -> Result<(), std::io::Error> {
+    let per_year = count.per_year(years).map(|v| format!("{v:.1}")).unwrap_or_default();
+    writeln!(out, "{label},{span},{start_year},{years},{},{},{},{},{per_year}", count.days, count.valid_days,
+             count.missing_days(), count.completeness() >= completeness)
+}
+
+/// Days per year as bars, years below the completeness fraction drawn gray with how many days they're missing.
+/// Second chart has the decades, as days per full year so a decade with holes compares fairly
+pub fn draw_counts(city: &str, threshold: &Threshold, yearly: &BTreeMap<i32, DayCount>, completeness: f64, unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some(first), Some(last)) = (yearly.keys().next().copied(), yearly.keys().last().copied()) else {
+        return Ok(());
+    };
+    let y_scale = yearchart::value_scale(yearly.values().map(|c| f64::from(c.days)).chain([0.0]), 0.0);
+    let file_name = format!("imgs/{city}_days_{}{}.png", threshold.slug(), unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first}-{last}  Days with {}", threshold.label(unit));
+    yearchart::draw_year_chart_base(&dwg, &title_text, first, last, &y_scale)?;
+    let half_width = (AXIS_WIDTH / (last - first + 1).max(1) / 2 - 1).max(1);
+    for (year, count) in yearly {
+        let x = year_x(f64::from(*year), first, last);
+        let color = if count.completeness() >= completeness { COUNT_COLOR } else { PARTIAL_COLOR };
+        dwg.draw(&Rectangle::new([(x - half_width, BOTTOM_LINE_Y - 2), (x + half_width, value_y(f64::from(count.days), &y_scale))], color.filled()))?;
+    }
+    let legend = vec![
+        (format!("At least {:.0}% of days have a {} reading", completeness * 100.0, threshold.series), COUNT_COLOR),
+        ("Partial year, count is low because days are missing".to_string(), PARTIAL_COLOR),
+    ];
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+
+    let decades = decade_counts(yearly);
+    let per_year: Vec<(i32, Option<f64>, f64)> = decades.iter().map(|(decade, (count, years))| (*decade, count.per_year(*years), count.completeness())).collect();
+    let (first_decade, last_decade) = (first.div_euclid(10) * 10, last.div_euclid(10) * 10);
+    let y_scale = yearchart::value_scale(per_year.iter().filter_map(|(_, v, _)| *v).chain([0.0]), 0.0);
+    let file_name = format!("imgs/{city}_days_{}_decades{}.png", threshold.slug(), unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city}  Days per Year with {} by Decade", threshold.label(unit));
+    yearchart::draw_year_chart_base(&dwg, &title_text, first_decade, last_decade + 10, &y_scale)?;
+    let decade_width = AXIS_WIDTH / ((last_decade - first_decade) / 10 + 1);
+    for (decade, value, decade_completeness) in per_year {
+        let Some(value) = value else { continue; };
+        let x0 = year_x(f64::from(decade), first_decade, last_decade + 10);
+        let color = if decade_completeness >= completeness { COUNT_COLOR } else { PARTIAL_COLOR };
+        dwg.draw(&Rectangle::new([(x0 + 4, BOTTOM_LINE_Y - 2), (x0 + decade_width - 4, value_y(value, &y_scale))], color.filled()))?;
+    }
+    let legend = vec![
+        ("Days scaled to a full year of readings".to_string(), COUNT_COLOR),
+        (format!("Decade with less than {:.0}% of days read", completeness * 100.0), PARTIAL_COLOR),
+    ];
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn day(year: i32, month: u32, dom: u32, tmax: Option<i32>) -> DailyTemp {
+        DailyTemp { date: NaiveDate::from_ymd_opt(year, month, dom).unwrap(), tmax, tmin: None, tavg: None }
+    }
+
+    const HOT: Threshold = Threshold { series: "tmax", above: true, value: 90 };
+
+    #[test]
+    fn counts_keep_missing_days_and_empty_years() {
+        let days = [day(2000, 7, 1, Some(90)), day(2000, 7, 2, Some(89)), day(2000, 7, 3, None), day(2003, 7, 1, Some(95))];
+        let counts = yearly_counts(&days, &HOT, 2000, 2002);
+        assert_eq!(counts.len(), 3); // 2003 is out of range, 2001 and 2002 have no rows
+        let c = counts[&2000];
+        assert_eq!((c.days, c.valid_days, c.total_days, c.missing_days()), (1, 2, 366, 364));
+        let c = counts[&2001];
+        assert_eq!((c.days, c.valid_days, c.total_days), (0, 0, 365));
+        assert_eq!(c.per_year(1), None);
+    }
+
+    #[test]
+    fn below_thresholds_include_the_value() {
+        let cold = Threshold { series: "tmax", above: false, value: 32 };
+        let days = [day(2001, 1, 1, Some(32)), day(2001, 1, 2, Some(33)), day(2001, 1, 3, Some(10))];
+        assert_eq!(yearly_counts(&days, &cold, 2001, 2001)[&2001].days, 2);
+    }
+
+    #[test]
+    fn per_year_scales_a_partial_year() {
+        let count = DayCount { days: 10, valid_days: 73, total_days: 365 };
+        assert_eq!(count.per_year(1), Some(50.0));
+        assert_eq!(count.completeness(), 0.2);
+        let decade = DayCount { days: 100, valid_days: 3650, total_days: 3650 };
+        assert_eq!(decade.per_year(10), Some(10.0));
+    }
+
+    #[test]
+    fn decades_start_on_the_zero_year() {
+        let days = [day(1959, 6, 1, Some(91)), day(1960, 6, 1, Some(92)), day(1969, 6, 1, Some(93))];
+        let decades = decade_counts(&yearly_counts(&days, &HOT, 1958, 1970));
+        assert_eq!(decades.keys().copied().collect::<Vec<i32>>(), vec![1950, 1960, 1970]);
+        let (sixties, years) = decades[&1960];
+        assert_eq!((sixties.days, years), (2, 10));
+        assert_eq!(decades[&1950].1, 2);
+    }
+}
@@ -0,0 +1,121 @@
+// Annual mean time series: avg hi, avg mean and avg low for every year with a least squares trend line,
+// an optional moving average and the slope in degrees per decade. Tables aggregate hasn't rebuilt have no
+// mean series, those get the midpoint of avg hi and avg low instead
+use std::collections::BTreeMap;
+use sqlx::{MySql, Pool};
+use plotters::prelude::*;
+use plotters::element::DashedPathElement;
+
+use crate::{DWG_WIDTH, DWG_HEIGHT};
+use crate::daily;
+use crate::periods::{self, BucketTemps};
+use crate::units::Unit;
+use crate::yearchart::{self, value_y, year_x};
+
+const HI_COLOR: RGBColor = RGBColor(200, 30, 30);
+const LOW_COLOR: RGBColor = RGBColor(30, 80, 200);
+const MEAN_COLOR: RGBColor = RGBColor(90, 90, 90);
+
+type SeriesLine = (String, Vec<(i32, f64)>, RGBColor); // legend name, (year, annual mean) points, color
+
+#[allow(clippy::too_many_arguments)]
+pub async fn draw_annual_trend(pool: &Pool<MySql>,
+                               city: &str,
+                               period: &str,
+                               tperiod: &str,
+                               first_year: i32,
+                               last_year: i32,
+                               smoothing_years: i32,
+                               series: &[&str],
+                               unit: Unit) -> Result<(), Box<dyn std::error::Error>> {
+    let city_period = format!("{city}_{period}");
+    let all_years = unit.years(&periods::temps_by_year(period, &periods::get_all_temps(pool, tperiod, &city_period).await?));
+    let years: BTreeMap<i32, BucketTemps> = all_years.range(first_year..=last_year).map(|(y, t)| (*y, t.clone())).collect();
+    let lines: Vec<SeriesLine> = daily::SERIES.iter().filter(|s| series.contains(s)).map(|name| {
+        let points = periods::annual_series(&years, name);
+        match *name {
+            "tmax" => ("Avg Hi".to_string(), points, HI_COLOR),
+            "tmin" => ("Avg Low".to_string(), points, LOW_COLOR),
+            _ if points.is_empty() => {
+                let midpoints = periods::annual_means(&years).iter()
+                    .filter_map(|(year, (hi, low))| Some((*year, ((*hi)? + (*low)?) / 2.0)))
+                    .collect();
+                ("Midpoint".to_string(), midpoints, MEAN_COLOR)
+            },
+            _ => ("Mean".to_string(), points, MEAN_COLOR),
+        }
+    }).collect();
+    if lines.iter().all(|(_, points, _)| points.is_empty()) {
+        println!("No complete years of {period} data for {city}, no trend chart drawn");
+        return Ok(());
+    }
+
+    let y_scale = yearchart::value_scale(lines.iter().flat_map(|(_, points, _)| points).map(|(_, v)| *v), unit.delta(2.0));
+    let file_name = format!("imgs/{city}_annual_trend{}.png", unit.file_suffix());
+    let dwg = BitMapBackend::new(&file_name, (DWG_WIDTH as u32, DWG_HEIGHT as u32)).into_drawing_area();
+    dwg.fill(&WHITE)?;
+    let title_text = format!("{city} {first_year}-{last_year}  Annual Mean Temperatures ({})", unit.symbol());
+    yearchart::draw_year_chart_base(&dwg, &title_text, first_year, last_year, &y_scale)?;
+
+    let mut legend = Vec::new();
+    for (name, points, color) in &lines {
+        let (points, color) = (points.as_slice(), *color);
+        // raw years faint, smoothing and trend on top
+        yearchart::draw_year_line(&dwg, points, first_year, last_year, &y_scale, color.mix(0.5).stroke_width(1))?;
+        if smoothing_years > 1 {
+            let smoothed = moving_average(points, smoothing_years);
+            yearchart::draw_year_line(&dwg, &smoothed, first_year, last_year, &y_scale, color.stroke_width(3))?;
+        }
+        match linear_fit(points) {
+            Some((slope, intercept)) => {
+                let (start, end) = (points[0].0, points[points.len() - 1].0);
+                let line: Vec<(i32, i32)> = [start, end].iter()
+                    .map(|year| (year_x(f64::from(*year), first_year, last_year), value_y(slope * f64::from(*year) + intercept, &y_scale)))
+                    .collect();
+                dwg.draw(&DashedPathElement::new(line, 12, 6, color.stroke_width(2)))?;
+                legend.push((format!("{name}  trend {:+.2} {} per decade ({} years)", slope * 10.0, unit.symbol(), points.len()), color));
+            },
+            None => legend.push((format!("{name}  not enough years for a trend"), color)),
+        }
+    }
+    if smoothing_years > 1 {
+        legend.push((format!("Thick lines: {smoothing_years} year centered moving average"), RGBColor(255, 255, 255)));
+    }
+    yearchart::draw_legend(&dwg, &legend)?;
+    dwg.present()?;
+    println!("Drew {file_name}");
+    Ok(())
+}
+
+/// Ordinary least squares fit of value on year, (slope per year, intercept). None with fewer than 2 years
+pub fn linear_fit(points: &[(i32, f64)]) -> Option<(f64, f64)> {
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| f64::from(*x)).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| *y).sum::<f64>() / n;
+    let (mut sxy, mut sxx) = (0.0, 0.0);
+    for (x, y) in points {
+        let dx = f64::from(*x) - mean_x;
+        sxy += dx * (y - mean_y);
+        sxx += dx * dx;
+    }
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    Some((slope, mean_y - slope * mean_x))
+}
+
+/// Centered moving average over `window` years. A year is only smoothed when more than half of its window has data
+pub fn moving_average(points: &[(i32, f64)], window: i32) -> Vec<(i32, f64)> {
+    let half = window / 2;
+    points.iter().filter_map(|(year, _)| {
+        let in_window: Vec<f64> = points.iter()
+            .filter(|(y, _)| (y - year).abs() <= half)
+            .map(|(_, v)| *v)
+            .collect();
+        if in_window.len() as i32 * 2 > window { periods::mean(in_window.into_iter()).map(|m| (*year, m)) } else { None }
+    }).collect()
+}
@@ -0,0 +1,98 @@
+// Temperature units for output. Everything in the database stays whole °F; charts, csv exports and printed
+// reports convert on the way out. Absolute temperatures go through temp(), anything that is a difference
+// of temperatures (anomalies, ranges, slopes, degree days, shifts) through delta().
+use std::collections::BTreeMap;
+
+use crate::periods::BucketTemps;
+
+#[allow(dead_code)] // only the variant picked in main's config is constructed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    F,
+    C,
+    K, // meant for csv exports, charts work but the axis numbers are large
+}
+
+impl Unit {
+    /// A °F temperature in this unit
+    pub fn temp(self, fahrenheit: f64) -> f64 {
+        match self {
+            Unit::F => fahrenheit,
+            Unit::C => (fahrenheit - 32.0) * 5.0 / 9.0,
+            Unit::K => (fahrenheit - 32.0) * 5.0 / 9.0 + 273.15,
+        }
+    }
+
+    /// A difference of °F temperatures in this unit
+    pub fn delta(self, fahrenheit: f64) -> f64 {
+        match self {
+            Unit::F => fahrenheit,
+            Unit::C | Unit::K => fahrenheit * 5.0 / 9.0,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::F => "°F",
+            Unit::C => "°C",
+            Unit::K => "K",
+        }
+    }
+
+    /// Plain letter for data files, F, C or K
+    pub fn code(self) -> &'static str {
+        match self {
+            Unit::F => "F",
+            Unit::C => "C",
+            Unit::K => "K",
+        }
+    }
+
+    /// Added to file names so charts and csvs in other units don't overwrite the °F ones, empty for °F
+    pub fn file_suffix(self) -> &'static str {
+        match self {
+            Unit::F => "",
+            Unit::C => "_C",
+            Unit::K => "_K",
+        }
+    }
+
+    /// Grid step of a y axis given in °F steps, rounded so the labels stay whole numbers
+    pub fn step(self, fahrenheit_step: i32) -> i32 {
+        (self.delta(f64::from(fahrenheit_step)).round() as i32).max(1)
+    }
+
+    /// A temperature for a csv cell with `decimals` places, °F values that were whole stay whole
+    pub fn format_temp(self, fahrenheit: f64, decimals: usize) -> String {
+        match self {
+            Unit::F => format!("{:.decimals$}", fahrenheit),
+            _ => format!("{:.decimals$}", self.temp(fahrenheit), decimals = decimals.max(1)),
+        }
+    }
+
+    /// A °F temperature with its symbol for titles and labels, ex. 90°F or 32.2°C
+    pub fn temp_text(self, fahrenheit: f64) -> String {
+        format!("{}{}", self.format_temp(fahrenheit, 0), self.symbol())
+    }
+
+    /// format_temp for differences
+    pub fn format_delta(self, fahrenheit: f64, decimals: usize) -> String {
+        match self {
+            Unit::F => format!("{:.decimals$}", fahrenheit),
+            _ => format!("{:.decimals$}", self.delta(fahrenheit), decimals = decimals.max(1)),
+        }
+    }
+
+    pub fn temps(self, temps: &BucketTemps) -> BucketTemps {
+        temps.map(|t| self.temp(t))
+    }
+
+    pub fn deltas(self, temps: &BucketTemps) -> BucketTemps {
+        temps.map(|t| self.delta(t))
+    }
+
+    /// temps() for every year of a period table
+    pub fn years(self, years: &BTreeMap<i32, BucketTemps>) -> BTreeMap<i32, BucketTemps> {
+        years.iter().map(|(year, temps)| (*year, self.temps(temps))).collect()
+    }
+}
@@ -0,0 +1,103 @@
+// Shared pieces for charts with years along the x axis (trend lines, yearly counts, dates by year ...).
+// Same frame, grids and fonts as the period bar charts so the images sit together.
+use plotters::prelude::*;
+use plotters::coord::Shift;
+
+use crate::{YScale, AXIS_WIDTH, AXIS_HEIGHT, TOP_MARGIN, LEFT_MARGIN, BOTTOM_LINE_Y,
+            draw_axes, draw_grids, draw_title, draw_y_labels};
+
+/// Scale that fits every value with `pad` to spare, rounded out to multiples of 5
+pub fn value_scale(values: impl Iterator<Item = f64>, pad: f64) -> YScale {
+    let (low, high) = values.fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    let (low, high) = if low > high { (0.0, 10.0) } else { (low, high) }; // no values, draw an empty chart
+    let y_lowest = ((low - pad) / 5.0).floor() as i32 * 5;
+    let mut y_highest = ((high + pad) / 5.0).ceil() as i32 * 5;
+    if y_highest <= y_lowest {
+        y_highest = y_lowest + 5;
+    }
+    let y_range = y_highest - y_lowest;
+    let pixel_per_degree = f64::from(AXIS_HEIGHT) / f64::from(y_range);
+    YScale {
+        lowest: y_lowest,
+        highest: y_highest,
+        range: y_range,
+        pixel_per_degree,
+        zero_line_offset: f64::from(-y_lowest) * pixel_per_degree, // distance from bottom axis up to 0
+    }
+}
+
+pub fn year_x(year: f64, first_year: i32, last_year: i32) -> i32 {
+    if last_year <= first_year {
+        return LEFT_MARGIN + AXIS_WIDTH / 2;
+    }
+    LEFT_MARGIN + ((year - f64::from(first_year)) * f64::from(AXIS_WIDTH) / f64::from(last_year - first_year)).round() as i32
+}
+
+pub fn value_y(value: f64, y_scale: &YScale) -> i32 {
+    BOTTOM_LINE_Y - ((value - f64::from(y_scale.lowest)) * y_scale.pixel_per_degree).round() as i32
+}
+
+pub fn draw_year_chart_base(dwg: &DrawingArea<BitMapBackend, Shift>, title_text: &str, first_year: i32, last_year: i32, y_scale: &YScale) -> Result<(), Box<dyn std::error::Error>> {
+    let title_style = ("sans-serif", 36).into_font().color(&BLACK);
+    let x_axis_style = ("sans-serif", 16).into_font().color(&BLACK);
+    let y_axis_style = ("sans-serif", 18).into_font().color(&BLACK);
+    draw_axes(dwg)?;
+    draw_grids(dwg)?;
+    draw_title(dwg, title_text, title_style)?;
+    // a year label under each vertical grid line
+    for i in 0..5 {
+        let year = first_year + (last_year - first_year) * i / 4;
+        let label = year.to_string();
+        let (label_width, _) = dwg.estimate_text_size(&label, &x_axis_style)?;
+        let x = year_x(f64::from(year), first_year, last_year) - label_width as i32 / 2;
+        dwg.draw_text(&label, &x_axis_style, (x, BOTTOM_LINE_Y + 12))?;
+    }
+    draw_y_labels(dwg, &y_axis_style, y_scale.highest, y_scale.range)?;
+    Ok(())
+}
+
+/// Line through (year, value) points, broken wherever a year is missing so gaps aren't bridged
+pub fn draw_year_line(dwg: &DrawingArea<BitMapBackend, Shift>, points: &[(i32, f64)], first_year: i32, last_year: i32, y_scale: &YScale, style: ShapeStyle) -> Result<(), Box<dyn std::error::Error>> {
+    let mut segment: Vec<(i32, i32)> = Vec::new();
+    let mut last_point_year: Option<i32> = None;
+    for (year, value) in points {
+        if last_point_year.is_some_and(|last| year - last > 1) {
+            draw_segment(dwg, &segment, style)?;
+            segment.clear();
+        }
+        segment.push((year_x(f64::from(*year), first_year, last_year), value_y(*value, y_scale)));
+        last_point_year = Some(*year);
+    }
+    draw_segment(dwg, &segment, style)?;
+    Ok(())
+}
+
+fn draw_segment(dwg: &DrawingArea<BitMapBackend, Shift>, segment: &[(i32, i32)], style: ShapeStyle) -> Result<(), Box<dyn std::error::Error>> {
+    match segment.len() {
+        0 => {},
+        1 => { dwg.draw(&Circle::new(segment[0], 2, style.filled()))?; }, // lone year, show it as a dot
+        _ => { dwg.draw(&PathElement::new(segment.to_vec(), style))?; },
+    }
+    Ok(())
+}
+
+/// Color swatch and text for each entry, top left inside the plot
+pub fn draw_legend(dwg: &DrawingArea<BitMapBackend, Shift>, entries: &[(String, RGBColor)]) -> Result<(), Box<dyn std::error::Error>> {
+    let legend_style = ("sans-serif", 16).into_font().color(&BLACK);
+    let x = LEFT_MARGIN + 15;
+    let mut y = TOP_MARGIN + 10;
+    for (text, color) in entries {
+        let (text_width, text_height) = dwg.estimate_text_size(text, &legend_style)?;
+        dwg.draw(&Rectangle::new(
+            [(x - 5, y - 2), (x + 30 + text_width as i32 + 5, y + text_height as i32 + 2)],
+            Into::<ShapeStyle>::into(RGBAColor(255, 255, 255, 0.85)).filled(),
+        ))?;
+        dwg.draw(&Rectangle::new(
+            [(x, y + 2), (x + 20, y + text_height as i32 - 2)],
+            Into::<ShapeStyle>::into(color).filled(),
+        ))?;
+        dwg.draw_text(text, &legend_style, (x + 30, y))?;
+        y += text_height as i32 + 6;
+    }
+    Ok(())
+}